@@ -0,0 +1,64 @@
+// File: fixtures/test_extension/src/lib.rs
+//! Minimal extension used by the lib_cpi integration tests
+
+use lib_cpi::macros::cpi_extension;
+use lib_cpi::{ActionResult, CpiError};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::TcpStream;
+
+pub struct TestExtension;
+
+impl TestExtension {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for TestExtension {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The version is overridable so the hot reload tests can build a second, distinguishable version
+#[cpi_extension(
+    name = "test_extension",
+    provider_type = "test",
+    version = option_env!("CPI_FIXTURE_VERSION").unwrap_or(env!("CARGO_PKG_VERSION"))
+)]
+impl TestExtension {
+    #[action(description = "Returns the given message")]
+    #[param(name = "message", description = "Message to echo")]
+    fn echo(&self, message: String) -> ActionResult {
+        Ok(json!({ "message": message }))
+    }
+
+    #[action(description = "Adds two integers")]
+    #[param(name = "a", description = "First operand")]
+    #[param(name = "b", description = "Second operand")]
+    fn add(&self, a: i64, b: i64) -> ActionResult {
+        Ok(json!({ "sum": a + b }))
+    }
+
+    // Lets the reload tests keep a call in flight for exactly as long as they need
+    #[action(description = "Connects to `address` and returns once the peer closes the connection")]
+    #[param(name = "address", description = "host:port the test listens on")]
+    fn hold(&self, address: String) -> ActionResult {
+        let mut stream = TcpStream::connect(&address).map_err(|e| CpiError::unavailable(e.to_string()))?;
+        let _ = stream.read_to_end(&mut Vec::new());
+        Ok(json!("released"))
+    }
+
+    #[default_settings]
+    fn settings(&self) -> HashMap<String, Value> {
+        HashMap::from([("greeting".to_string(), json!("hello"))])
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+lib_cpi::register_extension!(TestExtension);
+
+#[cfg(target_arch = "wasm32")]
+lib_cpi::register_wasm_extension!(TestExtension);
@@ -0,0 +1,8 @@
+// File: fixtures/test_extension/src/main.rs
+//! Serves the fixture extension over stdio for the out-of-process tests
+
+use cpi_test_extension::TestExtension;
+
+fn main() -> std::io::Result<()> {
+    lib_cpi::rpc::serve_stdio(TestExtension::new())
+}
@@ -0,0 +1,395 @@
+//! Extensions whose actions are `async`
+//!
+//! A provider that waits on a hypervisor CLI or an HTTP API for minutes implements
+//! [`AsyncCpiExtension`] instead of `CpiExtension`, usually through `#[cpi_extension]`
+//! on an impl block with `async fn` actions. The adapters convert both ways:
+//!
+//! ```ignore
+//! // A sync extension in an async host: calls run on a pool of blocking threads
+//! let extension = Offloaded::new(registry.get("vbox").unwrap());
+//! extension.execute_action("create", &params).await?;
+//!
+//! // An async extension where a `CpiExtension` is expected: calls block the calling thread
+//! let extension: Box<dyn CpiExtension> = Box::new(Blocking::new(CloudApi::new()));
+//! ```
+//!
+//! Nothing here depends on a particular async runtime. [`block_on`] drives futures
+//! on the calling thread, which is enough for futures that are woken by threads of
+//! their own; an extension built on a runtime's timers or sockets is given that
+//! runtime's `block_on` through [`Blocking::with_executor`].
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::{Pin, pin};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use serde_json::Value;
+
+use crate::isolation::panic_error;
+use crate::{ActionContext, ActionDefinition, ActionResult, CpiExtension};
+
+/// A boxed future that can be awaited on any thread
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Threads in the pool [`Offloaded::new`] runs calls on
+pub const DEFAULT_BLOCKING_THREADS: usize = 16;
+
+/// `CpiExtension` with `async` actions
+///
+/// The methods describing the extension stay synchronous, since they only read
+/// what the extension declares; only running an action and checking the
+/// installation may wait on the provider.
+pub trait AsyncCpiExtension: Send + Sync {
+    /// Returns the name of the extension
+    fn name(&self) -> &str;
+
+    /// Returns the provider type
+    fn provider_type(&self) -> &str;
+
+    /// Returns all available actions
+    fn list_actions(&self) -> Vec<String>;
+
+    /// Returns the definition of a specific action
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition>;
+
+    /// Executes an action with the given parameters
+    fn execute_action<'a>(&'a self, action: &'a str, params: &'a HashMap<String, Value>) -> BoxFuture<'a, ActionResult>;
+
+    /// Executes an action on behalf of the call described by `context`; runs
+    /// `execute_action` unless implemented
+    fn execute_action_with_context<'a>(
+        &'a self,
+        action: &'a str,
+        params: &'a HashMap<String, Value>,
+        _context: &'a ActionContext,
+    ) -> BoxFuture<'a, ActionResult> {
+        self.execute_action(action, params)
+    }
+
+    /// Optional method that returns default parameter values for the provider
+    fn default_settings(&self) -> HashMap<String, Value> {
+        HashMap::new()
+    }
+
+    /// Test if the extension is properly installed
+    fn test_install(&self) -> BoxFuture<'_, ActionResult> {
+        Box::pin(std::future::ready(Ok(serde_json::json!({"status": "ok"}))))
+    }
+
+    fn version(&self) -> String {
+        "NONE".to_string()
+    }
+}
+
+/// Runs `future` to completion on the calling thread, parking it while the future waits
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed set of threads for calls that block
+///
+/// Cloning gives another handle to the same threads, which exit once every handle
+/// has been dropped and the queued calls have run.
+#[derive(Clone)]
+pub struct BlockingPool {
+    jobs: Sender<Job>,
+    threads: usize,
+}
+
+impl BlockingPool {
+    pub fn new(threads: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        for index in 0..threads.max(1) {
+            let queue = queue.clone();
+            thread::Builder::new()
+                .name(format!("cpi-blocking-{}", index))
+                .spawn(move || {
+                    loop {
+                        // The lock is released before the job runs
+                        let job = queue.lock().unwrap_or_else(PoisonError::into_inner).recv();
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    }
+                })
+                .expect("failed to spawn blocking pool thread");
+        }
+        Self {
+            jobs,
+            threads: threads.max(1),
+        }
+    }
+
+    /// The pool shared by every [`Offloaded::new`], with [`DEFAULT_BLOCKING_THREADS`] threads
+    pub fn global() -> Self {
+        static POOL: OnceLock<BlockingPool> = OnceLock::new();
+        POOL.get_or_init(|| BlockingPool::new(DEFAULT_BLOCKING_THREADS)).clone()
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Runs `call` on one of the pool's threads
+    ///
+    /// The returned future resolves to the call's result, or to the panic payload
+    /// if it panicked; the thread survives either way.
+    pub fn spawn<T: Send + 'static>(&self, call: impl FnOnce() -> T + Send + 'static) -> BlockingTask<T> {
+        let shared = Arc::new(Mutex::new(TaskState {
+            result: None,
+            waker: None,
+        }));
+        let task = BlockingTask { shared: shared.clone() };
+        let job = move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(call));
+            let mut state = shared.lock().unwrap_or_else(PoisonError::into_inner);
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        };
+        // The threads only stop once every handle, including this one, is gone
+        self.jobs.send(Box::new(job)).expect("blocking pool threads have stopped");
+        task
+    }
+}
+
+impl fmt::Debug for BlockingPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingPool").field("threads", &self.threads).finish()
+    }
+}
+
+struct TaskState<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// Result of a call running on a [`BlockingPool`]
+pub struct BlockingTask<T> {
+    shared: Arc<Mutex<TaskState<T>>>,
+}
+
+impl<T> Future for BlockingTask<T> {
+    type Output = thread::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// An `AsyncCpiExtension` running a sync extension's calls on a [`BlockingPool`]
+///
+/// A call that panics resolves to the `Internal` error described in
+/// [`crate::isolation`] instead of taking a pool thread down.
+pub struct Offloaded<E> {
+    inner: Arc<E>,
+    pool: BlockingPool,
+}
+
+impl<E: CpiExtension + 'static> Offloaded<E> {
+    /// Runs calls on the [`BlockingPool::global`] pool
+    pub fn new(inner: E) -> Self {
+        Self::with_pool(inner, BlockingPool::global())
+    }
+
+    pub fn with_pool(inner: E, pool: BlockingPool) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            pool,
+        }
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    fn offload<F>(&self, action: &str, call: F) -> BoxFuture<'static, ActionResult>
+    where
+        F: FnOnce(&E) -> ActionResult + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        let task = self.pool.spawn(move || call(&inner));
+        let action = action.to_string();
+        Box::pin(async move {
+            task.await
+                .unwrap_or_else(|payload: Box<dyn Any + Send>| Err(panic_error(&action, payload.as_ref())))
+        })
+    }
+}
+
+impl<E: fmt::Debug> fmt::Debug for Offloaded<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Offloaded").field("inner", &self.inner).field("pool", &self.pool).finish()
+    }
+}
+
+impl<E: CpiExtension + 'static> AsyncCpiExtension for Offloaded<E> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn provider_type(&self) -> &str {
+        self.inner.provider_type()
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        self.inner.list_actions()
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        self.inner.get_action_definition(action)
+    }
+
+    fn execute_action<'a>(&'a self, action: &'a str, params: &'a HashMap<String, Value>) -> BoxFuture<'a, ActionResult> {
+        let (name, params) = (action.to_string(), params.clone());
+        self.offload(action, move |inner| inner.execute_action(&name, &params))
+    }
+
+    fn execute_action_with_context<'a>(
+        &'a self,
+        action: &'a str,
+        params: &'a HashMap<String, Value>,
+        context: &'a ActionContext,
+    ) -> BoxFuture<'a, ActionResult> {
+        let (name, params, context) = (action.to_string(), params.clone(), context.clone());
+        self.offload(action, move |inner| inner.execute_action_with_context(&name, &params, &context))
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        self.inner.default_settings()
+    }
+
+    fn test_install(&self) -> BoxFuture<'_, ActionResult> {
+        self.offload("test_install", |inner| inner.test_install())
+    }
+
+    fn version(&self) -> String {
+        self.inner.version()
+    }
+}
+
+/// Drives a future returned by an [`AsyncCpiExtension`] to completion
+pub type Executor = Arc<dyn for<'a> Fn(BoxFuture<'a, ActionResult>) -> ActionResult + Send + Sync>;
+
+/// A `CpiExtension` waiting for an async extension's calls on the calling thread
+///
+/// Must not be called from a thread that is itself driving an async runtime, which
+/// would stall every other task on it.
+pub struct Blocking<E> {
+    inner: E,
+    executor: Option<Executor>,
+}
+
+impl<E: AsyncCpiExtension> Blocking<E> {
+    /// Waits with [`block_on`]
+    pub fn new(inner: E) -> Self {
+        Self { inner, executor: None }
+    }
+
+    /// Waits with `executor`, e.g. the `block_on` of the runtime the extension needs
+    pub fn with_executor<X>(inner: E, executor: X) -> Self
+    where
+        X: for<'a> Fn(BoxFuture<'a, ActionResult>) -> ActionResult + Send + Sync + 'static,
+    {
+        Self {
+            inner,
+            executor: Some(Arc::new(executor)),
+        }
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    fn wait(&self, future: BoxFuture<'_, ActionResult>) -> ActionResult {
+        match &self.executor {
+            Some(executor) => executor(future),
+            None => block_on(future),
+        }
+    }
+}
+
+impl<E: fmt::Debug> fmt::Debug for Blocking<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Blocking").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+impl<E: AsyncCpiExtension> CpiExtension for Blocking<E> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn provider_type(&self) -> &str {
+        self.inner.provider_type()
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        self.inner.list_actions()
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        self.inner.get_action_definition(action)
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        self.wait(self.inner.execute_action(action, params))
+    }
+
+    fn execute_action_with_context(
+        &self,
+        action: &str,
+        params: &HashMap<String, Value>,
+        context: &ActionContext,
+    ) -> ActionResult {
+        self.wait(self.inner.execute_action_with_context(action, params, context))
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        self.inner.default_settings()
+    }
+
+    fn test_install(&self) -> ActionResult {
+        self.wait(self.inner.test_install())
+    }
+
+    fn version(&self) -> String {
+        self.inner.version()
+    }
+}
@@ -0,0 +1,372 @@
+//! What a host knows about one call, handed to the action alongside its parameters
+//!
+//! ```ignore
+//! let context = ActionContext::new()
+//!     .with_timeout(Duration::from_secs(300))
+//!     .with_principal(Principal::new("alice"))
+//!     .with_settings(extension.default_settings());
+//! extension.execute_action_with_context("create", &params, &context)?;
+//! ```
+//!
+//! Extensions that only implement `execute_action` keep working: the default
+//! `execute_action_with_context` ignores the context. Across plugin boundaries the
+//! context travels as JSON with the deadline turned into the time that is left:
+//!
+//! ```json
+//! {"request_id": "…", "timeout_ms": 299870, "span": "vbox.create", "principal": {"id": "alice"}, "settings": {}}
+//! ```
+//!
+//! The cancellation token, the logger and the progress reporter stay on the side
+//! that made the context.
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, PoisonError, Weak};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::CpiError;
+
+/// Lets the caller of an action ask it to stop
+///
+/// Clones share the same state, so the host keeps one and the action checks
+/// another. Actions poll [`CancellationToken::is_cancelled`] between steps or wait
+/// on it in place of a sleep.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+#[derive(Debug, Default)]
+struct TokenState {
+    // Whether the token is cancelled, and the children to cancel along with it
+    inner: Mutex<(bool, Vec<Weak<TokenState>>)>,
+    condvar: Condvar,
+}
+
+impl TokenState {
+    fn cancel(&self) {
+        let children = {
+            let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+            inner.0 = true;
+            std::mem::take(&mut inner.1)
+        };
+        self.condvar.notify_all();
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token that is cancelled with this one, but can also be cancelled on its own
+    pub fn child(&self) -> Self {
+        let child = CancellationToken::new();
+        let mut inner = self.state.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if inner.0 {
+            child.state.inner.lock().unwrap_or_else(PoisonError::into_inner).0 = true;
+        } else {
+            inner.1.retain(|child| child.strong_count() > 0);
+            inner.1.push(Arc::downgrade(&child.state));
+        }
+        child
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.inner.lock().unwrap_or_else(PoisonError::into_inner).0
+    }
+
+    /// Waits up to `timeout` for cancellation and returns whether it happened
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let inner = self.state.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let (inner, _) = self
+            .state
+            .condvar
+            .wait_timeout_while(inner, timeout, |(cancelled, _)| !*cancelled)
+            .unwrap_or_else(PoisonError::into_inner);
+        inner.0
+    }
+}
+
+/// Who an action is run on behalf of
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Principal {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+}
+
+impl Principal {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            roles: Vec::new(),
+        }
+    }
+
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.roles.push(role.into());
+        self
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// One message an action logged through its context
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub request_id: String,
+    pub span: String,
+    pub message: String,
+}
+
+/// Receives the messages actions log; hosts forward them to their own logging
+pub type Logger = Arc<dyn Fn(&LogRecord) + Send + Sync>;
+
+/// Receives how far along an action is, in percent
+pub type ProgressReporter = Arc<dyn Fn(u8) + Send + Sync>;
+
+/// Per-call information for `execute_action_with_context`
+#[derive(Clone)]
+pub struct ActionContext {
+    /// Identifies the call in logs, unique within the process unless given by the caller
+    pub request_id: String,
+    /// When the caller stops waiting for the result
+    pub deadline: Option<Instant>,
+    pub cancellation: CancellationToken,
+    /// Name of the unit of work, such as `vbox.create`, recorded with every log message
+    pub span: String,
+    pub principal: Option<Principal>,
+    /// The settings the extension is run with, as of when the call was made
+    pub settings: HashMap<String, Value>,
+    logger: Option<Logger>,
+    progress: Option<ProgressReporter>,
+}
+
+impl Default for ActionContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ActionContext {
+    /// A context with a fresh request id and nothing else
+    pub fn new() -> Self {
+        Self {
+            request_id: next_request_id(),
+            deadline: None,
+            cancellation: CancellationToken::new(),
+            span: String::new(),
+            principal: None,
+            settings: HashMap::new(),
+            logger: None,
+            progress: None,
+        }
+    }
+
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = request_id.into();
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets the deadline `timeout` from now; a timeout too far out to represent
+    /// leaves the call without a deadline
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Instant::now().checked_add(timeout);
+        self
+    }
+
+    /// Sets the token the action polls; it is not sent across plugin or RPC
+    /// boundaries, so extensions there only stop at the deadline
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    pub fn with_span(mut self, span: impl Into<String>) -> Self {
+        self.span = span.into();
+        self
+    }
+
+    pub fn with_principal(mut self, principal: Principal) -> Self {
+        self.principal = Some(principal);
+        self
+    }
+
+    pub fn with_settings(mut self, settings: HashMap<String, Value>) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn with_logger(mut self, logger: impl Fn(&LogRecord) + Send + Sync + 'static) -> Self {
+        self.logger = Some(Arc::new(logger));
+        self
+    }
+
+    pub fn with_progress(mut self, progress: impl Fn(u8) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Time left until the deadline, zero once it has passed
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Whether the deadline has passed
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_some_and(|remaining| remaining.is_zero())
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Fails with a `Cancelled` or `Timeout` error once the action should stop
+    ///
+    /// Long-running actions call this between steps: `context.checkpoint()?;`
+    pub fn checkpoint(&self) -> Result<(), CpiError> {
+        if self.is_cancelled() {
+            return Err(CpiError::cancelled(format!("Request '{}' was cancelled", self.request_id)));
+        }
+        if self.is_expired() {
+            return Err(CpiError::timeout(format!("Request '{}' ran past its deadline", self.request_id)));
+        }
+        Ok(())
+    }
+
+    /// Reports that the action is `percent` done, capped at 100
+    pub fn report_progress(&self, percent: u8) {
+        if let Some(progress) = &self.progress {
+            progress(percent.min(100));
+        }
+    }
+
+    /// Hands `message` to the context's logger, if it has one
+    pub fn log(&self, level: LogLevel, message: impl Into<String>) {
+        if let Some(logger) = &self.logger {
+            logger(&LogRecord {
+                level,
+                request_id: self.request_id.clone(),
+                span: self.span.clone(),
+                message: message.into(),
+            });
+        }
+    }
+
+    pub fn error(&self, message: impl Into<String>) {
+        self.log(LogLevel::Error, message);
+    }
+
+    pub fn warn(&self, message: impl Into<String>) {
+        self.log(LogLevel::Warn, message);
+    }
+
+    pub fn info(&self, message: impl Into<String>) {
+        self.log(LogLevel::Info, message);
+    }
+
+    pub fn debug(&self, message: impl Into<String>) {
+        self.log(LogLevel::Debug, message);
+    }
+}
+
+impl fmt::Debug for ActionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ActionContext")
+            .field("request_id", &self.request_id)
+            .field("deadline", &self.deadline)
+            .field("cancelled", &self.is_cancelled())
+            .field("span", &self.span)
+            .field("principal", &self.principal)
+            .field("settings", &self.settings)
+            .finish_non_exhaustive()
+    }
+}
+
+// The context as it crosses plugin boundaries
+#[derive(Serialize, Deserialize)]
+struct ContextRepr {
+    request_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    span: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    principal: Option<Principal>,
+    #[serde(default)]
+    settings: HashMap<String, Value>,
+}
+
+impl Serialize for ActionContext {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ContextRepr {
+            request_id: self.request_id.clone(),
+            timeout_ms: self.remaining().map(|remaining| remaining.as_millis() as u64),
+            span: self.span.clone(),
+            principal: self.principal.clone(),
+            settings: self.settings.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ActionContext {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ContextRepr::deserialize(deserializer)?;
+        let mut context = ActionContext::new().with_request_id(repr.request_id).with_span(repr.span);
+        context.deadline = repr.timeout_ms.and_then(deadline_in);
+        context.principal = repr.principal;
+        context.settings = repr.settings;
+        Ok(context)
+    }
+}
+
+// WebAssembly guests have no clock, so a deadline sent to them is dropped. So is
+// one too far out to represent, rather than panicking on a peer's input.
+#[cfg(not(target_arch = "wasm32"))]
+fn deadline_in(timeout_ms: u64) -> Option<Instant> {
+    Instant::now().checked_add(Duration::from_millis(timeout_ms))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn deadline_in(_timeout_ms: u64) -> Option<Instant> {
+    None
+}
+
+pub(crate) fn next_request_id() -> String {
+    static PREFIX: OnceLock<u64> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    // Random per process, so ids from different hosts and plugins do not collide
+    let prefix = PREFIX.get_or_init(|| RandomState::new().hash_one(()));
+    format!("{:016x}-{}", prefix, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
@@ -0,0 +1,194 @@
+//! Errors returned by extensions
+//!
+//! A [`CpiError`] carries an [`ErrorKind`] hosts can act on, so that "bad parameter",
+//! "VM not found" and "provider CLI crashed" are told apart without matching on
+//! messages. Errors cross plugin boundaries as JSON:
+//!
+//! ```json
+//! {"kind": "NotFound", "message": "VM 'web' not found", "retryable": false, "details": {"vm": "web"}}
+//! ```
+//!
+//! Extensions built before errors were structured report plain strings; those
+//! are read as `Internal` errors, as are the `String` errors that `?` and `.into()`
+//! convert while actions are being migrated.
+
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::validation::ValidationErrors;
+
+/// What went wrong, in terms a host can act on
+///
+/// More kinds may be added, so match with a wildcard arm. Kinds this version does
+/// not know, sent by a newer extension, are read as `Internal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The parameters were missing, malformed or out of range
+    InvalidParams,
+    /// The resource the action works on does not exist
+    NotFound,
+    /// The resource the action would create exists already
+    AlreadyExists,
+    /// The provider refused the credentials or the operation
+    Unauthorized,
+    /// The operation did not finish in time
+    Timeout,
+    /// The caller cancelled the operation
+    Cancelled,
+    /// The provider or something it depends on cannot be reached right now
+    Unavailable,
+    /// The extension does not offer the action or operation
+    Unsupported,
+    /// Anything else: a bug, a crashed provider CLI, an unexpected response
+    #[serde(other)]
+    Internal,
+}
+
+impl ErrorKind {
+    /// Whether errors of this kind are worth retrying unless stated otherwise
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorKind::Timeout | ErrorKind::Unavailable)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Error of an action or of the extension machinery around it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "CpiErrorRepr")]
+pub struct CpiError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Whether the same call may succeed if it is made again later
+    pub retryable: bool,
+    /// Machine-readable context, such as the resource that was not found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl CpiError {
+    /// An error of `kind`, retryable if errors of that kind usually are
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            retryable: kind.is_retryable(),
+            details: None,
+        }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidParams, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NotFound, message)
+    }
+
+    pub fn already_exists(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::AlreadyExists, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Unauthorized, message)
+    }
+
+    pub fn timeout(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Timeout, message)
+    }
+
+    pub fn cancelled(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Cancelled, message)
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Unavailable, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Internal, message)
+    }
+
+    pub fn unsupported(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Unsupported, message)
+    }
+
+    pub fn with_retryable(mut self, retryable: bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+impl fmt::Display for CpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CpiError {}
+
+impl From<String> for CpiError {
+    fn from(message: String) -> Self {
+        CpiError::internal(message)
+    }
+}
+
+impl From<&str> for CpiError {
+    fn from(message: &str) -> Self {
+        CpiError::internal(message)
+    }
+}
+
+impl From<ValidationErrors> for CpiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let details = serde_json::to_value(&errors.0).ok();
+        CpiError {
+            details,
+            ..CpiError::invalid_params(errors.to_string())
+        }
+    }
+}
+
+// Structured errors, or the plain strings older extensions report
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CpiErrorRepr {
+    Message(String),
+    Structured {
+        kind: ErrorKind,
+        message: String,
+        retryable: Option<bool>,
+        #[serde(default)]
+        details: Option<Value>,
+    },
+}
+
+impl From<CpiErrorRepr> for CpiError {
+    fn from(repr: CpiErrorRepr) -> Self {
+        match repr {
+            CpiErrorRepr::Message(message) => CpiError::internal(message),
+            CpiErrorRepr::Structured {
+                kind,
+                message,
+                retryable,
+                details,
+            } => CpiError {
+                kind,
+                retryable: retryable.unwrap_or(kind.is_retryable()),
+                message,
+                details,
+            },
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Bounding how long actions run, and stopping them early
+//!
+//! ```ignore
+//! let extension = ActionExecutor::new(vbox).with_default_timeout(Duration::from_secs(600));
+//! // Uses the action's declared timeout, or ten minutes
+//! extension.execute_action("clone", &params)?;
+//! // A deadline on the context takes precedence over both
+//! extension.execute_action_with_context("clone", &params, &ActionContext::new().with_timeout(hour))?;
+//! ```
+//!
+//! Rust cannot stop a thread from the outside, so cancellation is cooperative:
+//! when the deadline passes or the caller cancels, the executor returns at once
+//! and cancels the token on the action's context. The action notices through
+//! [`ActionContext::checkpoint`] or its token and winds down on its own.
+//!
+//! Extensions across a plugin boundary get the remaining time as `timeout_ms` on
+//! the context, but not the token, so they can only give up at the deadline.
+//!
+//! At most [`DEFAULT_MAX_RUNNING`] actions run at a time, counting those that were
+//! abandoned but have not returned yet. A call waits for one of them to return,
+//! or fails once its deadline passes or the caller cancels while it waits.
+
+use std::collections::HashMap;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+
+use crate::isolation::catch_panic;
+use crate::slots::Slots;
+use crate::{ActionContext, ActionDefinition, ActionResult, CpiError, CpiExtension};
+
+/// How many actions an executor runs at the same time unless configured otherwise
+pub const DEFAULT_MAX_RUNNING: usize = 64;
+
+// How long the executor waits between checks when a call has no deadline
+const IDLE_WAIT: Duration = Duration::from_secs(3600);
+
+// How often a call waiting for a thread checks whether it was cancelled
+const SLOT_WAIT: Duration = Duration::from_millis(50);
+
+/// A `CpiExtension` that enforces timeouts and cancellation on the one it wraps
+///
+/// Each call runs on its own thread, so an action that ignores its token only
+/// keeps that thread busy rather than blocking later calls, until
+/// [`ActionExecutor::with_max_running`] threads are busy.
+///
+/// Cancelling does not reach a `ForeignExtension` or `RemoteExtension`: the call
+/// returns `Cancelled` while the action on the other side keeps running until it
+/// finishes or its deadline passes. An action's timeout
+/// is the deadline on the call's context if it has one, then the `timeout` from
+/// its definition, then the executor's default.
+pub struct ActionExecutor<E> {
+    inner: Arc<E>,
+    default_timeout: Option<Duration>,
+    running: Arc<Slots>,
+}
+
+impl<E: CpiExtension + 'static> ActionExecutor<E> {
+    pub fn new(inner: E) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            default_timeout: None,
+            running: Arc::new(Slots::new(DEFAULT_MAX_RUNNING)),
+        }
+    }
+
+    /// Sets how many actions run at the same time
+    pub fn with_max_running(mut self, max_running: usize) -> Self {
+        self.running = Arc::new(Slots::new(max_running));
+        self
+    }
+
+    /// Sets the timeout for actions that do not declare one
+    pub fn with_default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// The timeout `action` runs with when the call sets no deadline
+    pub fn timeout_for(&self, action: &str) -> Option<Duration> {
+        self.inner
+            .get_action_definition(action)
+            .and_then(|definition| definition.timeout)
+            .or(self.default_timeout)
+    }
+
+    fn run(&self, action: &str, params: &HashMap<String, Value>, context: &ActionContext) -> ActionResult {
+        let started = Instant::now();
+        let mut context = context.clone();
+        if context.deadline.is_none() {
+            context.deadline = self.timeout_for(action).and_then(|timeout| started.checked_add(timeout));
+        }
+        let caller = context.cancellation.clone();
+        // The action gets a token of its own, so a timeout does not cancel the caller's
+        let token = caller.child();
+        context.cancellation = token.clone();
+        // Wakes the executor when the action returns or the caller cancels, but not
+        // when the action cancels its own token
+        let wake = caller.child();
+        let deadline = context.deadline;
+
+        let cancelled = || {
+            CpiError::cancelled(format!("Action '{}' was cancelled", action)).with_details(json!({ "action": action }))
+        };
+        let timed_out = |deadline: Instant| {
+            let timeout = deadline.saturating_duration_since(started).as_millis() as u64;
+            CpiError::timeout(format!("Action '{}' did not finish before its deadline", action))
+                .with_details(json!({ "action": action, "timeout_ms": timeout }))
+        };
+
+        let slot = loop {
+            let wait = deadline.map_or(SLOT_WAIT, |deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Some(slot) = self.running.acquire_timeout(wait.min(SLOT_WAIT)) {
+                break slot;
+            }
+            if caller.is_cancelled() {
+                return Err(cancelled());
+            }
+            if let Some(deadline) = deadline.filter(|deadline| Instant::now() >= *deadline) {
+                return Err(timed_out(deadline));
+            }
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let inner = self.inner.clone();
+        let name = action.to_string();
+        let params = params.clone();
+        let done = wake.clone();
+        // The thread is not named after the action, whose name comes from the caller
+        let spawned = thread::Builder::new().name("cpi-action".to_string()).spawn(move || {
+            let _slot = slot;
+            let result = catch_panic(&name, || inner.execute_action_with_context(&name, &params, &context));
+            let _ = sender.send(result);
+            done.cancel();
+        });
+        if let Err(error) = spawned {
+            return Err(CpiError::internal(format!("Failed to start action '{}': {}", action, error)));
+        }
+
+        loop {
+            let wait = deadline.map_or(IDLE_WAIT, |deadline| deadline.saturating_duration_since(Instant::now()));
+            wake.wait_timeout(wait);
+            if let Ok(result) = receiver.try_recv() {
+                return result;
+            }
+            if caller.is_cancelled() {
+                token.cancel();
+                return Err(cancelled());
+            }
+            if let Some(deadline) = deadline.filter(|deadline| Instant::now() >= *deadline) {
+                token.cancel();
+                return Err(timed_out(deadline));
+            }
+            if wake.is_cancelled() {
+                // Only the worker wakes us otherwise, after sending its result
+                return receiver.recv().unwrap_or_else(|_| Err(cancelled()));
+            }
+        }
+    }
+}
+
+impl<E: CpiExtension + 'static> CpiExtension for ActionExecutor<E> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn provider_type(&self) -> &str {
+        self.inner.provider_type()
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        self.inner.list_actions()
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        self.inner.get_action_definition(action)
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        self.run(action, params, &ActionContext::new())
+    }
+
+    fn execute_action_with_context(
+        &self,
+        action: &str,
+        params: &HashMap<String, Value>,
+        context: &ActionContext,
+    ) -> ActionResult {
+        self.run(action, params, context)
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        self.inner.default_settings()
+    }
+
+    fn test_install(&self) -> ActionResult {
+        self.inner.test_install()
+    }
+
+    fn version(&self) -> String {
+        self.inner.version()
+    }
+}
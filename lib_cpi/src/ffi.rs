@@ -0,0 +1,454 @@
+// File: lib_cpi/src/ffi.rs
+//! C-compatible boundary between extension libraries and hosts
+//!
+//! Trait objects have no stable layout, so instead of handing a `dyn CpiExtension`
+//! across `extern "C"`, `register_extension!` exports a [`CpiExtensionVTable`]: an
+//! opaque instance pointer plus plain function pointers that exchange JSON encoded
+//! byte buffers. Every buffer is allocated by the extension and must be released
+//! with the table's `free_buffer`, so host and extension never share an allocator.
+//! On the host side [`ForeignExtension`] turns the table back into a `CpiExtension`.
+//!
+//! The JSON payloads are independent of how they are transported: [`invoke`] answers
+//! them on the extension side and [`JsonExtension`] issues them on the host side,
+//! which is also how WebAssembly extensions are bridged.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void};
+use std::mem::ManuallyDrop;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::isolation::catch_panic;
+use crate::{ActionContext, ActionDefinition, ActionResult, CpiError, CpiExtension};
+
+/// Version of the boundary defined in this module
+///
+/// Bumped on every incompatible change to [`CpiExtensionVTable`] or the JSON it carries.
+/// Hosts refuse to load extensions that report a different version.
+pub const ABI_VERSION: u32 = 1;
+
+/// Version of lib_cpi itself, reported by extensions next to [`ABI_VERSION`]
+pub const LIB_CPI_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const LIB_CPI_VERSION_NUL: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+/// Name of the exported `extern "C" fn() -> u32` returning the extension's [`ABI_VERSION`]
+pub const ABI_VERSION_SYMBOL: &str = "cpi_abi_version";
+
+/// Name of the exported `extern "C" fn() -> *const c_char` returning the extension's [`LIB_CPI_VERSION`]
+pub const LIB_VERSION_SYMBOL: &str = "cpi_lib_version";
+
+pub type AbiVersionFn = unsafe extern "C" fn() -> u32;
+pub type LibVersionFn = unsafe extern "C" fn() -> *const c_char;
+
+/// Nul-terminated [`LIB_CPI_VERSION`]; used by `register_extension!`
+pub fn lib_cpi_version_ptr() -> *const c_char {
+    LIB_CPI_VERSION_NUL.as_ptr() as *const c_char
+}
+
+/// Borrowed byte slice passed from the host into the extension
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CpiSlice {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl CpiSlice {
+    pub fn new(bytes: &[u8]) -> Self {
+        Self {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        }
+    }
+
+    /// # Safety
+    /// `ptr` must point to `len` readable bytes that outlive the returned slice
+    pub unsafe fn as_bytes<'a>(&self) -> &'a [u8] {
+        if self.ptr.is_null() || self.len == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
+    }
+}
+
+/// Owned byte buffer allocated by the extension
+#[repr(C)]
+#[derive(Debug)]
+pub struct CpiBuffer {
+    pub ptr: *mut u8,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl CpiBuffer {
+    pub fn from_vec(bytes: Vec<u8>) -> Self {
+        let mut bytes = ManuallyDrop::new(bytes);
+        Self {
+            ptr: bytes.as_mut_ptr(),
+            len: bytes.len(),
+            capacity: bytes.capacity(),
+        }
+    }
+
+    /// # Safety
+    /// The buffer must have been created by [`CpiBuffer::from_vec`] in the same
+    /// library that calls this, and must not be used afterwards
+    pub unsafe fn into_vec(self) -> Vec<u8> {
+        unsafe { Vec::from_raw_parts(self.ptr, self.len, self.capacity) }
+    }
+
+    /// # Safety
+    /// The buffer must not have been freed yet
+    pub unsafe fn as_bytes(&self) -> &[u8] {
+        if self.ptr.is_null() || self.len == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
+    }
+}
+
+/// Signature shared by all extension entry points: JSON in, JSON out
+pub type CpiCallFn = unsafe extern "C" fn(instance: *const c_void, input: CpiSlice) -> CpiBuffer;
+
+/// Function table exported by an extension library
+#[repr(C)]
+pub struct CpiExtensionVTable {
+    pub instance: *mut c_void,
+    pub name: CpiCallFn,
+    pub provider_type: CpiCallFn,
+    pub list_actions: CpiCallFn,
+    pub get_action_definition: CpiCallFn,
+    pub execute_action: CpiCallFn,
+    pub default_settings: CpiCallFn,
+    pub test_install: CpiCallFn,
+    pub version: CpiCallFn,
+    pub free_buffer: unsafe extern "C" fn(buffer: CpiBuffer),
+    pub drop_instance: unsafe extern "C" fn(instance: *mut c_void),
+}
+
+/// Entry points of the JSON boundary, shared by native libraries and WASM modules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Name,
+    ProviderType,
+    ListActions,
+    GetActionDefinition,
+    ExecuteAction,
+    DefaultSettings,
+    TestInstall,
+    Version,
+}
+
+impl Method {
+    pub const ALL: [Method; 8] = [
+        Method::Name,
+        Method::ProviderType,
+        Method::ListActions,
+        Method::GetActionDefinition,
+        Method::ExecuteAction,
+        Method::DefaultSettings,
+        Method::TestInstall,
+        Method::Version,
+    ];
+
+    /// Name of the `CpiExtension` method this entry point stands for
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Name => "name",
+            Method::ProviderType => "provider_type",
+            Method::ListActions => "list_actions",
+            Method::GetActionDefinition => "get_action_definition",
+            Method::ExecuteAction => "execute_action",
+            Method::DefaultSettings => "default_settings",
+            Method::TestInstall => "test_install",
+            Method::Version => "version",
+        }
+    }
+}
+
+/// Arguments of `execute_action` as they cross the boundary
+#[derive(Debug, Serialize, Deserialize)]
+struct ExecuteRequest {
+    action: String,
+    params: HashMap<String, Value>,
+    // Left out by `execute_action`, and by hosts built before contexts existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<ActionContext>,
+}
+
+// Extension side
+
+/// Answers one JSON encoded call on the extension side of the boundary
+///
+/// `input` is empty for methods without arguments, the JSON action name for
+/// `get_action_definition` and `{"action", "params", "context"?}` for `execute_action`. A panic
+/// in `execute_action` or `test_install` is answered as an `Internal` error.
+pub fn invoke<E: CpiExtension + ?Sized>(extension: &E, method: Method, input: &[u8]) -> Vec<u8> {
+    match method {
+        Method::Name => encode(&extension.name()),
+        Method::ProviderType => encode(&extension.provider_type()),
+        Method::ListActions => encode(&extension.list_actions()),
+        Method::GetActionDefinition => {
+            let definition = decode::<String>(input)
+                .ok()
+                .and_then(|action| extension.get_action_definition(&action));
+            encode(&definition)
+        }
+        Method::ExecuteAction => {
+            let result: ActionResult = decode::<ExecuteRequest>(input)
+                .map_err(CpiError::internal)
+                .and_then(|request| {
+                    catch_panic(&request.action, || match &request.context {
+                        Some(context) => extension.execute_action_with_context(&request.action, &request.params, context),
+                        None => extension.execute_action(&request.action, &request.params),
+                    })
+                });
+            encode(&result)
+        }
+        Method::DefaultSettings => encode(&extension.default_settings()),
+        Method::TestInstall => encode(&catch_panic("test_install", || extension.test_install())),
+        Method::Version => encode(&extension.version()),
+    }
+}
+
+fn encode(value: &impl Serialize) -> Vec<u8> {
+    serde_json::to_vec(value).unwrap_or_else(|_| b"null".to_vec())
+}
+
+fn decode<T: DeserializeOwned>(input: &[u8]) -> Result<T, String> {
+    serde_json::from_slice(input).map_err(|e| format!("Malformed request across extension boundary: {}", e))
+}
+
+/// Wraps an extension in a function table; used by `register_extension!`
+pub fn export<E: CpiExtension + 'static>(extension: E) -> CpiExtensionVTable {
+    CpiExtensionVTable {
+        instance: Box::into_raw(Box::new(extension)) as *mut c_void,
+        name: shim::<E, { Method::Name as u8 }>,
+        provider_type: shim::<E, { Method::ProviderType as u8 }>,
+        list_actions: shim::<E, { Method::ListActions as u8 }>,
+        get_action_definition: shim::<E, { Method::GetActionDefinition as u8 }>,
+        execute_action: shim::<E, { Method::ExecuteAction as u8 }>,
+        default_settings: shim::<E, { Method::DefaultSettings as u8 }>,
+        test_install: shim::<E, { Method::TestInstall as u8 }>,
+        version: shim::<E, { Method::Version as u8 }>,
+        free_buffer: free_buffer_shim,
+        drop_instance: drop_instance_shim::<E>,
+    }
+}
+
+/// Builds the extension with `new` and wraps it like [`export`]; used by `register_extension!`
+///
+/// A panic in `new` must not unwind into the host, so it is reported as a table
+/// with a null `instance`, which [`CpiExtensionVTable::is_failed`] detects and
+/// the loader refuses.
+pub fn export_with<E: CpiExtension + 'static>(new: impl FnOnce() -> E) -> CpiExtensionVTable {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| export(new()))) {
+        Ok(vtable) => vtable,
+        Err(_) => CpiExtensionVTable {
+            instance: std::ptr::null_mut(),
+            name: failed_shim,
+            provider_type: failed_shim,
+            list_actions: failed_shim,
+            get_action_definition: failed_shim,
+            execute_action: failed_shim,
+            default_settings: failed_shim,
+            test_install: failed_shim,
+            version: failed_shim,
+            free_buffer: free_buffer_shim,
+            drop_instance: drop_nothing_shim,
+        },
+    }
+}
+
+impl CpiExtensionVTable {
+    /// Whether the extension could not be created, see [`export_with`]
+    pub fn is_failed(&self) -> bool {
+        self.instance.is_null()
+    }
+}
+
+unsafe extern "C" fn failed_shim(_this: *const c_void, _input: CpiSlice) -> CpiBuffer {
+    CpiBuffer::from_vec(b"null".to_vec())
+}
+
+unsafe extern "C" fn drop_nothing_shim(_this: *mut c_void) {}
+
+unsafe extern "C" fn shim<E: CpiExtension, const METHOD: u8>(this: *const c_void, input: CpiSlice) -> CpiBuffer {
+    let extension = unsafe { &*(this as *const E) };
+    let input = unsafe { input.as_bytes() };
+    // Unwinding must not cross `extern "C"`; a panic outside an action answers `null`,
+    // which the host reads as the method's default
+    let output = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        invoke(extension, Method::ALL[METHOD as usize], input)
+    }));
+    CpiBuffer::from_vec(output.unwrap_or_else(|_| b"null".to_vec()))
+}
+
+unsafe extern "C" fn free_buffer_shim(buffer: CpiBuffer) {
+    drop(unsafe { buffer.into_vec() });
+}
+
+unsafe extern "C" fn drop_instance_shim<E>(this: *mut c_void) {
+    drop(unsafe { Box::from_raw(this as *mut E) });
+}
+
+// Host side
+
+/// Host side of a JSON boundary: delivers a call to the extension and returns its JSON answer
+pub trait JsonBoundary: Send + Sync {
+    fn call(&self, method: Method, input: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// Exposes any [`JsonBoundary`] as a `CpiExtension`
+pub struct JsonExtension<B: JsonBoundary> {
+    boundary: B,
+    name: String,
+    provider_type: String,
+}
+
+impl<B: JsonBoundary> JsonExtension<B> {
+    pub fn new(boundary: B) -> Self {
+        let mut extension = Self {
+            boundary,
+            name: String::new(),
+            provider_type: String::new(),
+        };
+        // name() and provider_type() hand out references, so cache them once
+        extension.name = extension.call(Method::Name, &[]).unwrap_or_default();
+        extension.provider_type = extension.call(Method::ProviderType, &[]).unwrap_or_default();
+        extension
+    }
+
+    /// Returns the boundary the extension is reached through
+    pub fn boundary(&self) -> &B {
+        &self.boundary
+    }
+
+    fn call<T: DeserializeOwned>(&self, method: Method, input: &[u8]) -> Result<T, String> {
+        let output = self.boundary.call(method, input)?;
+        serde_json::from_slice(&output)
+            .map_err(|e| format!("Malformed '{}' response from extension '{}': {}", method.as_str(), self.name, e))
+    }
+
+    fn call_with<T: DeserializeOwned>(&self, method: Method, input: &impl Serialize) -> Result<T, String> {
+        let input = serde_json::to_vec(input).map_err(|e| e.to_string())?;
+        self.call(method, &input)
+    }
+}
+
+impl<B: JsonBoundary> std::fmt::Debug for JsonExtension<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonExtension")
+            .field("name", &self.name)
+            .field("provider_type", &self.provider_type)
+            .finish()
+    }
+}
+
+impl<B: JsonBoundary> CpiExtension for JsonExtension<B> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn provider_type(&self) -> &str {
+        &self.provider_type
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        self.call(Method::ListActions, &[]).unwrap_or_default()
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        self.call_with(Method::GetActionDefinition, &action).unwrap_or(None)
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        let request = ExecuteRequest {
+            action: action.to_string(),
+            params: params.clone(),
+            context: None,
+        };
+        self.call_with::<ActionResult>(Method::ExecuteAction, &request)?
+    }
+
+    fn execute_action_with_context(
+        &self,
+        action: &str,
+        params: &HashMap<String, Value>,
+        context: &ActionContext,
+    ) -> ActionResult {
+        let request = ExecuteRequest {
+            action: action.to_string(),
+            params: params.clone(),
+            context: Some(context.clone()),
+        };
+        self.call_with::<ActionResult>(Method::ExecuteAction, &request)?
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        self.call(Method::DefaultSettings, &[]).unwrap_or_default()
+    }
+
+    fn test_install(&self) -> ActionResult {
+        self.call::<ActionResult>(Method::TestInstall, &[])?
+    }
+
+    fn version(&self) -> String {
+        self.call(Method::Version, &[]).unwrap_or_else(|_| "NONE".to_string())
+    }
+}
+
+/// Owns the instance behind a [`CpiExtensionVTable`] and calls through its function pointers
+pub struct VTableBoundary {
+    vtable: CpiExtensionVTable,
+}
+
+// SAFETY: the table can only be built by `export` from a `CpiExtension`, which is
+// `Send + Sync`
+unsafe impl Send for VTableBoundary {}
+unsafe impl Sync for VTableBoundary {}
+
+impl JsonBoundary for VTableBoundary {
+    fn call(&self, method: Method, input: &[u8]) -> Result<Vec<u8>, String> {
+        let function = match method {
+            Method::Name => self.vtable.name,
+            Method::ProviderType => self.vtable.provider_type,
+            Method::ListActions => self.vtable.list_actions,
+            Method::GetActionDefinition => self.vtable.get_action_definition,
+            Method::ExecuteAction => self.vtable.execute_action,
+            Method::DefaultSettings => self.vtable.default_settings,
+            Method::TestInstall => self.vtable.test_install,
+            Method::Version => self.vtable.version,
+        };
+        unsafe {
+            let buffer = function(self.vtable.instance, CpiSlice::new(input));
+            let output = buffer.as_bytes().to_vec();
+            (self.vtable.free_buffer)(buffer);
+            Ok(output)
+        }
+    }
+}
+
+impl Drop for VTableBoundary {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.drop_instance)(self.vtable.instance) }
+    }
+}
+
+/// Host-side adapter that exposes a [`CpiExtensionVTable`] as a `CpiExtension`
+pub type ForeignExtension = JsonExtension<VTableBoundary>;
+
+impl ForeignExtension {
+    /// Takes ownership of the instance behind `vtable`
+    ///
+    /// # Safety
+    /// `vtable` must come from [`export`] (usually through `register_extension!`)
+    /// and the library it was loaded from must outlive the returned value
+    pub unsafe fn from_vtable(vtable: CpiExtensionVTable) -> Self {
+        Self::new(VTableBoundary { vtable })
+    }
+}
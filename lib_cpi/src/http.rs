@@ -0,0 +1,230 @@
+// File: lib_cpi/src/http.rs
+//! HTTP/REST gateway in front of a `CpiExtension` (enabled by the `http` feature)
+//!
+//! | Route                   | Backed by                        |
+//! |-------------------------|----------------------------------|
+//! | `GET /actions`          | `list_actions`                   |
+//! | `GET /actions/{name}`   | `get_action_definition`          |
+//! | `POST /actions/{name}`  | `execute_action`, body = params  |
+//! | `GET /settings`         | `default_settings`               |
+//! | `GET /health`           | `test_install`                   |
+//!
+//! Successful calls answer `200` with the JSON result. Failures answer with
+//! `response::error` bodies: `400` for a malformed request body or path, `404` for
+//! unknown routes and actions, `405` for the wrong method, `413` for a body over
+//! [`MAX_BODY`] bytes and `503` when `test_install` fails. A failed action
+//! answers with a `response::failure` body and a status that follows its
+//! `ErrorKind`, e.g. `400` for `InvalidParams`, `404` for `NotFound` and `500`
+//! for `Internal`.
+//!
+//! A gateway handles at most [`DEFAULT_MAX_REQUESTS`] requests at a time; further
+//! requests wait to be read until one finishes.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::slots::Slots;
+use crate::{CpiExtension, ErrorKind, response};
+
+/// Largest request body the gateway reads, in bytes
+pub const MAX_BODY: u64 = 1024 * 1024;
+
+/// How many requests a gateway handles at the same time unless configured otherwise
+pub const DEFAULT_MAX_REQUESTS: usize = 64;
+
+/// Serves an extension over HTTP
+pub struct HttpGateway {
+    server: Arc<Server>,
+    extension: Arc<dyn CpiExtension>,
+    requests: Arc<Slots>,
+}
+
+impl HttpGateway {
+    /// Binds `address`, e.g. `127.0.0.1:8080`; port 0 picks a free port
+    pub fn bind(address: &str, extension: Arc<dyn CpiExtension>) -> Result<Self, String> {
+        let server = Server::http(address).map_err(|e| format!("Failed to bind HTTP gateway to '{}': {}", address, e))?;
+        Ok(Self {
+            server: Arc::new(server),
+            extension,
+            requests: Arc::new(Slots::new(DEFAULT_MAX_REQUESTS)),
+        })
+    }
+
+    /// Sets how many requests are handled at the same time
+    pub fn with_max_requests(mut self, max_requests: usize) -> Self {
+        self.requests = Arc::new(Slots::new(max_requests));
+        self
+    }
+
+    /// The bound address, with the actual port when binding to port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Handles requests until the gateway is shut down through a [`GatewayHandle`]
+    pub fn run(self) {
+        loop {
+            // Waits for a slot before taking the next request off the server
+            let slot = self.requests.acquire();
+            let Ok(request) = self.server.recv() else {
+                break;
+            };
+            let extension = self.extension.clone();
+            std::thread::spawn(move || {
+                let _slot = slot;
+                respond(extension.as_ref(), request);
+            });
+        }
+    }
+
+    /// Runs the gateway on a background thread
+    pub fn spawn(self) -> GatewayHandle {
+        let server = self.server.clone();
+        let local_addr = self.local_addr();
+        let thread = std::thread::spawn(move || self.run());
+        GatewayHandle {
+            server,
+            local_addr,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Handle to a gateway started with [`HttpGateway::spawn`]; stops it when dropped
+pub struct GatewayHandle {
+    server: Arc<Server>,
+    local_addr: Option<SocketAddr>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GatewayHandle {
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Stops accepting requests and waits for the gateway thread to finish
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.server.unblock();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for GatewayHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn respond(extension: &dyn CpiExtension, mut request: Request) {
+    let mut body = Vec::new();
+    // One byte over the limit is enough to tell the body is too large
+    let (status, value) = match request.as_reader().take(MAX_BODY + 1).read_to_end(&mut body) {
+        Ok(_) if body.len() as u64 > MAX_BODY => {
+            (413, response::error(format!("Request body is larger than {} bytes", MAX_BODY)))
+        }
+        Ok(_) => {
+            let method = request.method().as_str().to_string();
+            route(extension, &method, request.url(), &body)
+        }
+        Err(e) => (400, response::error(format!("Failed to read request body: {}", e))),
+    };
+
+    let content_type = Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+    let response = Response::from_string(value.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    let _ = request.respond(response);
+}
+
+/// Maps one HTTP request onto the extension, returning the status code and JSON body
+pub fn route(extension: &dyn CpiExtension, method: &str, url: &str, body: &[u8]) -> (u16, Value) {
+    let path = url.split('?').next().unwrap_or_default().trim_end_matches('/');
+    let segments = match path.split('/').skip(1).map(percent_decode).collect::<Option<Vec<String>>>() {
+        Some(segments) => segments,
+        None => return (400, response::error(format!("Malformed path: {}", path))),
+    };
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let get = method == Method::Get.as_str();
+    let post = method == Method::Post.as_str();
+
+    match segments.as_slice() {
+        ["actions"] if get => (200, json!(extension.list_actions())),
+        ["actions", action] if get => match extension.get_action_definition(action) {
+            Some(definition) => (200, json!(definition)),
+            None => (404, response::error(format!("Unknown action: {}", action))),
+        },
+        ["actions", action] if post => {
+            if !extension.list_actions().iter().any(|a| a == action) {
+                return (404, response::error(format!("Unknown action: {}", action)));
+            }
+            let params = match parse_params(body) {
+                Ok(params) => params,
+                Err(error) => return (400, response::error(error)),
+            };
+            match extension.execute_action(action, &params) {
+                Ok(result) => (200, result),
+                Err(error) => (status(error.kind), response::failure(&error)),
+            }
+        }
+        ["settings"] if get => (200, json!(extension.default_settings())),
+        ["health"] if get => match extension.test_install() {
+            Ok(result) => (200, result),
+            Err(error) => (503, response::failure(&error)),
+        },
+        ["actions"] | ["actions", _] | ["settings"] | ["health"] => {
+            (405, response::error(format!("Method {} not allowed on {}", method, path)))
+        }
+        _ => (404, response::error(format!("Not found: {}", path))),
+    }
+}
+
+/// HTTP status of a failed action
+pub fn status(kind: ErrorKind) -> u16 {
+    match kind {
+        ErrorKind::InvalidParams => 400,
+        ErrorKind::Unauthorized => 403,
+        ErrorKind::NotFound => 404,
+        ErrorKind::AlreadyExists => 409,
+        ErrorKind::Internal => 500,
+        ErrorKind::Unsupported => 501,
+        ErrorKind::Unavailable => 503,
+        ErrorKind::Timeout => 504,
+        // As reverse proxies report a client that went away
+        ErrorKind::Cancelled => 499,
+    }
+}
+
+// Decodes `%XX` escapes; `None` for a broken escape or a result that is not UTF-8
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn parse_params(body: &[u8]) -> Result<HashMap<String, Value>, String> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(HashMap::new());
+    }
+    serde_json::from_slice(body).map_err(|e| format!("Request body must be a JSON object of parameters: {}", e))
+}
@@ -0,0 +1,179 @@
+//! Keeping a panicking extension from taking its host down
+//!
+//! Panics are caught per call and reported as `Internal` errors naming the action:
+//!
+//! ```json
+//! {"kind": "Internal", "message": "Action 'create' panicked: index out of bounds", "retryable": false,
+//!  "details": {"action": "create", "panic": "index out of bounds"}}
+//! ```
+//!
+//! `register_extension!` libraries and [`crate::rpc::serve_stdio`] processes catch
+//! them on the extension side, since unwinding across `extern "C"` is undefined behaviour.
+//! Hosts wrap in-process extensions in [`Isolated`], which also stops calling an
+//! extension that keeps panicking.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use serde_json::{json, Value};
+
+use crate::{ActionContext, ActionDefinition, ActionResult, CpiError, CpiExtension, ErrorKind};
+
+/// Consecutive panics after which [`Isolated`] considers an extension unhealthy
+pub const DEFAULT_MAX_PANICS: usize = 3;
+
+/// Runs `call`, turning a panic into the error [`panic_error`] describes
+pub fn catch_panic(action: &str, call: impl FnOnce() -> ActionResult) -> ActionResult {
+    panic::catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|payload| Err(panic_error(action, payload.as_ref())))
+}
+
+/// `Internal` error for a panic in `action`, carrying the panic message
+pub fn panic_error(action: &str, payload: &(dyn Any + Send)) -> CpiError {
+    let message = panic_message(payload);
+    CpiError::internal(format!("Action '{}' panicked: {}", action, message))
+        .with_details(json!({ "action": action, "panic": message }))
+}
+
+/// Whether `error` reports a panic, possibly caught on the other side of a boundary
+pub fn is_panic(error: &CpiError) -> bool {
+    error.kind == ErrorKind::Internal && error.details.as_ref().is_some_and(|details| details["panic"].is_string())
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// A `CpiExtension` that survives panics in the one it wraps
+///
+/// Panics in `execute_action` and `test_install` become `Internal` errors, and
+/// panics in the other methods fall back to what an extension without actions
+/// would answer. After `max_panics` calls in a row have panicked, the extension is
+/// unhealthy: actions fail with a non-retryable `Unavailable` error without being
+/// called until [`Isolated::reset`]. Panics reported by the extension itself, as
+/// loaded libraries and remote processes do, count the same way.
+#[derive(Debug)]
+pub struct Isolated<E> {
+    inner: E,
+    max_panics: usize,
+    panics: AtomicUsize,
+}
+
+impl<E: CpiExtension> Isolated<E> {
+    pub fn new(inner: E) -> Self {
+        Self {
+            inner,
+            max_panics: DEFAULT_MAX_PANICS,
+            panics: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets how many consecutive panics make the extension unhealthy
+    pub fn with_max_panics(mut self, max_panics: usize) -> Self {
+        self.max_panics = max_panics;
+        self
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    /// Number of calls in a row that have panicked
+    pub fn panics(&self) -> usize {
+        self.panics.load(Ordering::SeqCst)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.panics() < self.max_panics
+    }
+
+    /// Marks the extension healthy again, e.g. after it has been reloaded
+    pub fn reset(&self) {
+        self.panics.store(0, Ordering::SeqCst);
+    }
+
+    fn record(&self, panicked: bool) {
+        if panicked {
+            self.panics.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.panics.store(0, Ordering::SeqCst);
+        }
+    }
+
+    fn guard(&self, action: &str, call: impl FnOnce() -> ActionResult) -> ActionResult {
+        if !self.is_healthy() {
+            let error = CpiError::unavailable(format!(
+                "Extension '{}' is unhealthy after {} consecutive panics",
+                self.name(),
+                self.panics()
+            ));
+            return Err(error.with_retryable(false));
+        }
+        let result = catch_panic(action, call);
+        self.record(result.as_ref().is_err_and(is_panic));
+        result
+    }
+
+    fn fallback<T>(&self, call: impl FnOnce() -> T, default: impl FnOnce() -> T) -> T {
+        match panic::catch_unwind(AssertUnwindSafe(call)) {
+            Ok(value) => value,
+            Err(_) => {
+                self.record(true);
+                default()
+            }
+        }
+    }
+}
+
+impl<E: CpiExtension> CpiExtension for Isolated<E> {
+    fn name(&self) -> &str {
+        self.fallback(|| self.inner.name(), || "")
+    }
+
+    fn provider_type(&self) -> &str {
+        self.fallback(|| self.inner.provider_type(), || "")
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        self.fallback(|| self.inner.list_actions(), Vec::new)
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        self.fallback(|| self.inner.get_action_definition(action), || None)
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        self.guard(action, || self.inner.execute_action(action, params))
+    }
+
+    fn execute_action_with_context(
+        &self,
+        action: &str,
+        params: &HashMap<String, Value>,
+        context: &ActionContext,
+    ) -> ActionResult {
+        self.guard(action, || self.inner.execute_action_with_context(action, params, context))
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        self.fallback(|| self.inner.default_settings(), HashMap::new)
+    }
+
+    fn test_install(&self) -> ActionResult {
+        self.guard("test_install", || self.inner.test_install())
+    }
+
+    fn version(&self) -> String {
+        self.fallback(|| self.inner.version(), || "NONE".to_string())
+    }
+}
@@ -0,0 +1,381 @@
+//! Running actions as operations, in-process
+//!
+//! ```ignore
+//! let extension = Jobs::new(vbox, ["create_vm", "export_image"]);
+//! let handle = Operation::from_value(extension.execute_action("create_vm", &params)?)?;
+//! // Later, possibly from another request
+//! let operation = extension.get_operation(&handle.id)?;
+//! ```
+//!
+//! Operations run on a small set of worker threads and stay `Pending` while all
+//! of them are busy. Actions report progress with
+//! [`ActionContext::report_progress`] and notice cancellation the usual way,
+//! through [`ActionContext::checkpoint`] or the context's token. A cancelled
+//! operation is reported `Cancelled` at once, but its worker stays busy until the
+//! action returns, so actions that rarely check their context hold up the queue.
+//! Finished operations are forgotten after the retention period.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+
+use crate::context::next_request_id;
+use crate::isolation::catch_panic;
+use crate::operation::{self, Operation, OperationState};
+use crate::validation::extract_string;
+use crate::{ActionContext, ActionDefinition, ActionResult, CancellationToken, CpiError, CpiExtension};
+
+/// How many operations a [`JobManager`] runs at the same time by default
+pub const DEFAULT_MAX_RUNNING: usize = 8;
+
+/// How long a [`JobManager`] keeps finished operations by default
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(3600);
+
+type Work = Box<dyn FnOnce(&ActionContext) -> ActionResult + Send>;
+
+struct Job {
+    operation: Operation,
+    cancellation: CancellationToken,
+    // Taken by the worker that runs the job
+    work: Option<(Work, ActionContext)>,
+    finished_at: Option<Instant>,
+}
+
+struct State {
+    jobs: Vec<Job>,
+    queue: VecDeque<String>,
+    running: usize,
+    max_running: usize,
+    retention: Duration,
+}
+
+impl State {
+    fn job(&mut self, id: &str) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.operation.id == id)
+    }
+
+    fn finish(&mut self, id: &str, result: ActionResult) {
+        let Some(job) = self.job(id) else {
+            return;
+        };
+        // A cancelled operation stays cancelled, whatever the action made of it
+        if job.operation.is_finished() {
+            return;
+        }
+        match result {
+            Ok(value) => {
+                job.operation.state = OperationState::Succeeded;
+                job.operation.progress = 100;
+                job.operation.result = Some(value);
+            }
+            Err(error) => {
+                job.operation.state = OperationState::Failed;
+                job.operation.error = Some(error);
+            }
+        }
+        job.finished_at = Some(Instant::now());
+    }
+
+    /// Marks the next queued job running and hands over its work
+    fn next(&mut self) -> Option<(String, String, Work, ActionContext)> {
+        while let Some(id) = self.queue.pop_front() {
+            let Some(job) = self.job(&id) else {
+                continue;
+            };
+            let Some((work, context)) = job.work.take() else {
+                continue;
+            };
+            job.operation.state = OperationState::Running;
+            return Some((id, job.operation.action.clone(), work, context));
+        }
+        None
+    }
+
+    fn prune(&mut self) {
+        let retention = self.retention;
+        self.jobs
+            .retain(|job| job.finished_at.is_none_or(|finished_at| finished_at.elapsed() < retention));
+    }
+}
+
+/// Runs work in the background and keeps track of it as operations
+///
+/// Clones share the same operations.
+#[derive(Clone)]
+pub struct JobManager {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for JobManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JobManager {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                jobs: Vec::new(),
+                queue: VecDeque::new(),
+                running: 0,
+                max_running: DEFAULT_MAX_RUNNING,
+                retention: DEFAULT_RETENTION,
+            })),
+        }
+    }
+
+    /// Sets how many operations run at the same time; at least one always does
+    ///
+    /// A cancelled operation counts until its action returns, which it only does
+    /// once it notices the cancellation.
+    pub fn with_max_running(self, max_running: usize) -> Self {
+        self.lock().max_running = max_running.max(1);
+        self
+    }
+
+    /// Sets how long finished operations can still be looked up
+    pub fn with_retention(self, retention: Duration) -> Self {
+        self.lock().retention = retention;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues `work` as an operation of `action` and returns it while still `Pending`
+    ///
+    /// `work` gets `context` with the operation's id as request id, a cancellation
+    /// token of its own and a progress reporter updating the operation.
+    pub fn spawn<F>(&self, action: &str, context: ActionContext, work: F) -> Operation
+    where
+        F: FnOnce(&ActionContext) -> ActionResult + Send + 'static,
+    {
+        let operation = Operation::new(next_request_id(), action);
+        let cancellation = CancellationToken::new();
+        let progress = progress_reporter(Arc::downgrade(&self.state), operation.id.clone());
+        let context = context
+            .with_request_id(operation.id.clone())
+            .with_cancellation(cancellation.clone())
+            .with_progress(progress);
+
+        let mut state = self.lock();
+        state.prune();
+        state.jobs.push(Job {
+            operation: operation.clone(),
+            cancellation,
+            work: Some((Box::new(work), context)),
+            finished_at: None,
+        });
+        state.queue.push_back(operation.id.clone());
+        while state.running < state.max_running {
+            let Some(next) = state.next() else {
+                break;
+            };
+            state.running += 1;
+            let id = next.0.clone();
+            let shared = self.state.clone();
+            let spawned = thread::Builder::new()
+                .name("cpi-job".to_string())
+                .spawn(move || run_jobs(&shared, next));
+            if spawned.is_err() {
+                // The job's work went down with the closure; fail it rather than leave it running
+                state.running -= 1;
+                state.finish(&id, Err(CpiError::unavailable("Failed to start a worker thread")));
+                break;
+            }
+        }
+        operation
+    }
+
+    pub fn get(&self, id: &str) -> Option<Operation> {
+        let mut state = self.lock();
+        state.prune();
+        state.job(id).map(|job| job.operation.clone())
+    }
+
+    /// Every operation, oldest first
+    pub fn list(&self) -> Vec<Operation> {
+        let mut state = self.lock();
+        state.prune();
+        state.jobs.iter().map(|job| job.operation.clone()).collect()
+    }
+
+    /// Marks an unfinished operation `Cancelled` and signals its action
+    ///
+    /// A running action is told through its context and may keep running until it
+    /// notices, holding on to its worker meanwhile; what it returns then is
+    /// discarded. Cancelling a finished operation leaves it as it is.
+    pub fn cancel(&self, id: &str) -> Result<Operation, CpiError> {
+        let mut state = self.lock();
+        state.prune();
+        let Some(job) = state.job(id) else {
+            return Err(CpiError::not_found(format!("Operation '{}' not found", id)));
+        };
+        if !job.operation.is_finished() {
+            job.cancellation.cancel();
+            job.work = None;
+            job.operation.state = OperationState::Cancelled;
+            job.operation.error = Some(CpiError::cancelled(format!("Operation '{}' was cancelled", id)));
+            job.finished_at = Some(Instant::now());
+        }
+        Ok(job.operation.clone())
+    }
+}
+
+impl std::fmt::Debug for JobManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.lock();
+        f.debug_struct("JobManager")
+            .field("operations", &state.jobs.len())
+            .field("running", &state.running)
+            .field("max_running", &state.max_running)
+            .field("retention", &state.retention)
+            .finish()
+    }
+}
+
+// Runs the given job, then queued ones until the queue is empty
+fn run_jobs(shared: &Mutex<State>, mut next: (String, String, Work, ActionContext)) {
+    loop {
+        let (id, action, work, context) = next;
+        let result = catch_panic(&action, || work(&context));
+        let mut state = shared.lock().unwrap_or_else(PoisonError::into_inner);
+        state.finish(&id, result);
+        match state.next() {
+            Some(job) => next = job,
+            None => {
+                state.running -= 1;
+                return;
+            }
+        }
+    }
+}
+
+fn progress_reporter(shared: Weak<Mutex<State>>, id: String) -> impl Fn(u8) + Send + Sync + 'static {
+    move |percent| {
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        let mut state = shared.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(job) = state.job(&id).filter(|job| job.operation.state == OperationState::Running) {
+            job.operation.progress = percent;
+        }
+    }
+}
+
+/// A `CpiExtension` running some of the actions of the one it wraps as operations
+///
+/// Calls to those actions return the `Pending` operation straight away, and the
+/// reserved actions of [`crate::operation`] are answered from the wrapper's
+/// [`JobManager`]. Cancelling an operation keeps its worker busy until the action
+/// returns, see [`JobManager::cancel`]. Operations do not inherit the deadline of
+/// the call that started them, since its caller is not waiting; wrap the
+/// extension in [`crate::ActionExecutor`] to bound them. Other actions run as
+/// usual.
+pub struct Jobs<E> {
+    inner: Arc<E>,
+    actions: Vec<String>,
+    manager: JobManager,
+}
+
+impl<E: CpiExtension + 'static> Jobs<E> {
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(inner: E, actions: I) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            actions: actions.into_iter().map(Into::into).collect(),
+            manager: JobManager::new(),
+        }
+    }
+
+    pub fn with_manager(mut self, manager: JobManager) -> Self {
+        self.manager = manager;
+        self
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    pub fn manager(&self) -> &JobManager {
+        &self.manager
+    }
+
+    fn start(&self, action: &str, params: &HashMap<String, Value>, context: &ActionContext) -> Operation {
+        let inner = self.inner.clone();
+        let name = action.to_string();
+        let params = params.clone();
+        let mut context = context.clone();
+        context.deadline = None;
+        self.manager.spawn(action, context, move |context| {
+            inner.execute_action_with_context(&name, &params, context)
+        })
+    }
+}
+
+impl<E: CpiExtension + 'static> CpiExtension for Jobs<E> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn provider_type(&self) -> &str {
+        self.inner.provider_type()
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        let mut actions = self.inner.list_actions();
+        for action in operation::ACTIONS {
+            if !actions.iter().any(|a| a == action) {
+                actions.push(action.to_string());
+            }
+        }
+        actions
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        operation::definition(action).or_else(|| self.inner.get_action_definition(action))
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        self.execute_action_with_context(action, params, &ActionContext::new())
+    }
+
+    fn execute_action_with_context(
+        &self,
+        action: &str,
+        params: &HashMap<String, Value>,
+        context: &ActionContext,
+    ) -> ActionResult {
+        match action {
+            operation::GET_OPERATION => {
+                let id = extract_string(params, "id")?;
+                let operation = self.manager.get(&id);
+                operation
+                    .map(|operation| operation.to_value())
+                    .ok_or_else(|| CpiError::not_found(format!("Operation '{}' not found", id)))
+            }
+            operation::LIST_OPERATIONS => Ok(json!(self.manager.list())),
+            operation::CANCEL_OPERATION => {
+                let id = extract_string(params, "id")?;
+                self.manager.cancel(&id).map(|operation| operation.to_value())
+            }
+            _ if self.actions.iter().any(|a| a == action) => Ok(self.start(action, params, context).to_value()),
+            _ => self.inner.execute_action_with_context(action, params, context),
+        }
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        self.inner.default_settings()
+    }
+
+    fn test_install(&self) -> ActionResult {
+        self.inner.test_install()
+    }
+
+    fn version(&self) -> String {
+        self.inner.version()
+    }
+}
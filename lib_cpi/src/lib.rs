@@ -1,34 +1,338 @@
 // File: lib_cpi/src/lib.rs
 use std::collections::HashMap;
+use std::sync::Arc;
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Marked `#[non_exhaustive]` so that fields can be added without breaking
+/// providers; build parameters with [`ActionParameter::new`] or [`param!`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct ActionParameter {
     pub name: String,
     pub description: String,
     pub required: bool,
     pub param_type: ParamType,
     pub default_value: Option<Value>,
+    /// Checked by `validation::check_constraints` before the action runs
+    #[serde(default, skip_serializing_if = "Constraints::is_empty")]
+    pub constraints: Constraints,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// An optional `Any` parameter without a default value or constraints
+impl Default for ActionParameter {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            description: String::new(),
+            required: false,
+            param_type: ParamType::Any,
+            default_value: None,
+            constraints: Constraints::default(),
+        }
+    }
+}
+
+impl ActionParameter {
+    pub fn new(name: impl Into<String>, description: impl Into<String>, param_type: ParamType, required: bool) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            required,
+            param_type,
+            default_value: None,
+            constraints: Constraints::default(),
+        }
+    }
+
+    pub fn with_default(mut self, default_value: Value) -> Self {
+        self.default_value = Some(default_value);
+        self
+    }
+
+    pub fn with_constraints(mut self, constraints: Constraints) -> Self {
+        self.constraints = constraints;
+        self
+    }
+}
+
+/// Restrictions on the value of a parameter, beyond its type
+///
+/// `min` and `max` bound numbers, and also `Duration` parameters in seconds and
+/// `ByteSize` parameters in bytes. Lengths count characters, and `pattern` must
+/// match the whole string. Definitions without constraints serialize as before.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Constraints {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_items: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<usize>,
+}
+
+impl Constraints {
+    pub fn is_empty(&self) -> bool {
+        *self == Constraints::default()
+    }
+
+    pub fn min(mut self, min: impl Into<f64>) -> Self {
+        self.min = Some(min.into());
+        self
+    }
+
+    pub fn max(mut self, max: impl Into<f64>) -> Self {
+        self.max = Some(max.into());
+        self
+    }
+
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+
+    pub fn min_length(mut self, min_length: usize) -> Self {
+        self.min_length = Some(min_length);
+        self
+    }
+
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    pub fn allowed<I: IntoIterator<Item = V>, V: Into<Value>>(mut self, allowed: I) -> Self {
+        self.allowed = Some(allowed.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn min_items(mut self, min_items: usize) -> Self {
+        self.min_items = Some(min_items);
+        self
+    }
+
+    pub fn max_items(mut self, max_items: usize) -> Self {
+        self.max_items = Some(max_items);
+        self
+    }
+}
+
+/// Type of an action parameter
+///
+/// Types without details serialize as their name (`"String"`), the others as a
+/// single-key object (`{"Enum": ["qcow2", "raw"]}`). An untyped `Array` and a
+/// free-form `Object` serialize as `"Array"` and `"Object"`, so definitions
+/// written before types carried details read and round-trip unchanged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ParamTypeRepr", into = "ParamTypeRepr")]
 pub enum ParamType {
     String,
+    Integer,
+    /// Any number, integer or not; older definitions use it where `Float` is meant
     Number,
+    Float,
     Boolean,
-    Object,
+    /// One of the given strings
+    Enum(Vec<String>),
+    /// A string such as a password, which must never be logged or echoed back
+    Secret,
+    /// A duration such as `"90s"` or `"1h30m"`, or a number of seconds
+    Duration,
+    /// A size such as `"20GiB"` or `"512MB"`, or a number of bytes
+    ByteSize,
+    /// An array whose items are all of the given type
+    Array(Box<ParamType>),
+    /// An object with the given fields; without fields any object is accepted
+    Object(Vec<ActionParameter>),
+    /// Any JSON value
+    Any,
+}
+
+impl ParamType {
+    /// An array of `items`
+    pub fn array(items: ParamType) -> Self {
+        ParamType::Array(Box::new(items))
+    }
+
+    /// An enum of the given values
+    pub fn one_of<I: IntoIterator<Item = S>, S: Into<String>>(values: I) -> Self {
+        ParamType::Enum(values.into_iter().map(Into::into).collect())
+    }
+}
+
+// The serialized form of `ParamType`, see there
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ParamTypeRepr {
+    Name(ParamTypeName),
+    Detailed(DetailedParamType),
+}
+
+#[derive(Serialize, Deserialize)]
+enum ParamTypeName {
+    String,
+    Integer,
+    Number,
+    Float,
+    Boolean,
+    Secret,
+    Duration,
+    ByteSize,
     Array,
+    Object,
+    Any,
+}
+
+#[derive(Serialize, Deserialize)]
+enum DetailedParamType {
+    Enum(Vec<String>),
+    Array(Box<ParamType>),
+    Object(Vec<ActionParameter>),
+}
+
+impl From<ParamTypeRepr> for ParamType {
+    fn from(repr: ParamTypeRepr) -> Self {
+        match repr {
+            ParamTypeRepr::Name(name) => match name {
+                ParamTypeName::String => ParamType::String,
+                ParamTypeName::Integer => ParamType::Integer,
+                ParamTypeName::Number => ParamType::Number,
+                ParamTypeName::Float => ParamType::Float,
+                ParamTypeName::Boolean => ParamType::Boolean,
+                ParamTypeName::Secret => ParamType::Secret,
+                ParamTypeName::Duration => ParamType::Duration,
+                ParamTypeName::ByteSize => ParamType::ByteSize,
+                ParamTypeName::Array => ParamType::array(ParamType::Any),
+                ParamTypeName::Object => ParamType::Object(Vec::new()),
+                ParamTypeName::Any => ParamType::Any,
+            },
+            ParamTypeRepr::Detailed(detailed) => match detailed {
+                DetailedParamType::Enum(values) => ParamType::Enum(values),
+                DetailedParamType::Array(items) => ParamType::Array(items),
+                DetailedParamType::Object(fields) => ParamType::Object(fields),
+            },
+        }
+    }
+}
+
+impl From<ParamType> for ParamTypeRepr {
+    fn from(param_type: ParamType) -> Self {
+        let name = match param_type {
+            ParamType::String => ParamTypeName::String,
+            ParamType::Integer => ParamTypeName::Integer,
+            ParamType::Number => ParamTypeName::Number,
+            ParamType::Float => ParamTypeName::Float,
+            ParamType::Boolean => ParamTypeName::Boolean,
+            ParamType::Secret => ParamTypeName::Secret,
+            ParamType::Duration => ParamTypeName::Duration,
+            ParamType::ByteSize => ParamTypeName::ByteSize,
+            ParamType::Any => ParamTypeName::Any,
+            ParamType::Array(items) if *items == ParamType::Any => ParamTypeName::Array,
+            ParamType::Object(fields) if fields.is_empty() => ParamTypeName::Object,
+            ParamType::Enum(values) => return ParamTypeRepr::Detailed(DetailedParamType::Enum(values)),
+            ParamType::Array(items) => return ParamTypeRepr::Detailed(DetailedParamType::Array(items)),
+            ParamType::Object(fields) => return ParamTypeRepr::Detailed(DetailedParamType::Object(fields)),
+        };
+        ParamTypeRepr::Name(name)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Marked `#[non_exhaustive]` like [`ActionParameter`]; build definitions with
+/// [`ActionDefinition::new`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct ActionDefinition {
     pub name: String,
     pub description: String,
     pub parameters: Vec<ActionParameter>,
+    /// How long the action may run unless a call sets its own deadline; enforced
+    /// by [`executor::ActionExecutor`]
+    #[serde(default, rename = "timeout_ms", skip_serializing_if = "Option::is_none", with = "timeout_ms")]
+    pub timeout: Option<std::time::Duration>,
+}
+
+// Timeouts are exchanged as whole milliseconds
+mod timeout_ms {
+    use std::time::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(timeout: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match timeout {
+            Some(timeout) => serializer.serialize_u64(timeout.as_millis() as u64),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+    }
 }
 
-pub type ActionResult = Result<Value, String>;
+impl ActionDefinition {
+    pub fn new(name: impl Into<String>, description: impl Into<String>, parameters: Vec<ActionParameter>) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameters,
+            timeout: None,
+        }
+    }
+
+    /// Sets how long the action may run unless a call sets its own deadline
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// A copy of `params` that is safe to log, with the values of `Secret` parameters masked
+    pub fn redact(&self, params: &HashMap<String, Value>) -> HashMap<String, Value> {
+        params
+            .iter()
+            .map(|(name, value)| (name.clone(), redact_value(self.parameters.iter().find(|p| &p.name == name), value)))
+            .collect()
+    }
+}
+
+const REDACTED: &str = "********";
+
+fn redact_value(param: Option<&ActionParameter>, value: &Value) -> Value {
+    let Some(param) = param else {
+        return value.clone();
+    };
+    match (&param.param_type, value) {
+        (_, Value::Null) => Value::Null,
+        (ParamType::Secret, _) => Value::String(REDACTED.to_string()),
+        (ParamType::Array(items), Value::Array(values)) => {
+            let item = ActionParameter { param_type: (**items).clone(), ..param.clone() };
+            Value::Array(values.iter().map(|value| redact_value(Some(&item), value)).collect())
+        }
+        (ParamType::Object(fields), Value::Object(map)) => Value::Object(
+            map.iter()
+                .map(|(name, value)| (name.clone(), redact_value(fields.iter().find(|f| &f.name == name), value)))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+pub type ActionResult = Result<Value, CpiError>;
+
+pub mod error;
+pub use error::{CpiError, ErrorKind};
+
+pub mod context;
+pub use context::{ActionContext, CancellationToken, Principal};
 
 /// Main trait that must be implemented by CPI extensions
 pub trait CpiExtension: Send + Sync {
@@ -46,6 +350,19 @@ pub trait CpiExtension: Send + Sync {
     
     /// Executes an action with the given parameters
     fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult;
+
+    /// Executes an action on behalf of the call described by `context`
+    ///
+    /// Extensions that have no use for the context need not implement this; by
+    /// default it runs `execute_action`.
+    fn execute_action_with_context(
+        &self,
+        action: &str,
+        params: &HashMap<String, Value>,
+        _context: &ActionContext,
+    ) -> ActionResult {
+        self.execute_action(action, params)
+    }
     
     /// Optional method that returns default parameter values for the provider
     fn default_settings(&self) -> HashMap<String, Value> {
@@ -64,128 +381,277 @@ pub trait CpiExtension: Send + Sync {
     }
 }
 
+// Shared and boxed extensions, such as the `Arc`s the registry hands out, are extensions too
+macro_rules! forward_extension {
+    ($($pointer:ident),*) => {$(
+        impl<E: CpiExtension + ?Sized> CpiExtension for $pointer<E> {
+            fn name(&self) -> &str {
+                (**self).name()
+            }
+
+            fn provider_type(&self) -> &str {
+                (**self).provider_type()
+            }
+
+            fn list_actions(&self) -> Vec<String> {
+                (**self).list_actions()
+            }
+
+            fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+                (**self).get_action_definition(action)
+            }
+
+            fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+                (**self).execute_action(action, params)
+            }
+
+            fn execute_action_with_context(
+                &self,
+                action: &str,
+                params: &HashMap<String, Value>,
+                context: &ActionContext,
+            ) -> ActionResult {
+                (**self).execute_action_with_context(action, params, context)
+            }
+
+            fn default_settings(&self) -> HashMap<String, Value> {
+                (**self).default_settings()
+            }
+
+            fn test_install(&self) -> ActionResult {
+                (**self).test_install()
+            }
+
+            fn version(&self) -> String {
+                (**self).version()
+            }
+        }
+    )*};
+}
+
+forward_extension!(Box, Arc);
+
 // Required function signature for dynamic registration
-pub type GetExtensionFn = unsafe extern "C" fn() -> *mut dyn CpiExtension;
+pub type GetExtensionFn = unsafe extern "C" fn() -> ffi::CpiExtensionVTable;
+
+pub mod ffi;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod loader;
+#[cfg(not(target_arch = "wasm32"))]
+pub use loader::{ExtensionLoader, LoadedExtension};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod registry;
+#[cfg(not(target_arch = "wasm32"))]
+pub use registry::ExtensionRegistry;
+
+mod slots;
+
+pub mod rpc;
+pub use rpc::RemoteExtension;
+
+#[cfg(feature = "http")]
+pub mod http;
+
+pub mod wasm;
+#[cfg(feature = "wasm")]
+pub use wasm::{WasmExtension, WasmRuntime};
 
 // Explicitly re-export the action macro from lib_cpi_macros
 // This ensures the macro is available when users import lib_cpi
 pub use lib_cpi_macros::action;
 
+/// The procedural macros from lib_cpi_macros
+///
+/// `#[param]` lives here rather than at the crate root, where it would clash with `param!`.
+pub mod macros {
+    pub use lib_cpi_macros::{action, cpi_extension, generate_metadata, param, register_actions, register_async_actions};
+}
+
+// Used by the code the macros generate
+pub use serde_json;
+
+#[doc(hidden)]
+pub mod __private {
+    use std::collections::HashMap;
+    use serde::Serialize;
+    use serde_json::Value;
+
+    /// `default_settings` of a `#[cpi_extension]` from any value that serializes to a JSON object
+    pub fn settings<T: Serialize + ?Sized>(value: &T) -> HashMap<String, Value> {
+        match serde_json::to_value(value) {
+            Ok(Value::Object(map)) => map.into_iter().collect(),
+            Ok(other) => panic!("#[default_settings] must be a JSON object, got {}", other),
+            Err(e) => panic!("#[default_settings] cannot be serialized: {}", e),
+        }
+    }
+
+    /// `default_settings` of a `#[cpi_extension]` from JSON text
+    pub fn settings_from_json(text: &str) -> HashMap<String, Value> {
+        serde_json::from_str(text).unwrap_or_else(|e| panic!("#[default_settings] must be a JSON object: {}", e))
+    }
+
+    /// `default_value` of a `#[param(default = ...)]` given as a Rust expression
+    pub fn default_value<T: Serialize + ?Sized>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap_or_else(|e| panic!("#[param] default cannot be serialized: {}", e))
+    }
+
+    /// A JSON value written in a `#[param]`, as text that was checked while compiling
+    pub fn json_literal(text: &str) -> Value {
+        serde_json::from_str(text).unwrap_or_else(|e| panic!("#[param] value is not valid JSON: {}", e))
+    }
+
+    /// What the `<action>_execute` of a sync or an `async fn` action returns, so
+    /// generated dispatch does not need to know which it calls
+    pub trait ActionOutput<'a> {
+        fn into_future(self) -> crate::BoxFuture<'a, crate::ActionResult>;
+
+        fn wait(self) -> crate::ActionResult;
+    }
+
+    impl<'a> ActionOutput<'a> for crate::ActionResult {
+        fn into_future(self) -> crate::BoxFuture<'a, crate::ActionResult> {
+            Box::pin(std::future::ready(self))
+        }
+
+        fn wait(self) -> crate::ActionResult {
+            self
+        }
+    }
+
+    impl<'a> ActionOutput<'a> for crate::BoxFuture<'a, crate::ActionResult> {
+        fn into_future(self) -> crate::BoxFuture<'a, crate::ActionResult> {
+            self
+        }
+
+        fn wait(self) -> crate::ActionResult {
+            crate::asynchronous::block_on(self)
+        }
+    }
+}
+
 // Entry point macro that every extension DLL must implement
 #[macro_export]
 macro_rules! register_extension {
     ($ext_type:ty) => {
-        #[no_mangle]
-        pub unsafe extern "C" fn get_extension() -> *mut dyn $crate::CpiExtension {
-            // Create a Box containing the extension implementation
-            let extension = Box::new(<$ext_type>::new());
-            // Convert the Box into a raw pointer and return it
-            Box::into_raw(extension)
+        // Checked by the loader before `get_extension` is called
+        #[unsafe(no_mangle)]
+        pub extern "C" fn cpi_abi_version() -> u32 {
+            $crate::ffi::ABI_VERSION
         }
-    };
-}
 
-// Helper functions for parameter validation
-pub mod validation {
-    use super::*;
-    
-    pub fn extract_string(params: &HashMap<String, Value>, name: &str) -> Result<String, String> {
-        match params.get(name) {
-            Some(Value::String(s)) => Ok(s.clone()),
-            Some(_) => Err(format!("Parameter '{}' must be a string", name)),
-            None => Err(format!("Required parameter '{}' not provided", name)),
+        #[unsafe(no_mangle)]
+        pub extern "C" fn cpi_lib_version() -> *const ::std::ffi::c_char {
+            $crate::ffi::lib_cpi_version_ptr()
         }
-    }
-    
-    pub fn extract_string_opt(params: &HashMap<String, Value>, name: &str) -> Result<Option<String>, String> {
-        match params.get(name) {
-            Some(Value::String(s)) => Ok(Some(s.clone())),
-            Some(_) => Err(format!("Parameter '{}' must be a string", name)),
-            None => Ok(None),
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn get_extension() -> $crate::ffi::CpiExtensionVTable {
+            // Hand the extension out through a C-compatible function table rather
+            // than a Rust trait object, whose layout is not stable across compilers.
+            // A panicking constructor yields a failed table instead of unwinding into the host
+            $crate::ffi::export_with(<$ext_type>::new)
         }
-    }
-    
-    pub fn extract_int(params: &HashMap<String, Value>, name: &str) -> Result<i64, String> {
-        match params.get(name) {
-            Some(Value::Number(n)) if n.is_i64() => Ok(n.as_i64().unwrap()),
-            Some(_) => Err(format!("Parameter '{}' must be an integer", name)),
-            None => Err(format!("Required parameter '{}' not provided", name)),
+    };
+}
+
+// Entry point macro for extensions compiled to wasm32-unknown-unknown; see the wasm module
+#[macro_export]
+macro_rules! register_wasm_extension {
+    ($ext_type:ty) => {
+        // WASM modules are instantiated once per host, so one instance serves every call
+        fn __cpi_wasm_extension() -> &'static $ext_type {
+            static EXTENSION: ::std::sync::OnceLock<$ext_type> = ::std::sync::OnceLock::new();
+            EXTENSION.get_or_init(<$ext_type>::new)
         }
-    }
-    
-    pub fn extract_int_opt(params: &HashMap<String, Value>, name: &str) -> Result<Option<i64>, String> {
-        match params.get(name) {
-            Some(Value::Number(n)) if n.is_i64() => Ok(Some(n.as_i64().unwrap())),
-            Some(_) => Err(format!("Parameter '{}' must be an integer", name)),
-            None => Ok(None),
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn cpi_abi_version() -> u32 {
+            $crate::ffi::ABI_VERSION
         }
-    }
-    
-    pub fn extract_float(params: &HashMap<String, Value>, name: &str) -> Result<f64, String> {
-        match params.get(name) {
-            Some(Value::Number(n)) => Ok(n.as_f64().unwrap()),
-            Some(_) => Err(format!("Parameter '{}' must be a number", name)),
-            None => Err(format!("Required parameter '{}' not provided", name)),
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn cpi_lib_version() -> u64 {
+            $crate::wasm::guest::static_str($crate::ffi::LIB_CPI_VERSION)
         }
-    }
-    
-    pub fn extract_bool(params: &HashMap<String, Value>, name: &str) -> Result<bool, String> {
-        match params.get(name) {
-            Some(Value::Bool(b)) => Ok(*b),
-            Some(_) => Err(format!("Parameter '{}' must be a boolean", name)),
-            None => Err(format!("Required parameter '{}' not provided", name)),
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn cpi_alloc(len: u32) -> u32 {
+            $crate::wasm::guest::alloc(len)
         }
-    }
-    
-    pub fn extract_json(params: &HashMap<String, Value>, name: &str) -> Result<Value, String> {
-        match params.get(name) {
-            Some(v) => Ok(v.clone()),
-            None => Err(format!("Required parameter '{}' not provided", name)),
+
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn cpi_free(ptr: u32, len: u32) {
+            unsafe { $crate::wasm::guest::free(ptr, len) }
         }
-    }
-    
-    pub fn validate_params(
-        params: &HashMap<String, Value>, 
-        required: &[&str]
-    ) -> Result<(), String> {
-        for &param in required {
-            if !params.contains_key(param) {
-                return Err(format!("Required parameter '{}' not provided", param));
+
+        $crate::register_wasm_extension!(@methods
+            cpi_name => Name,
+            cpi_provider_type => ProviderType,
+            cpi_list_actions => ListActions,
+            cpi_get_action_definition => GetActionDefinition,
+            cpi_execute_action => ExecuteAction,
+            cpi_default_settings => DefaultSettings,
+            cpi_test_install => TestInstall,
+            cpi_version => Version,
+        );
+    };
+    (@methods $($export:ident => $method:ident,)*) => {
+        $(
+            #[unsafe(no_mangle)]
+            pub unsafe extern "C" fn $export(ptr: u32, len: u32) -> u64 {
+                unsafe { $crate::wasm::guest::call(__cpi_wasm_extension(), $crate::ffi::Method::$method, ptr, len) }
             }
-        }
-        Ok(())
-    }
+        )*
+    };
 }
 
+// Helper functions for parameter validation
+pub mod validation;
+
+pub mod validated;
+pub use validated::Validated;
+
+pub mod isolation;
+pub use isolation::Isolated;
+
+pub mod asynchronous;
+pub use asynchronous::{AsyncCpiExtension, Blocking, BoxFuture, Offloaded};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod executor;
+#[cfg(not(target_arch = "wasm32"))]
+pub use executor::ActionExecutor;
+
+pub mod operation;
+pub use operation::{Operation, OperationState, Operations};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod jobs;
+#[cfg(not(target_arch = "wasm32"))]
+pub use jobs::{JobManager, Jobs};
+
 // Helper macro to simplify creating parameter definitions
+//
+// Constraints follow as `key = value` pairs named like the `Constraints` fields:
+// `param!("cpus", "Number of CPUs", ParamType::Integer, required, min = 1, max = 64)`
 #[macro_export]
 macro_rules! param {
-    ($name:expr, $desc:expr, $type:expr, required) => {
-        $crate::ActionParameter {
-            name: $name.to_string(),
-            description: $desc.to_string(),
-            required: true,
-            param_type: $type,
-            default_value: None,
-        }
+    ($name:expr, $desc:expr, $type:expr, required $(, $key:ident = $value:expr)*) => {
+        $crate::ActionParameter::new($name.to_string(), $desc.to_string(), $type, true)
+            .with_constraints($crate::Constraints::default()$(.$key($value))*)
     };
-    ($name:expr, $desc:expr, $type:expr, optional, $default:expr) => {
-        $crate::ActionParameter {
-            name: $name.to_string(),
-            description: $desc.to_string(),
-            required: false,
-            param_type: $type,
-            default_value: Some($default),
-        }
+    ($name:expr, $desc:expr, $type:expr, optional $(, $key:ident = $value:expr)+) => {
+        $crate::ActionParameter::new($name.to_string(), $desc.to_string(), $type, false)
+            .with_constraints($crate::Constraints::default()$(.$key($value))*)
+    };
+    ($name:expr, $desc:expr, $type:expr, optional, $default:expr $(, $key:ident = $value:expr)*) => {
+        $crate::ActionParameter::new($name.to_string(), $desc.to_string(), $type, false)
+            .with_default($default)
+            .with_constraints($crate::Constraints::default()$(.$key($value))*)
     };
     ($name:expr, $desc:expr, $type:expr, optional) => {
-        $crate::ActionParameter {
-            name: $name.to_string(),
-            description: $desc.to_string(),
-            required: false,
-            param_type: $type,
-            default_value: None,
-        }
+        $crate::ActionParameter::new($name.to_string(), $desc.to_string(), $type, false)
     };
 }
 
@@ -218,4 +684,15 @@ pub mod response {
             "error": message.as_ref(),
         })
     }
+
+    /// `error` with the kind, retryability and details of a `CpiError`
+    pub fn failure(cpi_error: &crate::CpiError) -> Value {
+        let mut value = error(&cpi_error.message);
+        value["kind"] = json!(cpi_error.kind);
+        value["retryable"] = json!(cpi_error.retryable);
+        if let Some(details) = &cpi_error.details {
+            value["details"] = details.clone();
+        }
+        value
+    }
 }
\ No newline at end of file
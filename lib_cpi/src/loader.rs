@@ -0,0 +1,279 @@
+// File: lib_cpi/src/loader.rs
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+use libloading::Library;
+use serde_json::Value;
+
+use crate::{ActionContext, ActionDefinition, ActionResult, CpiExtension, GetExtensionFn};
+use crate::ffi::{
+    ABI_VERSION, ABI_VERSION_SYMBOL, AbiVersionFn, ForeignExtension, LIB_CPI_VERSION, LIB_VERSION_SYMBOL,
+    LibVersionFn,
+};
+
+/// Name of the entry point every extension library exports via `register_extension!`
+pub const ENTRY_SYMBOL: &str = "get_extension";
+
+/// Loads CPI extensions from shared libraries
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionLoader {
+    shadow_dir: Option<PathBuf>,
+}
+
+impl ExtensionLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a private copy of each library from `dir` instead of the original file
+    ///
+    /// The dynamic linker hands out the already loaded library when the same path is
+    /// opened twice, and overwriting a mapped library in place can crash the host.
+    /// Loading from a copy avoids both, so the original can be replaced and loaded
+    /// again side by side. The copy is deleted when the extension is unloaded.
+    pub fn with_shadow_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.shadow_dir = Some(dir.into());
+        self
+    }
+
+    /// Returns the shadow copy directory, if any
+    pub fn shadow_dir(&self) -> Option<&Path> {
+        self.shadow_dir.as_deref()
+    }
+
+    /// Loads the library at `path` and instantiates the extension it registers
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<LoadedExtension, String> {
+        let path = path.as_ref();
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        let shadow = match &self.shadow_dir {
+            Some(dir) => Some(ShadowCopy::create(path, dir)?),
+            None => None,
+        };
+        let load_path = shadow.as_ref().map_or(path, |shadow| shadow.path.as_path());
+
+        // SAFETY: loading a library runs its initialisers; extension libraries are
+        // trusted to be built with `register_extension!`
+        let library = unsafe { Library::new(load_path) }
+            .map_err(|e| format!("Failed to load extension library '{}': {}", path.display(), e))?;
+
+        let lib_cpi_version = check_abi_version(&library, path)?;
+
+        // SAFETY: `register_extension!` exports `get_extension` with this signature and
+        // the returned table is owned by the `LoadedExtension`, which keeps the library alive
+        let vtable = unsafe {
+            let get_extension = library
+                .get::<GetExtensionFn>(ENTRY_SYMBOL.as_bytes())
+                .map_err(|e| format!("Library '{}' does not export '{}': {}", path.display(), ENTRY_SYMBOL, e))?;
+            get_extension()
+        };
+        if vtable.is_failed() {
+            return Err(format!("Extension in '{}' panicked while being created", path.display()));
+        }
+        let extension = unsafe { ForeignExtension::from_vtable(vtable) };
+
+        Ok(LoadedExtension {
+            extension,
+            library,
+            _shadow: shadow,
+            path: path.to_path_buf(),
+            modified,
+            lib_cpi_version,
+            in_flight: AtomicUsize::new(0),
+        })
+    }
+}
+
+// A uniquely named copy of a library, removed again once it is no longer mapped
+#[derive(Debug)]
+struct ShadowCopy {
+    path: PathBuf,
+}
+
+impl ShadowCopy {
+    fn create(original: &Path, dir: &Path) -> Result<Self, String> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create shadow directory '{}': {}", dir.display(), e))?;
+
+        let stem = original.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+        let mut file_name = format!("{}-{}-{}", stem, std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+        if let Some(extension) = original.extension() {
+            file_name.push('.');
+            file_name.push_str(&extension.to_string_lossy());
+        }
+
+        let path = dir.join(file_name);
+        std::fs::copy(original, &path)
+            .map_err(|e| format!("Failed to copy '{}' to '{}': {}", original.display(), path.display(), e))?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for ShadowCopy {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Refuses libraries built against an incompatible boundary before any of their
+// entry points are called; returns the lib_cpi version the library reports
+fn check_abi_version(library: &Library, path: &Path) -> Result<String, String> {
+    // SAFETY: both symbols are exported by `register_extension!` with these signatures
+    unsafe {
+        let abi_version = library
+            .get::<AbiVersionFn>(ABI_VERSION_SYMBOL.as_bytes())
+            .map_err(|_| format!(
+                "Library '{}' does not export '{}'; it was built with an older lib_cpi and must be rebuilt against lib_cpi {}",
+                path.display(), ABI_VERSION_SYMBOL, LIB_CPI_VERSION
+            ))?();
+
+        let lib_cpi_version = match library.get::<LibVersionFn>(LIB_VERSION_SYMBOL.as_bytes()) {
+            Ok(lib_version) => {
+                let ptr = lib_version();
+                if ptr.is_null() {
+                    "unknown".to_string()
+                } else {
+                    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+                }
+            }
+            Err(_) => "unknown".to_string(),
+        };
+
+        if abi_version != ABI_VERSION {
+            return Err(format!(
+                "Library '{}' was built against lib_cpi {} (ABI version {}), but this host uses lib_cpi {} (ABI version {})",
+                path.display(), lib_cpi_version, abi_version, LIB_CPI_VERSION, ABI_VERSION
+            ));
+        }
+
+        Ok(lib_cpi_version)
+    }
+}
+
+/// An extension instance together with the library its code lives in
+///
+/// The extension is always dropped before the library is unloaded.
+pub struct LoadedExtension {
+    // Field order matters: fields are dropped in declaration order, so the
+    // extension (whose function table points into the library) goes first
+    extension: ForeignExtension,
+    library: Library,
+    _shadow: Option<ShadowCopy>,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    lib_cpi_version: String,
+    in_flight: AtomicUsize,
+}
+
+// Counts an `execute_action` call for as long as it runs
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn enter(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl LoadedExtension {
+    /// Returns the path the extension was loaded from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the modification time of the library when it was loaded
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// Returns the number of `execute_action` calls currently running
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Returns the lib_cpi version the extension was built against
+    pub fn lib_cpi_version(&self) -> &str {
+        &self.lib_cpi_version
+    }
+
+    /// Returns the loaded extension
+    pub fn extension(&self) -> &dyn CpiExtension {
+        &self.extension
+    }
+
+    /// Drops the extension and unloads its library, reporting unload failures
+    pub fn unload(self) -> Result<(), String> {
+        let LoadedExtension { extension, library, _shadow: shadow, path, .. } = self;
+        drop(extension);
+        let result = library
+            .close()
+            .map_err(|e| format!("Failed to unload extension library '{}': {}", path.display(), e));
+        // The shadow copy can only be removed once the library is closed
+        drop(shadow);
+        result
+    }
+}
+
+impl std::fmt::Debug for LoadedExtension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoadedExtension")
+            .field("name", &self.extension.name())
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl CpiExtension for LoadedExtension {
+    fn name(&self) -> &str {
+        self.extension.name()
+    }
+
+    fn provider_type(&self) -> &str {
+        self.extension.provider_type()
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        self.extension.list_actions()
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        self.extension.get_action_definition(action)
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        let _in_flight = InFlight::enter(&self.in_flight);
+        self.extension.execute_action(action, params)
+    }
+
+    fn execute_action_with_context(
+        &self,
+        action: &str,
+        params: &HashMap<String, Value>,
+        context: &ActionContext,
+    ) -> ActionResult {
+        let _in_flight = InFlight::enter(&self.in_flight);
+        self.extension.execute_action_with_context(action, params, context)
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        self.extension.default_settings()
+    }
+
+    fn test_install(&self) -> ActionResult {
+        self.extension.test_install()
+    }
+
+    fn version(&self) -> String {
+        self.extension.version()
+    }
+}
@@ -0,0 +1,148 @@
+//! Actions that answer at once and finish in the background
+//!
+//! An action run as an operation returns a handle instead of its result:
+//!
+//! ```json
+//! {"id": "…", "action": "create_vm", "state": "Pending", "progress": 0}
+//! ```
+//!
+//! The caller then polls through three reserved actions, which cross plugin
+//! boundaries like any other action: `get_operation` and `cancel_operation` take
+//! the operation's `id`, `list_operations` takes nothing. [`Operations`] calls them
+//! on any `CpiExtension`, and [`crate::jobs::Jobs`] implements them in-process for
+//! any extension. A finished operation carries the action's `result` or `error`:
+//!
+//! ```json
+//! {"id": "…", "action": "create_vm", "state": "Succeeded", "progress": 100, "result": {"id": "vm-1"}}
+//! ```
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{ActionDefinition, ActionResult, CpiError, CpiExtension, ParamType, param};
+
+/// Reserved action returning the operation with the given `id`
+pub const GET_OPERATION: &str = "get_operation";
+/// Reserved action returning every operation the extension knows of
+pub const LIST_OPERATIONS: &str = "list_operations";
+/// Reserved action asking the operation with the given `id` to stop
+pub const CANCEL_OPERATION: &str = "cancel_operation";
+
+/// Every reserved action, which extensions running operations add to `list_actions`
+pub const ACTIONS: [&str; 3] = [GET_OPERATION, LIST_OPERATIONS, CANCEL_OPERATION];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OperationState {
+    /// Waiting for a free worker
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl OperationState {
+    /// Whether the operation has stopped and will not change again
+    pub fn is_finished(self) -> bool {
+        matches!(self, OperationState::Succeeded | OperationState::Failed | OperationState::Cancelled)
+    }
+}
+
+/// Status of one run of an action in the background
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: String,
+    pub action: String,
+    pub state: OperationState,
+    /// How far along the action is, from 0 to 100 percent
+    #[serde(default)]
+    pub progress: u8,
+    /// What the action returned, once it has succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// Why the operation failed or was cancelled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<CpiError>,
+}
+
+impl Operation {
+    /// A new `Pending` operation
+    pub fn new(id: impl Into<String>, action: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            action: action.into(),
+            state: OperationState::Pending,
+            progress: 0,
+            result: None,
+            error: None,
+        }
+    }
+
+    /// Reads an operation returned by an action or one of the reserved actions
+    pub fn from_value(value: Value) -> Result<Self, CpiError> {
+        serde_json::from_value(value).map_err(|e| CpiError::internal(format!("Malformed operation: {}", e)))
+    }
+
+    pub fn to_value(&self) -> Value {
+        json!(self)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.is_finished()
+    }
+
+    /// The final result of the action, once the operation has finished
+    pub fn outcome(&self) -> Option<ActionResult> {
+        match self.state {
+            OperationState::Pending | OperationState::Running => None,
+            OperationState::Succeeded => Some(Ok(self.result.clone().unwrap_or(Value::Null))),
+            OperationState::Failed | OperationState::Cancelled => Some(Err(self.error.clone().unwrap_or_else(|| {
+                CpiError::internal(format!("Operation '{}' finished without a result", self.id))
+            }))),
+        }
+    }
+}
+
+/// Definition of one of the reserved actions, for extensions implementing them
+pub fn definition(action: &str) -> Option<ActionDefinition> {
+    let id = || param!("id", "Identifier of the operation", ParamType::String, required);
+    let (description, parameters) = match action {
+        GET_OPERATION => ("Returns the state of an operation", vec![id()]),
+        LIST_OPERATIONS => ("Lists running and recently finished operations", Vec::new()),
+        CANCEL_OPERATION => ("Asks a pending or running operation to stop", vec![id()]),
+        _ => return None,
+    };
+    Some(ActionDefinition::new(action, description, parameters))
+}
+
+/// Polling and cancelling operations through the reserved actions
+///
+/// Implemented for every `CpiExtension`; extensions that do not run operations
+/// fail these calls with the `Unsupported` error of an unknown action.
+pub trait Operations {
+    fn get_operation(&self, id: &str) -> Result<Operation, CpiError>;
+
+    fn list_operations(&self) -> Result<Vec<Operation>, CpiError>;
+
+    fn cancel_operation(&self, id: &str) -> Result<Operation, CpiError>;
+}
+
+impl<E: CpiExtension + ?Sized> Operations for E {
+    fn get_operation(&self, id: &str) -> Result<Operation, CpiError> {
+        Operation::from_value(self.execute_action(GET_OPERATION, &id_param(id))?)
+    }
+
+    fn list_operations(&self) -> Result<Vec<Operation>, CpiError> {
+        let value = self.execute_action(LIST_OPERATIONS, &HashMap::new())?;
+        serde_json::from_value(value).map_err(|e| CpiError::internal(format!("Malformed operation: {}", e)))
+    }
+
+    fn cancel_operation(&self, id: &str) -> Result<Operation, CpiError> {
+        Operation::from_value(self.execute_action(CANCEL_OPERATION, &id_param(id))?)
+    }
+}
+
+fn id_param(id: &str) -> HashMap<String, Value> {
+    HashMap::from([("id".to_string(), json!(id))])
+}
@@ -0,0 +1,362 @@
+// File: lib_cpi/src/registry.rs
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, mpsc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::CpiExtension;
+use crate::loader::{ExtensionLoader, LoadedExtension};
+
+/// How long a reload waits for running `execute_action` calls on the old instance
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A library that could not be added to the registry
+#[derive(Debug, Clone)]
+pub struct LoadFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// Outcome of scanning a plugin directory
+#[derive(Debug, Clone, Default)]
+pub struct ScanReport {
+    /// Names of the extensions that were loaded
+    pub loaded: Vec<String>,
+    /// Libraries that failed to load, one entry per file
+    pub failures: Vec<LoadFailure>,
+}
+
+/// Emitted when an extension has been replaced by a new build of its library
+#[derive(Debug, Clone)]
+pub struct ReloadEvent {
+    pub name: String,
+    pub path: PathBuf,
+    /// `version()` reported by the instance that was replaced
+    pub old_version: String,
+    /// `version()` reported by the instance now in the registry
+    pub new_version: String,
+    /// Whether all in-flight calls on the old instance finished within the drain timeout
+    pub drained: bool,
+    /// Whether the old library was unloaded; false while other handles to it are still alive
+    pub unloaded: bool,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    extensions: HashMap<String, Arc<LoadedExtension>>,
+    provider_types: HashMap<String, Vec<String>>,
+}
+
+impl Index {
+    fn insert(&mut self, extension: Arc<LoadedExtension>) {
+        let name = extension.name().to_string();
+        self.provider_types
+            .entry(extension.provider_type().to_string())
+            .or_default()
+            .push(name.clone());
+        self.extensions.insert(name, extension);
+    }
+
+    fn remove(&mut self, name: &str) -> Option<Arc<LoadedExtension>> {
+        let extension = self.extensions.remove(name)?;
+        if let Some(names) = self.provider_types.get_mut(extension.provider_type()) {
+            names.retain(|n| n != name);
+            if names.is_empty() {
+                self.provider_types.remove(extension.provider_type());
+            }
+        }
+        Some(extension)
+    }
+}
+
+/// Collection of loaded extensions indexed by name and provider type
+///
+/// All methods take `&self`, so a registry can be shared between threads and
+/// reloaded while it is in use.
+///
+/// A registry from [`ExtensionRegistry::new`] loads every library from a shadow
+/// copy, so a new build can be put in place while the old one is mapped. Ship it
+/// by writing to a temporary name in the same directory and renaming it over the
+/// old file, so the watcher never sees a half-written library. Pass
+/// `ExtensionLoader::new()` to [`ExtensionRegistry::with_loader`] to map the
+/// originals instead; reloads still use shadow copies.
+#[derive(Debug)]
+pub struct ExtensionRegistry {
+    loader: ExtensionLoader,
+    index: RwLock<Index>,
+    // Modification times that were already tried, so a broken build is not reloaded on every poll
+    attempted: Mutex<HashMap<PathBuf, SystemTime>>,
+    drain_timeout: Duration,
+}
+
+impl Default for ExtensionRegistry {
+    fn default() -> Self {
+        Self::with_loader(ExtensionLoader::new().with_shadow_dir(default_shadow_dir()))
+    }
+}
+
+impl ExtensionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry that loads libraries with the given loader
+    pub fn with_loader(loader: ExtensionLoader) -> Self {
+        Self {
+            loader,
+            index: RwLock::default(),
+            attempted: Mutex::default(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+        }
+    }
+
+    /// Sets how long a reload waits for in-flight calls on the old instance
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Loads every shared library in `dir`
+    ///
+    /// Only fails if the directory itself cannot be read; libraries that fail to
+    /// load are reported in the returned [`ScanReport`] instead.
+    pub fn scan_dir(&self, dir: impl AsRef<Path>) -> Result<ScanReport, String> {
+        let dir = dir.as_ref();
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read extension directory '{}': {}", dir.display(), e))?;
+
+        let mut libraries: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && is_library(path))
+            .collect();
+        libraries.sort();
+
+        let mut report = ScanReport::default();
+        for path in libraries {
+            match self.load(&path) {
+                Ok(extension) => report.loaded.push(extension.name().to_string()),
+                Err(error) => report.failures.push(LoadFailure { path, error }),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Loads a single library and adds its extension to the registry
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<Arc<LoadedExtension>, String> {
+        let extension = self.loader.load(path)?;
+        self.insert(extension)
+    }
+
+    /// Adds an already loaded extension, refusing duplicate names
+    pub fn insert(&self, extension: LoadedExtension) -> Result<Arc<LoadedExtension>, String> {
+        let mut index = self.write();
+        if let Some(existing) = index.extensions.get(extension.name()) {
+            return Err(format!(
+                "Extension '{}' from '{}' is already loaded from '{}'",
+                extension.name(),
+                extension.path().display(),
+                existing.path().display()
+            ));
+        }
+
+        let extension = Arc::new(extension);
+        index.insert(extension.clone());
+        Ok(extension)
+    }
+
+    /// Removes an extension; its library is unloaded once the last handle is dropped
+    pub fn remove(&self, name: &str) -> Option<Arc<LoadedExtension>> {
+        self.write().remove(name)
+    }
+
+    /// Looks up an extension by its `name()`
+    ///
+    /// Hosts should look extensions up per call rather than keep the handle, so
+    /// that calls reach the new instance after a reload.
+    pub fn get(&self, name: &str) -> Option<Arc<LoadedExtension>> {
+        self.read().extensions.get(name).cloned()
+    }
+
+    /// Returns all extensions with the given `provider_type()`, in load order
+    pub fn by_provider_type(&self, provider_type: &str) -> Vec<Arc<LoadedExtension>> {
+        let index = self.read();
+        index
+            .provider_types
+            .get(provider_type)
+            .map(|names| names.iter().filter_map(|name| index.extensions.get(name).cloned()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the names of all loaded extensions, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.read().extensions.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Returns the provider types of all loaded extensions, sorted
+    pub fn provider_types(&self) -> Vec<String> {
+        let mut types: Vec<String> = self.read().provider_types.keys().cloned().collect();
+        types.sort();
+        types
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().extensions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().extensions.is_empty()
+    }
+
+    /// Replaces an extension with a fresh load of its library
+    ///
+    /// The new instance is loaded side by side with the old one and swapped in;
+    /// then in-flight `execute_action` calls on the old instance are drained and
+    /// its library is unloaded. The old instance stays in place if loading fails.
+    pub fn reload(&self, name: &str) -> Result<ReloadEvent, String> {
+        let old = self.get(name).ok_or_else(|| format!("Extension '{}' is not loaded", name))?;
+        let path = old.path().to_path_buf();
+
+        let new = self.reload_loader().load(&path)?;
+        if new.name() != name {
+            return Err(format!(
+                "Reloaded library '{}' provides extension '{}' instead of '{}'",
+                path.display(),
+                new.name(),
+                name
+            ));
+        }
+        let new_version = new.version();
+
+        {
+            let mut index = self.write();
+            index.remove(name);
+            index.insert(Arc::new(new));
+        }
+
+        let deadline = Instant::now() + self.drain_timeout;
+        while old.in_flight() > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let drained = old.in_flight() == 0;
+        let old_version = old.version();
+
+        // Handles held elsewhere keep the old library mapped until they are dropped
+        let unloaded = match Arc::try_unwrap(old) {
+            Ok(old) => old.unload().is_ok(),
+            Err(_) => false,
+        };
+
+        Ok(ReloadEvent {
+            name: name.to_string(),
+            path,
+            old_version,
+            new_version,
+            drained,
+            unloaded,
+        })
+    }
+
+    /// Reloads every extension whose library changed on disk since it was loaded
+    pub fn reload_changed(&self) -> Vec<Result<ReloadEvent, LoadFailure>> {
+        let candidates: Vec<(String, PathBuf, Option<SystemTime>)> = self
+            .read()
+            .extensions
+            .values()
+            .map(|extension| (extension.name().to_string(), extension.path().to_path_buf(), extension.modified()))
+            .collect();
+
+        let mut events = Vec::new();
+        for (name, path, loaded_at) in candidates {
+            let Some(modified) = std::fs::metadata(&path).and_then(|m| m.modified()).ok() else {
+                continue;
+            };
+            if Some(modified) == loaded_at {
+                continue;
+            }
+            if self.attempted().insert(path.clone(), modified) == Some(modified) {
+                continue;
+            }
+
+            events.push(self.reload(&name).map_err(|error| LoadFailure { path, error }));
+        }
+        events
+    }
+
+    /// Polls the loaded libraries every `interval` and reloads the ones that changed
+    ///
+    /// `on_event` is called from the watcher thread for every reload attempt. Builds
+    /// copied over a library in place can be picked up half-written and reported as
+    /// failures; rename them into place instead. Watching stops when the returned [`RegistryWatcher`] is dropped.
+    pub fn watch<F>(self: &Arc<Self>, interval: Duration, on_event: F) -> RegistryWatcher
+    where
+        F: Fn(Result<ReloadEvent, LoadFailure>) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let registry = Arc::downgrade(self);
+        let thread = std::thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let Some(registry) = registry.upgrade() else {
+                    break;
+                };
+                for event in registry.reload_changed() {
+                    on_event(event);
+                }
+            }
+        });
+
+        RegistryWatcher {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    // Reloading the same path must not hand back the library that is already mapped
+    fn reload_loader(&self) -> ExtensionLoader {
+        if self.loader.shadow_dir().is_some() {
+            self.loader.clone()
+        } else {
+            self.loader.clone().with_shadow_dir(default_shadow_dir())
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Index> {
+        self.index.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Index> {
+        self.index.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn attempted(&self) -> MutexGuard<'_, HashMap<PathBuf, SystemTime>> {
+        self.attempted.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn default_shadow_dir() -> PathBuf {
+    std::env::temp_dir().join("lib_cpi-shadow")
+}
+
+/// Background thread started by [`ExtensionRegistry::watch`]; stops it when dropped
+#[derive(Debug)]
+pub struct RegistryWatcher {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for RegistryWatcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn is_library(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == std::env::consts::DLL_EXTENSION)
+}
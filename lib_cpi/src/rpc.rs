@@ -0,0 +1,512 @@
+// File: lib_cpi/src/rpc.rs
+//! Out-of-process extensions over JSON-RPC 2.0
+//!
+//! Messages are newline-delimited JSON objects. The extension process runs
+//! [`serve_stdio`] (or a [`socket::RpcServer`] when it is a long-lived daemon) and
+//! the host talks to it through [`RemoteExtension`], so a crashing or panicking
+//! provider takes down only its own process.
+//!
+//! Methods mirror `CpiExtension`: `name`, `provider_type`, `list_actions`,
+//! `get_action_definition` (`{"action"}`), `execute_action` (`{"action", "params"}`,
+//! plus the serialized `ActionContext` as `"context"` when there is one),
+//! `default_settings`, `test_install` and `version`. An action that returns `Err`
+//! or panics is reported as a JSON-RPC error with code [`ACTION_FAILED`].
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, mpsc};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::isolation::catch_panic;
+use crate::{ActionContext, ActionDefinition, ActionResult, CpiError, CpiExtension};
+
+pub mod socket;
+pub use socket::{Endpoint, RpcServer};
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+// Standard JSON-RPC error codes
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+
+/// Error code used when the action itself returned an error
+pub const ACTION_FAILED: i64 = -32000;
+
+/// Error code reported by the client when the connection to the extension failed
+pub const TRANSPORT_ERROR: i64 = -32001;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub id: Value,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// [`ACTION_FAILED`] with the action's `CpiError` as `data`
+    pub fn action_failed(error: CpiError) -> Self {
+        Self {
+            data: serde_json::to_value(&error).ok(),
+            ..Self::new(ACTION_FAILED, error.message)
+        }
+    }
+
+    /// The `CpiError` a failed call stands for on the client side
+    ///
+    /// Servers built before errors were structured send no `data`, so their
+    /// messages become `Internal` errors. Lost connections are `Unavailable`.
+    pub fn into_cpi_error(self) -> CpiError {
+        match self.code {
+            ACTION_FAILED => match self.data.and_then(|data| serde_json::from_value(data).ok()) {
+                Some(error) => error,
+                None => CpiError::internal(self.message),
+            },
+            TRANSPORT_ERROR => CpiError::unavailable(self.message),
+            _ => CpiError::internal(self.message),
+        }
+    }
+}
+
+impl Response {
+    fn new(id: Value, outcome: Result<Value, RpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ActionParams {
+    action: String,
+    #[serde(default)]
+    params: HashMap<String, Value>,
+    #[serde(default)]
+    context: Option<ActionContext>,
+}
+
+// Server side
+
+/// Serves `extension` over this process's stdin and stdout until stdin is closed
+///
+/// Stdout carries the protocol, so the extension must log to stderr only.
+pub fn serve_stdio(extension: impl CpiExtension) -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    serve(&extension, stdin.lock(), stdout.lock())
+}
+
+/// Answers newline-delimited requests from `reader` on `writer` until end of input
+pub fn serve<E: CpiExtension + ?Sized>(extension: &E, reader: impl BufRead, mut writer: impl Write) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = handle_message(extension, &line);
+        write_response(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+fn write_response(writer: &mut impl Write, response: &Response) -> io::Result<()> {
+    let mut line = serde_json::to_vec(response)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    writer.flush()
+}
+
+/// Parses one request line and produces its response
+pub fn handle_message<E: CpiExtension + ?Sized>(extension: &E, line: &str) -> Response {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return Response::new(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))),
+    };
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    match serde_json::from_value::<Request>(value) {
+        Ok(request) => handle_request(extension, &request),
+        Err(e) => Response::new(id, Err(RpcError::new(INVALID_REQUEST, e.to_string()))),
+    }
+}
+
+/// Dispatches a request to the matching `CpiExtension` method
+pub fn handle_request<E: CpiExtension + ?Sized>(extension: &E, request: &Request) -> Response {
+    Response::new(request.id.clone(), dispatch(extension, &request.method, &request.params))
+}
+
+fn dispatch<E: CpiExtension + ?Sized>(extension: &E, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "name" => Ok(json!(extension.name())),
+        "provider_type" => Ok(json!(extension.provider_type())),
+        "list_actions" => Ok(json!(extension.list_actions())),
+        "get_action_definition" => {
+            let action: ActionParams = parse_params(params)?;
+            Ok(json!(extension.get_action_definition(&action.action)))
+        }
+        "execute_action" => {
+            let action: ActionParams = parse_params(params)?;
+            catch_panic(&action.action, || match &action.context {
+                Some(context) => extension.execute_action_with_context(&action.action, &action.params, context),
+                None => extension.execute_action(&action.action, &action.params),
+            })
+            .map_err(RpcError::action_failed)
+        }
+        "default_settings" => Ok(json!(extension.default_settings())),
+        "test_install" => catch_panic("test_install", || extension.test_install()).map_err(RpcError::action_failed),
+        "version" => Ok(json!(extension.version())),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: &Value) -> Result<T, RpcError> {
+    serde_json::from_value(params.clone()).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+// Client side
+
+/// One established connection to an extension: the write half plus the requests
+/// waiting for the reader thread to deliver their responses
+struct Connection {
+    writer: Mutex<Box<dyn Write + Send>>,
+    pending: Mutex<HashMap<u64, mpsc::Sender<Response>>>,
+    alive: AtomicBool,
+    close: Box<dyn Fn() + Send + Sync>,
+}
+
+impl Connection {
+    fn open(transport: Transport) -> Arc<Self> {
+        let Transport { writer, reader, close } = transport;
+        let connection = Arc::new(Self {
+            writer: Mutex::new(writer),
+            pending: Mutex::default(),
+            alive: AtomicBool::new(true),
+            close,
+        });
+
+        let reading = connection.clone();
+        std::thread::spawn(move || reading.read_responses(reader));
+        connection
+    }
+
+    // Runs on the reader thread: routes every response to the caller waiting for its id
+    fn read_responses(&self, mut reader: Box<dyn BufRead + Send>) {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            let Ok(response) = serde_json::from_str::<Response>(&line) else {
+                continue;
+            };
+            let Some(id) = response.id.as_u64() else {
+                continue;
+            };
+            if let Some(waiter) = lock(&self.pending).remove(&id) {
+                let _ = waiter.send(response);
+            }
+        }
+
+        // Dropping the senders wakes every caller still waiting on this connection
+        self.alive.store(false, Ordering::SeqCst);
+        lock(&self.pending).clear();
+    }
+
+    fn send(&self, id: u64, line: &[u8]) -> Result<mpsc::Receiver<Response>, RpcError> {
+        let (sender, receiver) = mpsc::channel();
+        lock(&self.pending).insert(id, sender);
+        // The reader thread marks the connection dead before it clears `pending`,
+        // so a request registered after that is caught here instead of waiting forever
+        if !self.alive.load(Ordering::SeqCst) {
+            lock(&self.pending).remove(&id);
+            return Err(RpcError::new(TRANSPORT_ERROR, "Extension closed the connection"));
+        }
+
+        let mut writer = lock(&self.writer);
+        if let Err(e) = writer.write_all(line).and_then(|_| writer.flush()) {
+            lock(&self.pending).remove(&id);
+            self.alive.store(false, Ordering::SeqCst);
+            // The extension may exit before the reader thread has seen the end of its output
+            if e.kind() == io::ErrorKind::BrokenPipe {
+                return Err(RpcError::new(TRANSPORT_ERROR, "Extension closed the connection"));
+            }
+            return Err(RpcError::new(TRANSPORT_ERROR, format!("Failed to send request to extension: {}", e)));
+        }
+        Ok(receiver)
+    }
+
+    fn shutdown(&self) {
+        self.alive.store(false, Ordering::SeqCst);
+        *lock(&self.writer) = Box::new(io::sink());
+        (self.close)();
+    }
+}
+
+/// The two halves of a byte stream to an extension, plus a way to tear it down
+pub struct Transport {
+    pub writer: Box<dyn Write + Send>,
+    pub reader: Box<dyn BufRead + Send>,
+    /// Called when the client is dropped; must make the reader return end of input
+    pub close: Box<dyn Fn() + Send + Sync>,
+}
+
+type Connector = Box<dyn Fn() -> io::Result<Transport> + Send + Sync>;
+
+/// JSON-RPC client that multiplexes concurrent requests over one connection by id
+struct Client {
+    connection: Mutex<Option<Arc<Connection>>>,
+    // Re-establishes the connection after it dropped; stdio children cannot be reconnected
+    connector: Option<Connector>,
+    next_id: AtomicU64,
+}
+
+impl Client {
+    fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let request = Request {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id: json!(id),
+            method: method.to_string(),
+            params,
+        };
+        let mut line = serde_json::to_vec(&request).map_err(|e| RpcError::new(INVALID_REQUEST, e.to_string()))?;
+        line.push(b'\n');
+
+        // A request that could not be sent is retried once on a fresh connection;
+        // one that was sent is never repeated, since actions need not be idempotent
+        let receiver = match self.connection()?.send(id, &line) {
+            Ok(receiver) => receiver,
+            Err(error) if self.connector.is_some() => {
+                self.disconnect();
+                self.connection().map_err(|_| error)?.send(id, &line)?
+            }
+            Err(error) => return Err(error),
+        };
+
+        let response = receiver
+            .recv()
+            .map_err(|_| RpcError::new(TRANSPORT_ERROR, "Extension closed the connection"))?;
+        match response.error {
+            Some(error) => Err(error),
+            None => Ok(response.result.unwrap_or(Value::Null)),
+        }
+    }
+
+    // Returns the live connection, reconnecting if the previous one dropped
+    fn connection(&self) -> Result<Arc<Connection>, RpcError> {
+        let mut current = lock(&self.connection);
+        if let Some(connection) = current.as_ref().filter(|c| c.alive.load(Ordering::SeqCst)) {
+            return Ok(connection.clone());
+        }
+
+        let Some(connector) = &self.connector else {
+            return Err(RpcError::new(TRANSPORT_ERROR, "Extension closed the connection"));
+        };
+        let transport = connector()
+            .map_err(|e| RpcError::new(TRANSPORT_ERROR, format!("Failed to connect to extension: {}", e)))?;
+        let connection = Connection::open(transport);
+        *current = Some(connection.clone());
+        Ok(connection)
+    }
+
+    fn disconnect(&self) {
+        if let Some(connection) = lock(&self.connection).take() {
+            connection.shutdown();
+        }
+    }
+}
+
+/// A `CpiExtension` implemented by an extension in another process
+///
+/// The extension is either a child process spoken to over stdio ([`RemoteExtension::spawn`])
+/// or a daemon listening on a socket ([`RemoteExtension::connect`]). Calls may be made
+/// from several threads at once; each request carries its own id, so responses are
+/// matched up even when they arrive out of order.
+pub struct RemoteExtension {
+    client: Client,
+    child: Option<Child>,
+    name: String,
+    provider_type: String,
+}
+
+impl RemoteExtension {
+    /// Spawns `command` and talks to it over its stdin and stdout
+    pub fn spawn(mut command: Command) -> Result<Self, String> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start extension process {:?}: {}", command.get_program(), e))?;
+
+        let writer = child.stdin.take().ok_or("Extension process has no stdin")?;
+        let reader = child.stdout.take().ok_or("Extension process has no stdout")?;
+        let transport = Transport {
+            writer: Box::new(writer),
+            reader: Box::new(BufReader::new(reader)),
+            close: Box::new(|| {}),
+        };
+
+        match Self::new(transport, None) {
+            Ok(mut extension) => {
+                extension.child = Some(child);
+                Ok(extension)
+            }
+            Err(error) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(error)
+            }
+        }
+    }
+
+    /// Connects to an extension served with [`socket::RpcServer`], reconnecting
+    /// automatically if the connection drops
+    pub fn connect(endpoint: Endpoint) -> Result<Self, String> {
+        let connector: Connector = Box::new(move || endpoint.open());
+        let transport = connector().map_err(|e| format!("Failed to connect to extension: {}", e))?;
+        Self::new(transport, Some(connector))
+    }
+
+    /// Talks to an extension over an already established transport
+    pub fn from_transport(transport: Transport) -> Result<Self, String> {
+        Self::new(transport, None)
+    }
+
+    fn new(transport: Transport, connector: Option<Connector>) -> Result<Self, String> {
+        let mut extension = Self {
+            client: Client {
+                connection: Mutex::new(Some(Connection::open(transport))),
+                connector,
+                next_id: AtomicU64::new(0),
+            },
+            child: None,
+            name: String::new(),
+            provider_type: String::new(),
+        };
+        // name() and provider_type() hand out references, so fetch them once
+        extension.name = extension.call("name", Value::Null)?;
+        extension.provider_type = extension.call("provider_type", Value::Null)?;
+        Ok(extension)
+    }
+
+    fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, String> {
+        self.call_action(method, params).map_err(|e| e.message)
+    }
+
+    // Calls that report the extension's own errors
+    fn call_action<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, CpiError> {
+        let result = self.client.call(method, params).map_err(RpcError::into_cpi_error)?;
+        serde_json::from_value(result)
+            .map_err(|e| CpiError::internal(format!("Malformed '{}' result from extension: {}", method, e)))
+    }
+}
+
+impl Drop for RemoteExtension {
+    fn drop(&mut self) {
+        // Closing stdin (or the socket) asks the server loop to finish
+        self.client.disconnect();
+
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+impl CpiExtension for RemoteExtension {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn provider_type(&self) -> &str {
+        &self.provider_type
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        self.call("list_actions", Value::Null).unwrap_or_default()
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        self.call("get_action_definition", json!({ "action": action })).unwrap_or(None)
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        self.call_action("execute_action", json!({ "action": action, "params": params }))
+    }
+
+    fn execute_action_with_context(
+        &self,
+        action: &str,
+        params: &HashMap<String, Value>,
+        context: &ActionContext,
+    ) -> ActionResult {
+        self.call_action("execute_action", json!({ "action": action, "params": params, "context": context }))
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        self.call("default_settings", Value::Null).unwrap_or_default()
+    }
+
+    fn test_install(&self) -> ActionResult {
+        self.call_action("test_install", Value::Null)
+    }
+
+    fn version(&self) -> String {
+        self.call("version", Value::Null).unwrap_or_else(|_| "NONE".to_string())
+    }
+}
+
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,370 @@
+// File: lib_cpi/src/rpc/socket.rs
+//! Serving an extension as a long-lived daemon over TCP or a Unix domain socket
+//!
+//! The protocol is the same newline-delimited JSON-RPC used over stdio. Every
+//! connection may have several requests in flight; each is handled on its own
+//! thread and answered as soon as it finishes, tagged with the request's id.
+//!
+//! A server handles at most [`DEFAULT_MAX_REQUESTS`] requests at a time across all
+//! connections; further requests wait unread on their connection until one
+//! finishes. Connections beyond [`DEFAULT_MAX_CONNECTIONS`] are closed on accept.
+//! A request line longer than [`DEFAULT_MAX_LINE`] bytes is skipped and answered
+//! with an [`INVALID_REQUEST`] error.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::CpiExtension;
+use crate::slots::Slots;
+use super::{INVALID_REQUEST, Response, RpcError, Transport, handle_message, lock, write_response};
+
+/// Address an extension daemon listens on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// `host:port`, e.g. `127.0.0.1:7400`; port 0 picks a free port when binding
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    /// Parses `tcp://host:port` or `unix:///path/to/socket`
+    pub fn parse(endpoint: &str) -> Result<Self, String> {
+        if let Some(address) = endpoint.strip_prefix("tcp://") {
+            return Ok(Endpoint::Tcp(address.to_string()));
+        }
+        #[cfg(unix)]
+        if let Some(path) = endpoint.strip_prefix("unix://") {
+            return Ok(Endpoint::Unix(PathBuf::from(path)));
+        }
+        Err(format!("Unsupported endpoint '{}', expected tcp://host:port or unix:///path", endpoint))
+    }
+
+    pub(crate) fn open(&self) -> io::Result<Transport> {
+        match self {
+            Endpoint::Tcp(address) => tcp_transport(TcpStream::connect(address)?),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => unix_transport(UnixStream::connect(path)?),
+        }
+    }
+}
+
+fn tcp_transport(stream: TcpStream) -> io::Result<Transport> {
+    stream.set_nodelay(true)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let closer = stream.try_clone()?;
+    Ok(Transport {
+        writer: Box::new(stream),
+        reader: Box::new(reader),
+        close: Box::new(move || {
+            let _ = closer.shutdown(Shutdown::Both);
+        }),
+    })
+}
+
+#[cfg(unix)]
+fn unix_transport(stream: UnixStream) -> io::Result<Transport> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let closer = stream.try_clone()?;
+    Ok(Transport {
+        writer: Box::new(stream),
+        reader: Box::new(reader),
+        close: Box::new(move || {
+            let _ = closer.shutdown(Shutdown::Both);
+        }),
+    })
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "tcp://{}", address),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// How many requests a server handles at the same time unless configured otherwise
+pub const DEFAULT_MAX_REQUESTS: usize = 64;
+
+/// How many clients a server keeps connected unless configured otherwise
+pub const DEFAULT_MAX_CONNECTIONS: usize = 256;
+
+/// Longest request line a server reads unless configured otherwise, in bytes
+pub const DEFAULT_MAX_LINE: usize = 16 * 1024 * 1024;
+
+// How long the accept loop pauses after a failed accept, so that a lasting error
+// such as running out of file descriptors does not keep it spinning
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Exposes a `CpiExtension` to any number of controllers over a socket
+pub struct RpcServer {
+    listener: Listener,
+    endpoint: Endpoint,
+    extension: Arc<dyn CpiExtension>,
+    stopping: Arc<AtomicBool>,
+    connections: Arc<Connections>,
+    max_connections: usize,
+    max_line: usize,
+    requests: Arc<Slots>,
+}
+
+// Open client connections, so shutting down can close them
+#[derive(Default)]
+struct Connections {
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, Box<dyn Fn() + Send + Sync>>>,
+}
+
+impl Connections {
+    fn close_all(&self) {
+        for (_, close) in lock(&self.open).drain() {
+            close();
+        }
+    }
+}
+
+impl RpcServer {
+    /// Binds `endpoint`; a stale Unix socket file left by a previous run is replaced
+    pub fn bind(endpoint: &Endpoint, extension: Arc<dyn CpiExtension>) -> io::Result<Self> {
+        let (listener, endpoint) = match endpoint {
+            Endpoint::Tcp(address) => {
+                let listener = TcpListener::bind(address)?;
+                let local = listener.local_addr()?.to_string();
+                (Listener::Tcp(listener), Endpoint::Tcp(local))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                (Listener::Unix(UnixListener::bind(path)?), endpoint.clone())
+            }
+        };
+
+        Ok(Self {
+            listener,
+            endpoint,
+            extension,
+            stopping: Arc::default(),
+            connections: Arc::default(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            max_line: DEFAULT_MAX_LINE,
+            requests: Arc::new(Slots::new(DEFAULT_MAX_REQUESTS)),
+        })
+    }
+
+    /// Sets how many requests are handled at the same time, across all connections
+    pub fn with_max_requests(mut self, max_requests: usize) -> Self {
+        self.requests = Arc::new(Slots::new(max_requests));
+        self
+    }
+
+    /// Sets how many clients may be connected at the same time
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Sets the longest request line that is read, in bytes
+    pub fn with_max_line(mut self, max_line: usize) -> Self {
+        self.max_line = max_line;
+        self
+    }
+
+    /// The bound endpoint, with the actual port when binding to port 0
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Accepts connections until the server is shut down through a [`ServerHandle`]
+    pub fn run(self) -> io::Result<()> {
+        loop {
+            let transport = match &self.listener {
+                Listener::Tcp(listener) => listener.accept().and_then(|(stream, _)| tcp_transport(stream)),
+                #[cfg(unix)]
+                Listener::Unix(listener) => listener.accept().and_then(|(stream, _)| unix_transport(stream)),
+            };
+
+            if self.stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            // A failed accept only affects that one client
+            let Ok(transport) = transport else {
+                std::thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            };
+
+            // Registered before the connection thread starts, so a shutdown cannot miss it
+            let Transport { reader, writer, close } = transport;
+            let id = self.connections.next_id.fetch_add(1, Ordering::Relaxed);
+            {
+                let mut open = lock(&self.connections.open);
+                if open.len() >= self.max_connections {
+                    close();
+                    continue;
+                }
+                open.insert(id, close);
+            }
+
+            let extension = self.extension.clone();
+            let connections = self.connections.clone();
+            let requests = self.requests.clone();
+            let max_line = self.max_line;
+            std::thread::spawn(move || {
+                serve_connection(extension, &requests, max_line, reader, writer);
+                if let Some(close) = lock(&connections.open).remove(&id) {
+                    close();
+                }
+            });
+        }
+        self.connections.close_all();
+
+        #[cfg(unix)]
+        if let Endpoint::Unix(path) = &self.endpoint {
+            let _ = std::fs::remove_file(path);
+        }
+        Ok(())
+    }
+
+    /// Runs the server on a background thread
+    pub fn spawn(self) -> ServerHandle {
+        let endpoint = self.endpoint.clone();
+        let stopping = self.stopping.clone();
+        let thread = std::thread::spawn(move || self.run());
+        ServerHandle {
+            endpoint,
+            stopping,
+            thread: Some(thread),
+        }
+    }
+}
+
+// Reads requests from one client and answers each on its own thread, so a slow
+// action does not hold up the others multiplexed on the same connection
+fn serve_connection(
+    extension: Arc<dyn CpiExtension>,
+    requests: &Arc<Slots>,
+    max_line: usize,
+    mut reader: Box<dyn BufRead + Send>,
+    writer: Box<dyn Write + Send>,
+) {
+    let writer = Arc::new(Mutex::new(writer));
+    loop {
+        let line = match read_line(&mut reader, max_line) {
+            Ok(Line::Request(line)) => line,
+            Ok(Line::TooLong) => {
+                let error = RpcError::new(INVALID_REQUEST, format!("Request is longer than {} bytes", max_line));
+                let _ = write_response(&mut *lock(&writer), &Response::new(serde_json::Value::Null, Err(error)));
+                continue;
+            }
+            Ok(Line::End) | Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Stops reading from this client until a request anywhere finishes
+        let slot = requests.acquire();
+        let extension = extension.clone();
+        let writer = writer.clone();
+        std::thread::spawn(move || {
+            let _slot = slot;
+            let response = handle_message(extension.as_ref(), &line);
+            let _ = write_response(&mut *lock(&writer), &response);
+        });
+    }
+}
+
+enum Line {
+    Request(String),
+    TooLong,
+    End,
+}
+
+// Reads the next line without holding more than `max_line` bytes of it; the rest
+// of a longer line is read and dropped
+fn read_line(reader: &mut dyn BufRead, max_line: usize) -> io::Result<Line> {
+    let mut line = Vec::new();
+    // One byte over the limit is enough to tell the line is too long
+    if reader.take(max_line as u64 + 1).read_until(b'\n', &mut line)? == 0 {
+        return Ok(Line::End);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    } else if line.len() > max_line {
+        loop {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+            match buffer.iter().position(|&byte| byte == b'\n') {
+                Some(end) => {
+                    reader.consume(end + 1);
+                    break;
+                }
+                None => {
+                    let len = buffer.len();
+                    reader.consume(len);
+                }
+            }
+        }
+        return Ok(Line::TooLong);
+    }
+    Ok(Line::Request(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Handle to a server started with [`RpcServer::spawn`]; stops the server when dropped
+///
+/// Stopping closes every open connection; clients reconnect on their next call.
+pub struct ServerHandle {
+    endpoint: Endpoint,
+    stopping: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl ServerHandle {
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Stops accepting, closes open connections and waits for the accept loop to finish
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        self.stopping.store(true, Ordering::SeqCst);
+        // Wake the blocking accept with a throwaway connection
+        drop(self.endpoint.open());
+        thread.join().unwrap_or(Ok(()))
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
@@ -0,0 +1,59 @@
+// File: lib_cpi/src/slots.rs
+//! Counting semaphore bounding how many threads servers and executors start
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+// Counts the work in progress, making callers wait while all slots are taken
+pub(crate) struct Slots {
+    used: Mutex<usize>,
+    freed: Condvar,
+    limit: usize,
+}
+
+impl Slots {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+            limit: limit.max(1),
+        }
+    }
+
+    pub(crate) fn acquire(self: &Arc<Self>) -> Slot {
+        let mut used = self.used();
+        while *used >= self.limit {
+            used = self.freed.wait(used).unwrap_or_else(PoisonError::into_inner);
+        }
+        *used += 1;
+        Slot(self.clone())
+    }
+
+    /// Like `acquire`, but gives up after `timeout`
+    pub(crate) fn acquire_timeout(self: &Arc<Self>, timeout: Duration) -> Option<Slot> {
+        let used = self.used();
+        let (mut used, _) = self
+            .freed
+            .wait_timeout_while(used, timeout, |used| *used >= self.limit)
+            .unwrap_or_else(PoisonError::into_inner);
+        if *used >= self.limit {
+            return None;
+        }
+        *used += 1;
+        Some(Slot(self.clone()))
+    }
+
+    fn used(&self) -> MutexGuard<'_, usize> {
+        self.used.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Gives its slot back when dropped
+pub(crate) struct Slot(Arc<Slots>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *self.0.used() -= 1;
+        self.0.freed.notify_one();
+    }
+}
@@ -0,0 +1,99 @@
+//! Enforcing action definitions around any extension
+//!
+//! ```ignore
+//! let extension = Validated::new(registry.get("vbox").unwrap());
+//! extension.execute_action("create", &params)?; // checked against `create`'s definition first
+//! ```
+
+use std::collections::HashMap;
+use serde_json::Value;
+
+use crate::validation::{self, ValidationOptions};
+use crate::{ActionContext, ActionDefinition, ActionResult, CpiError, CpiExtension};
+
+/// A `CpiExtension` that validates every `execute_action` against the action's definition
+///
+/// Actions missing from `list_actions` are refused, and the parameters are checked
+/// and default-filled with [`validation::validate_against_with`] before the wrapped
+/// extension sees them. The actions and definition are asked for on every call,
+/// so the checks follow an extension that is reloaded.
+#[derive(Debug, Clone)]
+pub struct Validated<E> {
+    inner: E,
+    options: ValidationOptions,
+}
+
+impl<E: CpiExtension> Validated<E> {
+    pub fn new(inner: E) -> Self {
+        Self::with_options(inner, ValidationOptions::default())
+    }
+
+    /// Validates with `options`, e.g. to coerce string parameters
+    pub fn with_options(inner: E, options: ValidationOptions) -> Self {
+        Self { inner, options }
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    fn validate(&self, action: &str, params: &HashMap<String, Value>) -> Result<HashMap<String, Value>, CpiError> {
+        if !self.inner.list_actions().iter().any(|listed| listed == action) {
+            return Err(CpiError::unsupported(format!("Unknown action: {}", action)));
+        }
+        let definition = self
+            .inner
+            .get_action_definition(action)
+            .ok_or_else(|| CpiError::internal(format!("Action '{}' has no definition", action)))?;
+        Ok(validation::validate_against_with(&definition, params, self.options)?)
+    }
+}
+
+impl<E: CpiExtension> CpiExtension for Validated<E> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn provider_type(&self) -> &str {
+        self.inner.provider_type()
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        self.inner.list_actions()
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        self.inner.get_action_definition(action)
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        let params = self.validate(action, params)?;
+        self.inner.execute_action(action, &params)
+    }
+
+    fn execute_action_with_context(
+        &self,
+        action: &str,
+        params: &HashMap<String, Value>,
+        context: &ActionContext,
+    ) -> ActionResult {
+        let params = self.validate(action, params)?;
+        self.inner.execute_action_with_context(action, &params, context)
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        self.inner.default_settings()
+    }
+
+    fn test_install(&self) -> ActionResult {
+        self.inner.test_install()
+    }
+
+    fn version(&self) -> String {
+        self.inner.version()
+    }
+}
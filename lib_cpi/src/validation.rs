@@ -0,0 +1,507 @@
+//! Helper functions for parameter validation
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{ActionDefinition, ActionParameter, CpiError, ParamType};
+
+pub fn extract_string(params: &HashMap<String, Value>, name: &str) -> Result<String, CpiError> {
+    match params.get(name) {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(_) => Err(CpiError::invalid_params(format!("Parameter '{}' must be a string", name))),
+        None => Err(CpiError::invalid_params(format!("Required parameter '{}' not provided", name))),
+    }
+}
+
+pub fn extract_string_opt(params: &HashMap<String, Value>, name: &str) -> Result<Option<String>, CpiError> {
+    match params.get(name) {
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(CpiError::invalid_params(format!("Parameter '{}' must be a string", name))),
+        None => Ok(None),
+    }
+}
+
+pub fn extract_int(params: &HashMap<String, Value>, name: &str) -> Result<i64, CpiError> {
+    match params.get(name) {
+        Some(Value::Number(n)) if n.is_i64() => Ok(n.as_i64().unwrap()),
+        Some(_) => Err(CpiError::invalid_params(format!("Parameter '{}' must be an integer", name))),
+        None => Err(CpiError::invalid_params(format!("Required parameter '{}' not provided", name))),
+    }
+}
+
+pub fn extract_int_opt(params: &HashMap<String, Value>, name: &str) -> Result<Option<i64>, CpiError> {
+    match params.get(name) {
+        Some(Value::Number(n)) if n.is_i64() => Ok(Some(n.as_i64().unwrap())),
+        Some(_) => Err(CpiError::invalid_params(format!("Parameter '{}' must be an integer", name))),
+        None => Ok(None),
+    }
+}
+
+pub fn extract_float(params: &HashMap<String, Value>, name: &str) -> Result<f64, CpiError> {
+    match params.get(name) {
+        Some(Value::Number(n)) => Ok(n.as_f64().unwrap()),
+        Some(_) => Err(CpiError::invalid_params(format!("Parameter '{}' must be a number", name))),
+        None => Err(CpiError::invalid_params(format!("Required parameter '{}' not provided", name))),
+    }
+}
+
+pub fn extract_bool(params: &HashMap<String, Value>, name: &str) -> Result<bool, CpiError> {
+    match params.get(name) {
+        Some(Value::Bool(b)) => Ok(*b),
+        Some(_) => Err(CpiError::invalid_params(format!("Parameter '{}' must be a boolean", name))),
+        None => Err(CpiError::invalid_params(format!("Required parameter '{}' not provided", name))),
+    }
+}
+
+pub fn extract_json(params: &HashMap<String, Value>, name: &str) -> Result<Value, CpiError> {
+    match params.get(name) {
+        Some(v) => Ok(v.clone()),
+        None => Err(CpiError::invalid_params(format!("Required parameter '{}' not provided", name))),
+    }
+}
+
+/// Extracts a `Duration` parameter, given as a string such as `"1h30m"` or a number of seconds
+pub fn extract_duration(params: &HashMap<String, Value>, name: &str) -> Result<std::time::Duration, CpiError> {
+    match params.get(name) {
+        Some(Value::String(s)) => parse_duration(s).map_err(|e| CpiError::invalid_params(format!("Parameter '{}' {}", name, e))),
+        Some(Value::Number(n)) => n
+            .as_f64()
+            .and_then(|secs| std::time::Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(|| CpiError::invalid_params(format!("Parameter '{}' must be a non-negative number of seconds", name))),
+        Some(_) => Err(CpiError::invalid_params(format!("Parameter '{}' must be a duration", name))),
+        None => Err(CpiError::invalid_params(format!("Required parameter '{}' not provided", name))),
+    }
+}
+
+/// Extracts a `ByteSize` parameter, given as a string such as `"20GiB"` or a number of bytes
+pub fn extract_byte_size(params: &HashMap<String, Value>, name: &str) -> Result<u64, CpiError> {
+    match params.get(name) {
+        Some(Value::String(s)) => parse_byte_size(s).map_err(|e| CpiError::invalid_params(format!("Parameter '{}' {}", name, e))),
+        Some(Value::Number(n)) if n.is_u64() => Ok(n.as_u64().unwrap()),
+        Some(_) => Err(CpiError::invalid_params(format!("Parameter '{}' must be a size", name))),
+        None => Err(CpiError::invalid_params(format!("Required parameter '{}' not provided", name))),
+    }
+}
+
+pub use lib_cpi_common::parse_duration;
+
+/// Parses a size such as `"20GiB"`, `"512MB"` or `"1.5 TB"` into bytes
+///
+/// `KB`, `MB`, ... are powers of 1000 and `KiB`, `MiB`, ... powers of 1024; a
+/// bare number is a number of bytes.
+pub fn parse_byte_size(text: &str) -> Result<u64, String> {
+    let invalid = || format!("must be a size such as '20GiB' or '512MB', got '{}'", text);
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let amount: f64 = text[..split].parse().map_err(|_| invalid())?;
+    let multiplier: u64 = match text[split..].trim() {
+        "" | "B" => 1,
+        "KB" | "kB" => 1000,
+        "MB" => 1000_u64.pow(2),
+        "GB" => 1000_u64.pow(3),
+        "TB" => 1000_u64.pow(4),
+        "PB" => 1000_u64.pow(5),
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        "TiB" => 1 << 40,
+        "PiB" => 1 << 50,
+        _ => return Err(invalid()),
+    };
+    let bytes = amount * multiplier as f64;
+    if bytes.fract() != 0.0 || bytes > u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(bytes as u64)
+}
+
+/// Extracts a parameter and converts it to the type of an action argument
+///
+/// Used by the dispatchers the macros generate. A missing or `null` parameter
+/// falls back to `default`. Without one it is an error for `required` parameters,
+/// even when `T` accepts `null` like `Value` does, and `None` for `Option` arguments.
+pub fn extract_arg<T: serde::de::DeserializeOwned>(
+    params: &HashMap<String, Value>,
+    name: &str,
+    default: Option<Value>,
+    required: bool,
+) -> Result<T, CpiError> {
+    let not_provided = || CpiError::invalid_params(format!("Required parameter '{}' not provided", name));
+    let value = match params.get(name).filter(|v| !v.is_null()).cloned().or(default) {
+        Some(value) => value,
+        None if required => return Err(not_provided()),
+        None => return serde_json::from_value(Value::Null).map_err(|_| not_provided()),
+    };
+    serde_json::from_value(value).map_err(|e| CpiError::invalid_params(format!("Parameter '{}' is invalid: {}", name, e)))
+}
+
+/// `extract_arg` for `Secret` parameters, whose errors never include the value
+pub fn extract_secret_arg<T: serde::de::DeserializeOwned>(
+    params: &HashMap<String, Value>,
+    name: &str,
+    default: Option<Value>,
+    required: bool,
+) -> Result<T, CpiError> {
+    extract_arg(params, name, default, required).map_err(|error| match params.get(name).filter(|v| !v.is_null()) {
+        Some(_) => CpiError::invalid_params(format!("Parameter '{}' is invalid", name)),
+        None => error,
+    })
+}
+
+pub fn validate_params(
+    params: &HashMap<String, Value>, 
+    required: &[&str]
+) -> Result<(), CpiError> {
+    for &param in required {
+        if !params.contains_key(param) {
+            return Err(CpiError::invalid_params(format!("Required parameter '{}' not provided", param)));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the parameters that were given against the constraints of `definition`
+///
+/// Missing and `null` parameters are left to the action, as are values of the
+/// wrong type. Fields of `Object` parameters are checked as well, and reported
+/// by their path (`'disk.size'`). The first violation is returned.
+pub fn check_constraints(definition: &ActionDefinition, params: &HashMap<String, Value>) -> Result<(), CpiError> {
+    for param in &definition.parameters {
+        if let Some(value) = params.get(&param.name) {
+            check_param(param, &param.name, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks one value against the constraints of `param`, naming it `path` in errors
+pub fn check_param(param: &ActionParameter, path: &str, value: &Value) -> Result<(), CpiError> {
+    if let Some(violation) = constraint_violation(param, value) {
+        return Err(CpiError::invalid_params(format!("Parameter '{}' {}", path, violation)));
+    }
+    if let (ParamType::Object(fields), Value::Object(map)) = (&param.param_type, value) {
+        for field in fields {
+            if let Some(value) = map.get(&field.name) {
+                check_param(field, &format!("{}.{}", path, field.name), value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The first constraint of `param` itself that `value` violates, as "must be ..."
+fn constraint_violation(param: &ActionParameter, value: &Value) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+    let constraints = &param.constraints;
+
+    if let Some(allowed) = &constraints.allowed
+        && !allowed.contains(value)
+    {
+        let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+        return Some(format!("must be one of {}", allowed.join(", ")));
+    }
+
+    if let Some(number) = numeric_value(&param.param_type, value) {
+        if let Some(min) = constraints.min
+            && number < min
+        {
+            return Some(format!("must be at least {}", min));
+        }
+        if let Some(max) = constraints.max
+            && number > max
+        {
+            return Some(format!("must be at most {}", max));
+        }
+    }
+
+    if let Value::String(text) = value {
+        let length = text.chars().count();
+        if let Some(min_length) = constraints.min_length
+            && length < min_length
+        {
+            return Some(format!("must be at least {} characters long", min_length));
+        }
+        if let Some(max_length) = constraints.max_length
+            && length > max_length
+        {
+            return Some(format!("must be at most {} characters long", max_length));
+        }
+        if let Some(pattern) = &constraints.pattern {
+            match anchored_regex(pattern) {
+                Ok(regex) if regex.is_match(text) => {}
+                Ok(_) => return Some(format!("must match the pattern '{}'", pattern)),
+                Err(e) => return Some(format!("has an invalid pattern: {}", e)),
+            }
+        }
+    }
+
+    if let Value::Array(items) = value {
+        if let Some(min_items) = constraints.min_items
+            && items.len() < min_items
+        {
+            return Some(format!("must have at least {} items", min_items));
+        }
+        if let Some(max_items) = constraints.max_items
+            && items.len() > max_items
+        {
+            return Some(format!("must have at most {} items", max_items));
+        }
+    }
+
+    None
+}
+
+// Compiles `pattern` to match whole values, once per pattern for the life of the process
+fn anchored_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    static COMPILED: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    let mut compiled = COMPILED.get_or_init(Mutex::default).lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(regex) = compiled.get(pattern) {
+        return Ok(regex.clone());
+    }
+    let regex = Regex::new(&format!("^(?:{})$", pattern))?;
+    compiled.insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+// What `min` and `max` compare: the number itself, or the seconds of a duration and the bytes of a size
+fn numeric_value(param_type: &ParamType, value: &Value) -> Option<f64> {
+    match (param_type, value) {
+        (_, Value::Number(number)) => number.as_f64(),
+        (ParamType::Duration, Value::String(text)) => parse_duration(text).ok().map(|duration| duration.as_secs_f64()),
+        (ParamType::ByteSize, Value::String(text)) => parse_byte_size(text).ok().map(|bytes| bytes as f64),
+        _ => None,
+    }
+}
+
+/// How `validate_against_with` treats the parameters it is given
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidationOptions {
+    /// Converts strings into the declared type, for parameters that come from a
+    /// command line or environment variables: `"42"` into an `Integer`, `"true"`
+    /// into a `Boolean`, `"a,b"` or `"[\"a\", \"b\"]"` into an `Array`, JSON text
+    /// into an `Object`
+    pub coerce: bool,
+    /// Passes parameters the definition does not know through instead of reporting them
+    pub allow_unknown: bool,
+}
+
+/// One problem `validate_against` found, with the parameter it concerns
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationError {
+    /// Path of the parameter, such as `cpus`, `network.mtu` or `disks[1]`
+    pub parameter: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Everything `validate_against` found wrong with a set of parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<&str> = self.0.iter().map(|error| error.message.as_str()).collect();
+        f.write_str(&messages.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Validates `params` against `definition` and returns them normalized
+///
+/// Reports every missing required parameter, value of the wrong type, unknown
+/// parameter and constraint violation at once, also inside `Array` items and
+/// `Object` fields. Missing and `null` parameters with a `default_value` get
+/// their default.
+pub fn validate_against(
+    definition: &ActionDefinition,
+    params: &HashMap<String, Value>,
+) -> Result<HashMap<String, Value>, ValidationErrors> {
+    validate_against_with(definition, params, ValidationOptions::default())
+}
+
+/// `validate_against`, optionally coercing strings and allowing unknown parameters
+pub fn validate_against_with(
+    definition: &ActionDefinition,
+    params: &HashMap<String, Value>,
+    options: ValidationOptions,
+) -> Result<HashMap<String, Value>, ValidationErrors> {
+    let mut validator = Validator { options, errors: Vec::new() };
+    let map: serde_json::Map<String, Value> = params.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    let normalized = validator.fields(&definition.parameters, "", map);
+
+    match validator.errors.is_empty() {
+        true => Ok(normalized.into_iter().collect()),
+        false => Err(ValidationErrors(validator.errors)),
+    }
+}
+
+struct Validator {
+    options: ValidationOptions,
+    errors: Vec<ValidationError>,
+}
+
+impl Validator {
+    fn error(&mut self, path: &str, message: String) {
+        self.errors.push(ValidationError { parameter: path.to_string(), message });
+    }
+
+    /// The parameters of an action, or the fields of an object at `prefix`
+    fn fields(
+        &mut self,
+        fields: &[ActionParameter],
+        prefix: &str,
+        mut given: serde_json::Map<String, Value>,
+    ) -> serde_json::Map<String, Value> {
+        let mut normalized = serde_json::Map::new();
+        for field in fields {
+            let path = format!("{}{}", prefix, field.name);
+            match given.remove(&field.name) {
+                Some(value) if !value.is_null() => {
+                    let value = self.param(field, &path, value);
+                    normalized.insert(field.name.clone(), value);
+                }
+                value => match &field.default_value {
+                    Some(default) => {
+                        normalized.insert(field.name.clone(), default.clone());
+                    }
+                    None if field.required => self.error(&path, format!("Required parameter '{}' not provided", path)),
+                    None => {
+                        // An explicit null is kept, a missing parameter stays missing
+                        if let Some(null) = value {
+                            normalized.insert(field.name.clone(), null);
+                        }
+                    }
+                },
+            }
+        }
+
+        let mut unknown: Vec<(String, Value)> = given.into_iter().collect();
+        unknown.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, value) in unknown {
+            if self.options.allow_unknown {
+                normalized.insert(name, value);
+            } else {
+                let path = format!("{}{}", prefix, name);
+                self.error(&path, format!("Unknown parameter '{}'", path));
+            }
+        }
+        normalized
+    }
+
+    fn param(&mut self, param: &ActionParameter, path: &str, value: Value) -> Value {
+        let errors = self.errors.len();
+        let value = self.value(&param.param_type, path, value);
+        // Constraints only make sense for a value of the right type
+        if self.errors.len() == errors
+            && let Some(violation) = constraint_violation(param, &value)
+        {
+            self.error(path, format!("Parameter '{}' {}", path, violation));
+        }
+        value
+    }
+
+    /// Checks and coerces a value of type `param_type`
+    fn value(&mut self, param_type: &ParamType, path: &str, value: Value) -> Value {
+        let value = match (self.options.coerce, &value) {
+            (true, Value::String(text)) => coerce(param_type, text).unwrap_or(value),
+            _ => value,
+        };
+
+        let expected = match (param_type, &value) {
+            (ParamType::Any, _) => return value,
+            (ParamType::String | ParamType::Secret, Value::String(_)) => return value,
+            (ParamType::String | ParamType::Secret, _) => "a string",
+            (ParamType::Integer, Value::Number(n)) if n.is_i64() || n.is_u64() => return value,
+            (ParamType::Integer, _) => "an integer",
+            (ParamType::Number | ParamType::Float, Value::Number(_)) => return value,
+            (ParamType::Number | ParamType::Float, _) => "a number",
+            (ParamType::Boolean, Value::Bool(_)) => return value,
+            (ParamType::Boolean, _) => "a boolean",
+            (ParamType::Enum(values), Value::String(text)) if values.contains(text) => return value,
+            (ParamType::Enum(values), _) => {
+                let values: Vec<String> = values.iter().map(|v| format!("\"{}\"", v)).collect();
+                self.error(path, format!("Parameter '{}' must be one of {}", path, values.join(", ")));
+                return value;
+            }
+            (ParamType::Duration, Value::String(text)) => {
+                if let Err(e) = parse_duration(text) {
+                    self.error(path, format!("Parameter '{}' {}", path, e));
+                }
+                return value;
+            }
+            (ParamType::Duration, Value::Number(n)) if n.as_f64().is_some_and(|secs| secs >= 0.0) => return value,
+            (ParamType::Duration, _) => "a duration",
+            (ParamType::ByteSize, Value::String(text)) => {
+                if let Err(e) = parse_byte_size(text) {
+                    self.error(path, format!("Parameter '{}' {}", path, e));
+                }
+                return value;
+            }
+            (ParamType::ByteSize, Value::Number(n)) if n.is_u64() => return value,
+            (ParamType::ByteSize, _) => "a size",
+            (ParamType::Array(items), Value::Array(_)) => {
+                let Value::Array(values) = value else { unreachable!() };
+                let values = values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, item)| self.value(items, &format!("{}[{}]", path, i), item))
+                    .collect();
+                return Value::Array(values);
+            }
+            (ParamType::Array(_), _) => "an array",
+            // Without fields any object is accepted as it is
+            (ParamType::Object(fields), Value::Object(_)) if fields.is_empty() => return value,
+            (ParamType::Object(fields), Value::Object(_)) => {
+                let Value::Object(map) = value else { unreachable!() };
+                return Value::Object(self.fields(fields, &format!("{}.", path), map));
+            }
+            (ParamType::Object(_), _) => "an object",
+        };
+        self.error(path, format!("Parameter '{}' must be {}", path, expected));
+        value
+    }
+}
+
+// A string from a command line or environment variable as a value of `param_type`, if it reads as one
+fn coerce(param_type: &ParamType, text: &str) -> Option<Value> {
+    let trimmed = text.trim();
+    match param_type {
+        ParamType::Integer | ParamType::Number | ParamType::Float => match serde_json::from_str(trimmed) {
+            Ok(Value::Number(n)) => Some(Value::Number(n)),
+            _ => None,
+        },
+        ParamType::Boolean => match trimmed.to_ascii_lowercase().as_str() {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        ParamType::Array(items) => match serde_json::from_str(trimmed) {
+            Ok(Value::Array(values)) => Some(Value::Array(values)),
+            _ if trimmed.is_empty() => Some(Value::Array(Vec::new())),
+            _ => {
+                let values = trimmed.split(',').map(|item| {
+                    let item = item.trim();
+                    coerce(items, item).unwrap_or_else(|| Value::String(item.to_string()))
+                });
+                Some(Value::Array(values.collect()))
+            }
+        },
+        ParamType::Object(_) => match serde_json::from_str(trimmed) {
+            Ok(Value::Object(map)) => Some(Value::Object(map)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,290 @@
+// File: lib_cpi/src/wasm.rs
+//! Extensions compiled to WebAssembly
+//!
+//! A WASM extension speaks the same JSON protocol as a native library (see
+//! [`ffi`](crate::ffi)), through these exports of the module:
+//!
+//! | Export                              | Purpose                                             |
+//! |-------------------------------------|-----------------------------------------------------|
+//! | `memory`                            | Linear memory all buffers live in                   |
+//! | `cpi_abi_version() -> i32`          | The guest's [`ABI_VERSION`](crate::ffi::ABI_VERSION) |
+//! | `cpi_lib_version() -> i64`          | Packed pointer/length of the guest's lib_cpi version |
+//! | `cpi_alloc(len: i32) -> i32`        | Allocates an input buffer of `len` bytes            |
+//! | `cpi_free(ptr: i32, len: i32)`      | Releases a buffer from `cpi_alloc` or a call        |
+//! | `cpi_<method>(ptr: i32, len: i32) -> i64` | One per [`Method`], e.g. `cpi_execute_action` |
+//!
+//! Results are returned packed as `(ptr << 32) | len` and are freed by the host
+//! with `cpi_free` once read. `register_wasm_extension!` generates all of the
+//! exports for a `CpiExtension` built for `wasm32-unknown-unknown`.
+//!
+//! Loading modules requires the `wasm` feature. The guest runs in the interpreter
+//! with no imports at all, so it can reach nothing but its own memory.
+
+use crate::ffi::Method;
+
+/// Name of the export for a boundary method, e.g. `cpi_list_actions`
+pub fn export_name(method: Method) -> String {
+    format!("cpi_{}", method.as_str())
+}
+
+/// Packs a guest buffer into the `i64` returned by the exports
+pub fn pack(ptr: u32, len: u32) -> u64 {
+    ((ptr as u64) << 32) | len as u64
+}
+
+/// Splits a value produced by [`pack`] into pointer and length
+pub fn unpack(packed: u64) -> (u32, u32) {
+    ((packed >> 32) as u32, packed as u32)
+}
+
+/// Guest side glue called by the exports of `register_wasm_extension!`
+///
+/// Only built for `wasm32`, where guest pointers fit the packed results.
+#[cfg(target_arch = "wasm32")]
+pub mod guest {
+    use super::pack;
+    use crate::CpiExtension;
+    use crate::ffi::{Method, invoke};
+
+    /// Allocates a zeroed buffer of `len` bytes for the host to write into
+    pub fn alloc(len: u32) -> u32 {
+        let buffer = vec![0u8; len as usize].into_boxed_slice();
+        Box::into_raw(buffer) as *mut u8 as usize as u32
+    }
+
+    /// Releases a buffer from [`alloc`] or one returned by [`call`]
+    ///
+    /// # Safety
+    /// `ptr` and `len` must describe a buffer from this module that was not freed yet
+    pub unsafe fn free(ptr: u32, len: u32) {
+        drop(unsafe { take(ptr, len) });
+    }
+
+    /// Answers one call and returns the packed output buffer
+    ///
+    /// The input buffer is consumed.
+    ///
+    /// # Safety
+    /// `ptr` and `len` must describe a buffer from [`alloc`] that was not freed yet
+    pub unsafe fn call<E: CpiExtension + ?Sized>(extension: &E, method: Method, ptr: u32, len: u32) -> u64 {
+        let input = unsafe { take(ptr, len) };
+        let output = invoke(extension, method, &input).into_boxed_slice();
+        let len = output.len() as u32;
+        pack(Box::into_raw(output) as *mut u8 as usize as u32, len)
+    }
+
+    /// Packed pointer to a static string, which the host reads but never frees
+    pub fn static_str(value: &'static str) -> u64 {
+        pack(value.as_ptr() as usize as u32, value.len() as u32)
+    }
+
+    unsafe fn take(ptr: u32, len: u32) -> Box<[u8]> {
+        if len == 0 {
+            return Box::default();
+        }
+        let slice = std::ptr::slice_from_raw_parts_mut(ptr as usize as *mut u8, len as usize);
+        unsafe { Box::from_raw(slice) }
+    }
+}
+
+#[cfg(feature = "wasm")]
+pub use runtime::{WasmBoundary, WasmExtension, WasmRuntime};
+
+#[cfg(feature = "wasm")]
+mod runtime {
+    use std::path::Path;
+    use std::sync::{Mutex, PoisonError};
+
+    use wasmi::{Config, Engine, Linker, Memory, Module, Store, TypedFunc};
+
+    use super::{export_name, unpack};
+    use crate::ffi::{ABI_VERSION, ABI_VERSION_SYMBOL, JsonBoundary, JsonExtension, LIB_VERSION_SYMBOL, Method};
+
+    /// Loads WASM extensions, optionally bounding how much work each call may do
+    #[derive(Debug, Clone, Default)]
+    pub struct WasmRuntime {
+        fuel: Option<u64>,
+    }
+
+    impl WasmRuntime {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Limits every call to roughly `fuel` executed instructions; calls that run
+        /// out fail instead of hanging the host
+        pub fn with_fuel(mut self, fuel: u64) -> Self {
+            self.fuel = Some(fuel);
+            self
+        }
+
+        pub fn fuel(&self) -> Option<u64> {
+            self.fuel
+        }
+
+        /// Loads a `.wasm` file
+        pub fn load(&self, path: impl AsRef<Path>) -> Result<WasmExtension, String> {
+            let path = path.as_ref();
+            let bytes = std::fs::read(path)
+                .map_err(|e| format!("Failed to read WASM extension '{}': {}", path.display(), e))?;
+            self.load_bytes(&bytes)
+        }
+
+        /// Loads a module from its binary encoding
+        pub fn load_bytes(&self, bytes: &[u8]) -> Result<WasmExtension, String> {
+            let boundary = WasmBoundary::instantiate(bytes, self.fuel)?;
+            Ok(JsonExtension::new(boundary))
+        }
+    }
+
+    struct Instance {
+        store: Store<()>,
+        memory: Memory,
+        alloc: TypedFunc<u32, u32>,
+        free: TypedFunc<(u32, u32), ()>,
+        // Indexed like `Method::ALL`
+        methods: Vec<TypedFunc<(u32, u32), u64>>,
+    }
+
+    /// A WASM extension instance, driven through its exports
+    ///
+    /// The instance is single threaded: concurrent calls are served one at a time.
+    pub struct WasmBoundary {
+        instance: Mutex<Instance>,
+        fuel: Option<u64>,
+        lib_cpi_version: String,
+    }
+
+    impl WasmBoundary {
+        fn instantiate(bytes: &[u8], fuel: Option<u64>) -> Result<Self, String> {
+            let mut config = Config::default();
+            config.consume_fuel(fuel.is_some());
+            let engine = Engine::new(&config);
+            let module = Module::new(&engine, bytes).map_err(|e| format!("Invalid WASM module: {}", e))?;
+
+            let mut store = Store::new(&engine, ());
+            refuel(&mut store, fuel)?;
+            // No host functions are linked, so modules with imports are refused here
+            let instance = Linker::<()>::new(&engine)
+                .instantiate(&mut store, &module)
+                .and_then(|pre| pre.start(&mut store))
+                .map_err(|e| format!("Failed to instantiate WASM extension: {}", e))?;
+
+            let abi_version = instance
+                .get_typed_func::<(), u32>(&store, ABI_VERSION_SYMBOL)
+                .map_err(|e| format!("Not a lib_cpi extension, '{}' is missing: {}", ABI_VERSION_SYMBOL, e))?
+                .call(&mut store, ())
+                .map_err(|e| format!("'{}' failed: {}", ABI_VERSION_SYMBOL, e))?;
+            if abi_version != ABI_VERSION {
+                return Err(format!(
+                    "WASM extension was built against extension ABI {}, this host supports ABI {}",
+                    abi_version, ABI_VERSION
+                ));
+            }
+
+            let memory = instance
+                .get_memory(&store, "memory")
+                .ok_or_else(|| "WASM extension does not export its memory".to_string())?;
+            let alloc = instance
+                .get_typed_func(&store, "cpi_alloc")
+                .map_err(|e| format!("WASM extension export 'cpi_alloc' is missing or invalid: {}", e))?;
+            let free = instance
+                .get_typed_func(&store, "cpi_free")
+                .map_err(|e| format!("WASM extension export 'cpi_free' is missing or invalid: {}", e))?;
+            let methods = Method::ALL
+                .iter()
+                .map(|method| {
+                    let name = export_name(*method);
+                    instance
+                        .get_typed_func(&store, &name)
+                        .map_err(|e| format!("WASM extension export '{}' is missing or invalid: {}", name, e))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            // Optional: the version is only reported to hosts, never checked
+            let lib_version = instance.get_typed_func::<(), u64>(&store, LIB_VERSION_SYMBOL).ok();
+
+            let mut instance = Instance {
+                store,
+                memory,
+                alloc,
+                free,
+                methods,
+            };
+            let lib_cpi_version = match lib_version {
+                Some(function) => {
+                    let (ptr, len) = unpack(function.call(&mut instance.store, ()).map_err(trap(LIB_VERSION_SYMBOL))?);
+                    String::from_utf8_lossy(&instance.read(ptr, len)?).into_owned()
+                }
+                None => String::new(),
+            };
+
+            Ok(Self {
+                instance: Mutex::new(instance),
+                fuel,
+                lib_cpi_version,
+            })
+        }
+
+        /// Version of lib_cpi the module was built with, empty if it does not say
+        pub fn lib_cpi_version(&self) -> &str {
+            &self.lib_cpi_version
+        }
+    }
+
+    impl Instance {
+        fn call(&mut self, method: Method, input: &[u8], fuel: Option<u64>) -> Result<Vec<u8>, String> {
+            refuel(&mut self.store, fuel)?;
+            let len = u32::try_from(input.len()).map_err(|_| "Input too large for a WASM extension".to_string())?;
+            let ptr = self.alloc.call(&mut self.store, len).map_err(trap("cpi_alloc"))?;
+            self.memory
+                .write(&mut self.store, ptr as usize, input)
+                .map_err(|e| format!("Failed to write input into WASM memory: {}", e))?;
+
+            // The guest takes ownership of the input buffer
+            let function = self.methods[method as usize];
+            let packed = function
+                .call(&mut self.store, (ptr, len))
+                .map_err(trap(&export_name(method)))?;
+            let (ptr, len) = unpack(packed);
+            let output = self.read(ptr, len)?;
+            self.free.call(&mut self.store, (ptr, len)).map_err(trap("cpi_free"))?;
+            Ok(output)
+        }
+
+        fn read(&self, ptr: u32, len: u32) -> Result<Vec<u8>, String> {
+            // Bounds are checked before copying, since the guest picks the length
+            let (start, end) = (ptr as usize, ptr as usize + len as usize);
+            let data = self.memory.data(&self.store);
+            data.get(start..end).map(<[u8]>::to_vec).ok_or_else(|| {
+                format!(
+                    "WASM extension returned an out of bounds buffer: {} bytes at {} in {} bytes of memory",
+                    len,
+                    ptr,
+                    data.len()
+                )
+            })
+        }
+    }
+
+    impl JsonBoundary for WasmBoundary {
+        fn call(&self, method: Method, input: &[u8]) -> Result<Vec<u8>, String> {
+            let mut instance = self.instance.lock().unwrap_or_else(PoisonError::into_inner);
+            instance.call(method, input, self.fuel)
+        }
+    }
+
+    fn refuel(store: &mut Store<()>, fuel: Option<u64>) -> Result<(), String> {
+        match fuel {
+            Some(fuel) => store.set_fuel(fuel).map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    fn trap(export: &str) -> impl Fn(wasmi::Error) -> String + '_ {
+        move |e| format!("WASM extension trapped in '{}': {}", export, e)
+    }
+
+    /// Host-side adapter that exposes a WASM module as a `CpiExtension`
+    pub type WasmExtension = JsonExtension<WasmBoundary>;
+}
@@ -0,0 +1,238 @@
+//! Tests for async extensions and the adapters between the sync and async traits
+
+mod common;
+
+use lib_cpi::asynchronous::{BlockingPool, block_on};
+use lib_cpi::macros::{cpi_extension, register_actions, register_async_actions};
+use lib_cpi::{ActionDefinition, ActionResult, AsyncCpiExtension, Blocking, CpiExtension, ErrorKind, Offloaded};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::params;
+
+// Implements AsyncCpiExtension through the attribute, since it has async actions
+struct Cloud {
+    pool: BlockingPool,
+}
+
+#[cpi_extension(name = "cloud", provider_type = "iaas")]
+impl Cloud {
+    #[action(description = "Provisions a machine")]
+    async fn provision(&self, name: String, cpus: Option<u8>) -> ActionResult {
+        // Waits on another thread, as a call to a provider API would
+        let id = self.pool.spawn(move || format!("i-{}", name)).await.unwrap();
+        Ok(json!({ "id": id, "cpus": cpus.unwrap_or(1) }))
+    }
+
+    #[action(description = "Lists regions")]
+    fn regions() -> ActionResult {
+        Ok(json!(["eu", "us"]))
+    }
+
+    #[test_install]
+    async fn reachable(&self) -> ActionResult {
+        Ok(json!({"status": "reachable"}))
+    }
+}
+
+fn cloud() -> Cloud {
+    Cloud {
+        pool: BlockingPool::new(2),
+    }
+}
+
+// Dispatches to the same kind of actions from hand-written impls
+struct Mixed;
+
+#[cpi_extension]
+impl Mixed {
+    #[action]
+    async fn double(&self, value: i64) -> ActionResult {
+        Ok(json!(value * 2))
+    }
+
+    #[action]
+    fn negate(&self, value: i64) -> ActionResult {
+        Ok(json!(-value))
+    }
+}
+
+impl AsyncCpiExtension for Mixed {
+    fn name(&self) -> &str {
+        "mixed"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    register_async_actions![double, negate];
+}
+
+struct MixedSync;
+
+#[cpi_extension]
+impl MixedSync {
+    #[action]
+    async fn double(&self, value: i64) -> ActionResult {
+        Ok(json!(value * 2))
+    }
+}
+
+impl CpiExtension for MixedSync {
+    fn name(&self) -> &str {
+        "mixed_sync"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    register_actions![double];
+}
+
+// Mixes sync methods into an async extension
+#[derive(Default)]
+struct Inventory {
+    calls: AtomicUsize,
+}
+
+#[cpi_extension(name = "inventory", provider_type = "test")]
+impl Inventory {
+    #[action]
+    fn count(&self) -> ActionResult {
+        Ok(json!(self.calls.fetch_add(1, Ordering::SeqCst) + 1))
+    }
+
+    #[action]
+    async fn refresh(&self) -> ActionResult {
+        Ok(json!("refreshed"))
+    }
+
+    #[test_install]
+    fn check(&self) -> ActionResult {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Ok(json!({"status": "ok"}))
+    }
+}
+
+/// A sync extension counting its calls, which panics on `crash`
+#[derive(Default)]
+struct Counter {
+    calls: AtomicUsize,
+}
+
+impl CpiExtension for Counter {
+    fn name(&self) -> &str {
+        "counter"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        vec!["count".to_string(), "crash".to_string()]
+    }
+
+    fn get_action_definition(&self, _action: &str) -> Option<ActionDefinition> {
+        None
+    }
+
+    fn execute_action(&self, action: &str, _params: &HashMap<String, Value>) -> ActionResult {
+        match action {
+            "crash" => panic!("counter overflow"),
+            _ => Ok(json!(self.calls.fetch_add(1, Ordering::SeqCst) + 1)),
+        }
+    }
+}
+
+#[test]
+fn test_async_actions_from_the_attribute() {
+    let extension = cloud();
+    assert_eq!(AsyncCpiExtension::name(&extension), "cloud");
+    assert_eq!(AsyncCpiExtension::list_actions(&extension), vec!["provision", "regions"]);
+    let definition = AsyncCpiExtension::get_action_definition(&extension, "provision").unwrap();
+    assert_eq!(definition.parameters[0].name, "name");
+
+    let result = block_on(AsyncCpiExtension::execute_action(&extension, "provision", &params(json!({"name": "web"}))));
+    assert_eq!(result.unwrap(), json!({"id": "i-web", "cpus": 1}));
+    let result = block_on(AsyncCpiExtension::execute_action(&extension, "regions", &HashMap::new()));
+    assert_eq!(result.unwrap(), json!(["eu", "us"]));
+    assert_eq!(block_on(AsyncCpiExtension::test_install(&extension)).unwrap(), json!({"status": "reachable"}));
+
+    // Arguments are extracted inside the future and fail it the usual way
+    let error = block_on(AsyncCpiExtension::execute_action(&extension, "provision", &HashMap::new())).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidParams);
+    let error = block_on(AsyncCpiExtension::execute_action(&extension, "destroy", &HashMap::new())).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_registered_async_actions() {
+    let value = params(json!({"value": 21}));
+    assert_eq!(block_on(Mixed.execute_action("double", &value)).unwrap(), json!(42));
+    assert_eq!(block_on(Mixed.execute_action("negate", &value)).unwrap(), json!(-21));
+
+    // A sync impl waits for its async actions
+    assert_eq!(MixedSync.execute_action("double", &value).unwrap(), json!(42));
+}
+
+#[test]
+fn test_sync_methods_of_async_extensions_run_when_polled() {
+    let extension = Inventory::default();
+    let empty = HashMap::new();
+    let count = AsyncCpiExtension::execute_action(&extension, "count", &empty);
+    let check = AsyncCpiExtension::test_install(&extension);
+    assert_eq!(extension.calls.load(Ordering::SeqCst), 0);
+
+    assert_eq!(block_on(count).unwrap(), json!(1));
+    assert_eq!(block_on(check).unwrap(), json!({"status": "ok"}));
+    assert_eq!(extension.calls.load(Ordering::SeqCst), 2);
+    let refresh = AsyncCpiExtension::execute_action(&extension, "refresh", &empty);
+    assert_eq!(block_on(refresh).unwrap(), json!("refreshed"));
+}
+
+#[test]
+fn test_blocking_adapter() {
+    let extension: Box<dyn CpiExtension> = Box::new(Blocking::new(cloud()));
+    assert_eq!(extension.name(), "cloud");
+    let result = extension.execute_action("provision", &params(json!({"name": "db", "cpus": 4})));
+    assert_eq!(result.unwrap(), json!({"id": "i-db", "cpus": 4}));
+    assert_eq!(extension.test_install().unwrap(), json!({"status": "reachable"}));
+
+    // Futures can be handed to the executor the extension needs
+    let waited = Arc::new(AtomicUsize::new(0));
+    let counted = waited.clone();
+    let extension = Blocking::with_executor(cloud(), move |future| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        block_on(future)
+    });
+    extension.execute_action("regions", &HashMap::new()).unwrap();
+    assert_eq!(waited.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_offloaded_adapter() {
+    let extension = Offloaded::with_pool(Counter::default(), BlockingPool::new(4));
+    assert_eq!(extension.name(), "counter");
+
+    let empty = HashMap::new();
+    let calls: Vec<_> = (0..8).map(|_| extension.execute_action("count", &empty)).collect();
+    for call in calls {
+        block_on(call).unwrap();
+    }
+    assert_eq!(extension.inner().calls.load(Ordering::SeqCst), 8);
+    assert_eq!(block_on(extension.test_install()).unwrap(), json!({"status": "ok"}));
+
+    // A panic fails the call and leaves the pool running
+    let error = block_on(extension.execute_action("crash", &HashMap::new())).unwrap_err();
+    assert_eq!(error.message, "Action 'crash' panicked: counter overflow");
+    assert_eq!(block_on(extension.execute_action("count", &HashMap::new())).unwrap(), json!(9));
+
+    // The default pool serves adapters made with new()
+    let extension = Offloaded::new(Counter::default());
+    assert_eq!(block_on(extension.execute_action("count", &HashMap::new())).unwrap(), json!(1));
+}
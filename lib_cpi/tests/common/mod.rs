@@ -0,0 +1,111 @@
+//! Shared helpers for integration tests
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use serde_json::Value;
+use std::sync::{Mutex, OnceLock};
+
+/// Builds `fixtures/test_extension` into its own target directory (so it does not
+/// contend with the outer `cargo test` lock) and returns that directory
+fn build_fixture(version: Option<&str>) -> PathBuf {
+    static BUILT: OnceLock<Mutex<HashMap<Option<String>, PathBuf>>> = OnceLock::new();
+    let mut built = BUILT.get_or_init(Mutex::default).lock().unwrap();
+    if let Some(dir) = built.get(&version.map(str::to_string)) {
+        return dir.clone();
+    }
+
+    let workspace = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..");
+    let target_dir = match version {
+        Some(version) => workspace.join("target").join(format!("cpi-fixtures-{}", version)),
+        None => workspace.join("target").join("cpi-fixtures"),
+    };
+
+    let mut command = Command::new(env!("CARGO"));
+    command
+        .current_dir(&workspace)
+        .args(["build", "--quiet", "-p", "cpi_test_extension", "--target-dir"])
+        .arg(&target_dir)
+        .env_remove("CPI_FIXTURE_VERSION");
+    if let Some(version) = version {
+        command.env("CPI_FIXTURE_VERSION", version);
+    }
+    let status = command.status().expect("failed to run cargo for the fixture extension");
+    assert!(status.success(), "building the fixture extension failed");
+
+    let dir = target_dir.join("debug");
+    built.insert(version.map(str::to_string), dir.clone());
+    dir
+}
+
+fn library_in(dir: &Path) -> PathBuf {
+    dir.join(format!(
+        "{}cpi_test_extension{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ))
+}
+
+/// Path to the fixture extension shared library
+pub fn fixture_library() -> PathBuf {
+    library_in(&build_fixture(None))
+}
+
+/// Path to the fixture executable that serves the extension over stdio
+pub fn fixture_binary() -> PathBuf {
+    build_fixture(None).join(format!("cpi_test_extension_stdio{}", std::env::consts::EXE_SUFFIX))
+}
+
+/// Path to a build of the fixture extension whose `version()` reports `version`
+pub fn fixture_library_with_version(version: &str) -> PathBuf {
+    library_in(&build_fixture(Some(version)))
+}
+
+/// Path to the fixture extension compiled to WebAssembly
+///
+/// Needs the `wasm32-unknown-unknown` target (`rustup target add wasm32-unknown-unknown`),
+/// so the tests using it are ignored by default; run them with `cargo test -- --ignored`.
+pub fn fixture_wasm() -> PathBuf {
+    static BUILT: OnceLock<PathBuf> = OnceLock::new();
+    BUILT
+        .get_or_init(|| {
+            assert!(wasm_target_installed(), "the wasm32-unknown-unknown target is not installed");
+            let workspace = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..");
+            let target_dir = workspace.join("target").join("cpi-fixtures-wasm");
+            let status = Command::new(env!("CARGO"))
+                .current_dir(&workspace)
+                .args(["build", "--quiet", "-p", "cpi_test_extension", "--lib"])
+                .args(["--target", "wasm32-unknown-unknown", "--target-dir"])
+                .arg(&target_dir)
+                .env_remove("CPI_FIXTURE_VERSION")
+                .status()
+                .expect("failed to run cargo for the fixture extension");
+            assert!(status.success(), "building the fixture extension for wasm32-unknown-unknown failed");
+            target_dir.join("wasm32-unknown-unknown").join("debug").join("cpi_test_extension.wasm")
+        })
+        .clone()
+}
+
+fn wasm_target_installed() -> bool {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let Ok(output) = Command::new(rustc).args(["--print", "sysroot"]).output() else {
+        return false;
+    };
+    let sysroot = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Path::new(&sysroot).join("lib").join("rustlib").join("wasm32-unknown-unknown").is_dir()
+}
+
+/// Parameter map from a JSON object
+pub fn params(value: Value) -> HashMap<String, Value> {
+    serde_json::from_value(value).unwrap()
+}
+
+/// Creates an empty scratch directory unique to this process and `name`
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lib_cpi-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
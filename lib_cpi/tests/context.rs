@@ -0,0 +1,201 @@
+//! Tests for passing an `ActionContext` to actions
+
+mod common;
+
+use lib_cpi::context::{LogLevel, LogRecord};
+use lib_cpi::ffi::{self, ForeignExtension};
+use lib_cpi::macros::cpi_extension;
+use lib_cpi::{
+    ActionContext, ActionDefinition, ActionResult, AsyncCpiExtension, CancellationToken, CpiExtension, Principal, rpc,
+};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::params;
+
+/// Knows nothing of contexts, as extensions built before them
+struct Legacy;
+
+impl CpiExtension for Legacy {
+    fn name(&self) -> &str {
+        "legacy"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        vec!["echo".to_string()]
+    }
+
+    fn get_action_definition(&self, _action: &str) -> Option<ActionDefinition> {
+        None
+    }
+
+    fn execute_action(&self, _action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        Ok(json!(params))
+    }
+}
+
+struct Hypervisor;
+
+#[cpi_extension(name = "vbox", provider_type = "hypervisor")]
+impl Hypervisor {
+    #[action(description = "Reports what it knows about the call")]
+    fn create(&self, name: String, context: &ActionContext, memory: Option<u64>) -> ActionResult {
+        context.info(format!("creating {}", name));
+        Ok(json!({
+            "name": name,
+            "memory": memory,
+            "request_id": context.request_id,
+            "caller": context.principal.as_ref().map(|principal| principal.id.clone()),
+            "default_memory": context.settings.get("memory"),
+            "has_deadline": context.deadline.is_some(),
+        }))
+    }
+
+    #[action(description = "Has no use for the context")]
+    fn ping(&self) -> ActionResult {
+        Ok(json!("pong"))
+    }
+}
+
+struct Cloud;
+
+#[cpi_extension(name = "cloud", provider_type = "iaas")]
+impl Cloud {
+    #[action]
+    async fn whoami(&self, context: &lib_cpi::ActionContext) -> ActionResult {
+        Ok(json!(context.principal.as_ref().map(|principal| principal.id.clone())))
+    }
+}
+
+#[test]
+fn test_default_implementation_ignores_the_context() {
+    let context = ActionContext::new().with_principal(Principal::new("alice"));
+    let result = Legacy.execute_action_with_context("echo", &params(json!({"a": 1})), &context);
+    assert_eq!(result.unwrap(), json!({"a": 1}));
+}
+
+#[test]
+fn test_actions_receive_the_context() {
+    let logged: Arc<Mutex<Vec<LogRecord>>> = Arc::default();
+    let sink = logged.clone();
+    let mut settings = HashMap::new();
+    settings.insert("memory".to_string(), json!(2048));
+    let context = ActionContext::new()
+        .with_request_id("req-1")
+        .with_timeout(Duration::from_secs(60))
+        .with_principal(Principal::new("alice").with_role("admin"))
+        .with_settings(settings)
+        .with_span("vbox.create")
+        .with_logger(move |record| sink.lock().unwrap().push(record.clone()));
+
+    let result = Hypervisor.execute_action_with_context("create", &params(json!({"name": "web"})), &context);
+    assert_eq!(
+        result.unwrap(),
+        json!({
+            "name": "web",
+            "memory": null,
+            "request_id": "req-1",
+            "caller": "alice",
+            "default_memory": 2048,
+            "has_deadline": true,
+        })
+    );
+    assert_eq!(
+        logged.lock().unwrap()[0],
+        LogRecord {
+            level: LogLevel::Info,
+            request_id: "req-1".to_string(),
+            span: "vbox.create".to_string(),
+            message: "creating web".to_string(),
+        }
+    );
+
+    // The context is not a parameter
+    let definition = Hypervisor.get_action_definition("create").unwrap();
+    let names: Vec<_> = definition.parameters.iter().map(|param| param.name.as_str()).collect();
+    assert_eq!(names, vec!["name", "memory"]);
+
+    // Without a context actions are given a fresh one
+    let result = Hypervisor.execute_action("create", &params(json!({"name": "db", "memory": 512}))).unwrap();
+    assert_eq!(result["caller"], json!(null));
+    assert!(!result["request_id"].as_str().unwrap().is_empty());
+    assert_eq!(Hypervisor.execute_action_with_context("ping", &HashMap::new(), &context).unwrap(), json!("pong"));
+
+    let context = ActionContext::new().with_principal(Principal::new("bob"));
+    let result = lib_cpi::asynchronous::block_on(Cloud.execute_action_with_context("whoami", &HashMap::new(), &context));
+    assert_eq!(result.unwrap(), json!("bob"));
+}
+
+#[test]
+fn test_context_crosses_plugin_boundaries() {
+    let context = ActionContext::new()
+        .with_request_id("req-2")
+        .with_timeout(Duration::from_secs(60))
+        .with_principal(Principal::new("carol"));
+
+    let value = serde_json::to_value(&context).unwrap();
+    assert_eq!(value["request_id"], json!("req-2"));
+    assert!(value["timeout_ms"].as_u64().unwrap() > 59_000);
+    let decoded: ActionContext = serde_json::from_value(value).unwrap();
+    assert_eq!(decoded.principal, context.principal);
+    assert!(decoded.remaining().unwrap() > Duration::from_secs(59));
+
+    let foreign = unsafe { ForeignExtension::from_vtable(ffi::export(Hypervisor)) };
+    let result = foreign.execute_action_with_context("create", &params(json!({"name": "web"})), &context).unwrap();
+    assert_eq!((&result["request_id"], &result["caller"]), (&json!("req-2"), &json!("carol")));
+    assert_eq!(foreign.execute_action("create", &params(json!({"name": "web"}))).unwrap()["caller"], json!(null));
+
+    let line = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "execute_action",
+        "params": {"action": "create", "params": {"name": "web"}, "context": context},
+    });
+    let response = rpc::handle_message(&Hypervisor, &line.to_string());
+    assert_eq!(response.result.unwrap()["caller"], json!("carol"));
+}
+
+#[test]
+fn test_huge_timeouts_do_not_panic() {
+    let decoded: ActionContext = serde_json::from_value(json!({"request_id": "req-3", "timeout_ms": u64::MAX})).unwrap();
+    assert!(decoded.remaining().is_none_or(|remaining| remaining > Duration::from_secs(3600)));
+    assert!(ActionContext::new().with_timeout(Duration::MAX).deadline.is_none());
+
+    let line = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "execute_action",
+        "params": {
+            "action": "create",
+            "params": {"name": "web"},
+            "context": {"request_id": "req-3", "timeout_ms": u64::MAX},
+        },
+    });
+    let response = rpc::handle_message(&Hypervisor, &line.to_string());
+    assert_eq!(response.result.unwrap()["request_id"], json!("req-3"));
+}
+
+#[test]
+fn test_cancellation_and_deadlines() {
+    let token = CancellationToken::new();
+    let context = ActionContext::new().with_cancellation(token.clone());
+    assert!(!context.is_cancelled());
+    assert!(!token.wait_timeout(Duration::from_millis(10)));
+
+    let waiter = thread::spawn(move || context.cancellation.wait_timeout(Duration::from_secs(30)));
+    token.cancel();
+    assert!(waiter.join().unwrap());
+
+    let context = ActionContext::new().with_deadline(Instant::now());
+    assert!(context.is_expired());
+    assert_eq!(context.remaining(), Some(Duration::ZERO));
+    assert!(!ActionContext::new().is_expired());
+    assert_ne!(ActionContext::new().request_id, ActionContext::new().request_id);
+}
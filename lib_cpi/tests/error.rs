@@ -0,0 +1,74 @@
+//! Tests for structured extension errors
+
+use lib_cpi::rpc::{self, RpcError};
+use lib_cpi::validation::{ValidationError, ValidationErrors};
+use lib_cpi::{CpiError, ErrorKind};
+use serde_json::json;
+
+#[test]
+fn test_kinds_and_retryability() {
+    assert!(CpiError::timeout("took too long").retryable);
+    assert!(CpiError::unavailable("VBoxManage is busy").retryable);
+    assert!(!CpiError::not_found("no such VM").retryable);
+    assert!(!CpiError::internal("crashed").with_retryable(false).retryable);
+    assert!(CpiError::internal("lock held").with_retryable(true).retryable);
+
+    let error = CpiError::already_exists("VM 'web' exists");
+    assert_eq!(error.kind, ErrorKind::AlreadyExists);
+    assert_eq!(error.to_string(), "VM 'web' exists");
+    assert_eq!(ErrorKind::Unauthorized.to_string(), "Unauthorized");
+}
+
+#[test]
+fn test_serialization_round_trip() {
+    let error = CpiError::not_found("VM 'web' not found").with_details(json!({"vm": "web"}));
+    let value = serde_json::to_value(&error).unwrap();
+    assert_eq!(
+        value,
+        json!({"kind": "NotFound", "message": "VM 'web' not found", "retryable": false, "details": {"vm": "web"}})
+    );
+    assert_eq!(serde_json::from_value::<CpiError>(value).unwrap(), error);
+
+    // Retryability falls back to the kind's when it is left out
+    let error: CpiError = serde_json::from_value(json!({"kind": "Timeout", "message": "slow"})).unwrap();
+    assert_eq!(error, CpiError::timeout("slow"));
+
+    // Kinds from newer extensions are still errors, just less specific ones
+    let value = json!({"kind": "QuotaExceeded", "message": "no more VMs", "retryable": true});
+    let error: CpiError = serde_json::from_value(value).unwrap();
+    assert_eq!(error.kind, ErrorKind::Internal);
+    assert_eq!(error.message, "no more VMs");
+    assert!(error.retryable);
+}
+
+#[test]
+fn test_legacy_string_errors() {
+    // Older extensions report bare strings
+    let error: CpiError = serde_json::from_value(json!("VBoxManage not found")).unwrap();
+    assert_eq!(error, CpiError::internal("VBoxManage not found"));
+
+    let error: CpiError = "VBoxManage not found".to_string().into();
+    assert_eq!(error.kind, ErrorKind::Internal);
+    assert!(!error.retryable);
+}
+
+#[test]
+fn test_validation_errors_become_invalid_params() {
+    let errors = ValidationErrors(vec![ValidationError {
+        parameter: "disk".to_string(),
+        message: "Required parameter 'disk' not provided".to_string(),
+    }]);
+    let error = CpiError::from(errors);
+    assert_eq!(error.kind, ErrorKind::InvalidParams);
+    assert_eq!(error.message, "Required parameter 'disk' not provided");
+    assert_eq!(error.details.unwrap()[0]["parameter"], json!("disk"));
+}
+
+#[test]
+fn test_rpc_errors_carry_the_kind() {
+    let error = CpiError::unauthorized("bad token").with_details(json!({"realm": "api"}));
+    assert_eq!(RpcError::action_failed(error.clone()).into_cpi_error(), error);
+
+    let transport = RpcError::new(rpc::TRANSPORT_ERROR, "connection reset");
+    assert_eq!(transport.into_cpi_error().kind, ErrorKind::Unavailable);
+}
@@ -0,0 +1,209 @@
+//! Tests for action timeouts and cancellation through `ActionExecutor`
+
+mod common;
+
+use lib_cpi::macros::cpi_extension;
+use lib_cpi::{ActionContext, ActionDefinition, ActionExecutor, ActionResult, CancellationToken, CpiExtension, ErrorKind};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::params;
+
+/// Tells the test when its slow action starts and stops, and holds its stubborn
+/// action until the test lets it go
+struct Hypervisor {
+    events: Sender<&'static str>,
+    gate: Mutex<Receiver<()>>,
+}
+
+#[cpi_extension(name = "vbox", provider_type = "hypervisor")]
+impl Hypervisor {
+    #[action(description = "Clones a machine, one disk at a time", timeout = "200ms")]
+    fn clone_vm(&self, context: &ActionContext, disks: Option<u64>) -> ActionResult {
+        self.events.send("started").ok();
+        for disk in 0..disks.unwrap_or(1000) {
+            if let Err(error) = context.checkpoint() {
+                self.events.send("stopped").ok();
+                return Err(error);
+            }
+            context.cancellation.wait_timeout(Duration::from_millis(10));
+            context.debug(format!("copied disk {}", disk));
+        }
+        Ok(json!("cloned"))
+    }
+
+    #[action(description = "Never looks at its context")]
+    fn stubborn(&self) -> ActionResult {
+        self.gate.lock().unwrap().recv_timeout(Duration::from_secs(30)).ok();
+        Ok(json!("done"))
+    }
+
+    #[action(description = "Stops itself early, which is not a timeout")]
+    fn give_up(&self, context: &ActionContext) -> ActionResult {
+        context.cancellation.cancel();
+        // Winds down at its checkpoint like any cancelled action, but with a result of its own
+        match context.checkpoint() {
+            Ok(()) => Ok(json!("carried on")),
+            Err(_) => Ok(json!("gave up")),
+        }
+    }
+
+    #[action(timeout = "1h30m")]
+    fn export(&self) -> ActionResult {
+        Ok(json!("exported"))
+    }
+}
+
+/// The extension, what it reports, and the gate its stubborn action waits on
+fn hypervisor() -> (Hypervisor, Receiver<&'static str>, Sender<()>) {
+    let (events, reported) = mpsc::channel();
+    let (gate, waiting) = mpsc::channel();
+    (Hypervisor { events, gate: Mutex::new(waiting) }, reported, gate)
+}
+
+/// Waits a few seconds at most for the extension to report `event`
+fn wait_for(events: &Receiver<&'static str>, event: &str) -> bool {
+    while let Ok(reported) = events.recv_timeout(Duration::from_secs(5)) {
+        if reported == event {
+            return true;
+        }
+    }
+    false
+}
+
+#[test]
+fn test_declared_timeouts() {
+    let (vbox, _, _) = hypervisor();
+    let definition = vbox.get_action_definition("clone_vm").unwrap();
+    assert_eq!(definition.timeout, Some(Duration::from_millis(200)));
+    let value = serde_json::to_value(&definition).unwrap();
+    assert_eq!(value["timeout_ms"], json!(200));
+    let decoded: ActionDefinition = serde_json::from_value(value).unwrap();
+    assert_eq!(decoded, definition);
+
+    // Definitions without a timeout serialize as before
+    let value = serde_json::to_value(vbox.get_action_definition("stubborn").unwrap()).unwrap();
+    assert!(value.get("timeout_ms").is_none());
+
+    let executor = ActionExecutor::new(vbox).with_default_timeout(Duration::from_secs(5));
+    assert_eq!(executor.timeout_for("export"), Some(Duration::from_secs(5400)));
+    assert_eq!(executor.timeout_for("stubborn"), Some(Duration::from_secs(5)));
+    assert_eq!(ActionExecutor::new(hypervisor().0).timeout_for("stubborn"), None);
+}
+
+#[test]
+fn test_timeout_signals_the_action() {
+    let (vbox, events, _gate) = hypervisor();
+    let executor = ActionExecutor::new(vbox);
+    assert_eq!(executor.execute_action("clone_vm", &params(json!({"disks": 2}))).unwrap(), json!("cloned"));
+
+    let started = Instant::now();
+    let error = executor.execute_action("clone_vm", &HashMap::new()).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Timeout);
+    assert!(error.retryable);
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // The action stops at its next checkpoint
+    assert!(wait_for(&events, "stopped"));
+
+    // An action that ignores the token still does not hold the caller
+    let executor = executor.with_default_timeout(Duration::from_millis(50));
+    let error = executor.execute_action("stubborn", &HashMap::new()).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Timeout);
+    assert_eq!(error.details.unwrap(), json!({"action": "stubborn", "timeout_ms": 50}));
+}
+
+#[test]
+fn test_calls_override_the_timeout() {
+    let (vbox, _events, _gate) = hypervisor();
+    let executor = ActionExecutor::new(vbox);
+
+    // A longer deadline lets the action run past its declared timeout
+    let context = ActionContext::new().with_timeout(Duration::from_secs(30));
+    let result = executor.execute_action_with_context("clone_vm", &params(json!({"disks": 30})), &context);
+    assert_eq!(result.unwrap(), json!("cloned"));
+
+    let context = ActionContext::new().with_timeout(Duration::from_millis(20));
+    let result = executor.execute_action_with_context("export", &HashMap::new(), &context);
+    assert_eq!(result.unwrap(), json!("exported"));
+    let error = executor.execute_action_with_context("stubborn", &HashMap::new(), &context).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Timeout);
+
+    // A timeout too long to represent means no deadline
+    let executor = ActionExecutor::new(hypervisor().0).with_default_timeout(Duration::MAX);
+    assert_eq!(executor.execute_action("export", &HashMap::new()).unwrap(), json!("exported"));
+}
+
+#[test]
+fn test_cancellation() {
+    let (vbox, events, _gate) = hypervisor();
+    let executor = ActionExecutor::new(vbox);
+    let token = CancellationToken::new();
+    let context = ActionContext::new()
+        .with_timeout(Duration::from_secs(30))
+        .with_cancellation(token.clone());
+    // Cancels once the action is under way
+    let canceller = thread::spawn(move || {
+        assert!(wait_for(&events, "started"));
+        token.cancel();
+        events
+    });
+
+    let error = executor.execute_action_with_context("clone_vm", &HashMap::new(), &context).unwrap_err();
+    let events = canceller.join().unwrap();
+    assert_eq!(error.kind, ErrorKind::Cancelled);
+    assert!(!error.retryable);
+    assert!(wait_for(&events, "stopped"));
+
+    // Child tokens follow their parent, but not the other way round
+    let parent = CancellationToken::new();
+    let child = parent.child();
+    child.cancel();
+    assert!(!parent.is_cancelled());
+    let other = parent.child();
+    parent.cancel();
+    assert!(other.is_cancelled());
+    assert!(parent.child().is_cancelled());
+
+    // An action cancelling its own token is not reported as timed out or cancelled
+    assert_eq!(executor.execute_action("give_up", &HashMap::new()).unwrap(), json!("gave up"));
+    let context = ActionContext::new().with_timeout(Duration::from_secs(30));
+    let result = executor.execute_action_with_context("give_up", &HashMap::new(), &context);
+    assert_eq!(result.unwrap(), json!("gave up"));
+
+    // Actions run without the executor can check for themselves
+    let context = ActionContext::new().with_deadline(Instant::now());
+    assert_eq!(context.checkpoint().unwrap_err().kind, ErrorKind::Timeout);
+    assert!(ActionContext::new().checkpoint().is_ok());
+}
+
+#[test]
+fn test_running_actions_are_capped() {
+    let (vbox, _events, gate) = hypervisor();
+    let executor = ActionExecutor::new(vbox).with_max_running(1);
+    let context = ActionContext::new().with_timeout(Duration::from_millis(20));
+    let error = executor.execute_action_with_context("stubborn", &HashMap::new(), &context).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Timeout);
+
+    // The abandoned action keeps its thread, so the next call cannot start
+    let context = ActionContext::new().with_timeout(Duration::from_millis(20));
+    let error = executor.execute_action_with_context("export", &HashMap::new(), &context).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Timeout);
+    let token = CancellationToken::new();
+    token.cancel();
+    let context = ActionContext::new().with_cancellation(token);
+    let error = executor.execute_action_with_context("export", &HashMap::new(), &context).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Cancelled);
+
+    // Once it returns, waiting calls go ahead
+    gate.send(()).unwrap();
+    assert_eq!(executor.execute_action("export", &HashMap::new()).unwrap(), json!("exported"));
+
+    // Action names become no thread names, so any name is safe
+    let error = executor.execute_action("bad\0name", &HashMap::new()).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Unsupported);
+}
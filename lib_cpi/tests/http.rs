@@ -0,0 +1,130 @@
+//! Tests for the HTTP gateway; run with `--features http`
+
+use lib_cpi::http::HttpGateway;
+use lib_cpi::{ActionDefinition, ActionResult, CpiError, CpiExtension, ParamType, param, validation};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+
+struct Greeter;
+
+impl CpiExtension for Greeter {
+    fn name(&self) -> &str {
+        "greeter"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        vec!["greet".to_string()]
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        match action {
+            "greet" => Some(ActionDefinition::new(
+                "greet",
+                "Greets someone",
+                vec![param!("name", "Who to greet", ParamType::String, required)],
+            )),
+            _ => None,
+        }
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        match action {
+            "greet" => Ok(json!(format!("Hello, {}!", validation::extract_string(params, "name")?))),
+            _ => Err(CpiError::unsupported(format!("Unknown action: {}", action))),
+        }
+    }
+
+    fn default_settings(&self) -> HashMap<String, Value> {
+        let mut settings = HashMap::new();
+        settings.insert("language".to_string(), json!("en"));
+        settings
+    }
+}
+
+fn request(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn test_http_gateway_routes() {
+    let gateway = HttpGateway::bind("127.0.0.1:0", Arc::new(Greeter)).unwrap();
+    let address = gateway.local_addr().unwrap();
+    let handle = gateway.spawn();
+
+    assert_eq!(request(address, "GET", "/actions", ""), (200, json!(["greet"])));
+
+    let (status, definition) = request(address, "GET", "/actions/greet", "");
+    assert_eq!(status, 200);
+    assert_eq!(definition["parameters"][0]["name"], json!("name"));
+
+    assert_eq!(
+        request(address, "POST", "/actions/greet", r#"{"name": "World"}"#),
+        (200, json!("Hello, World!"))
+    );
+
+    let (status, error) = request(address, "POST", "/actions/greet", "");
+    assert_eq!(status, 400);
+    assert_eq!(error["error"], json!("Required parameter 'name' not provided"));
+    assert_eq!(error["kind"], json!("InvalidParams"));
+    assert_eq!(error["retryable"], json!(false));
+
+    assert_eq!(request(address, "POST", "/actions/greet", "[1, 2]").0, 400);
+    assert_eq!(request(address, "POST", "/actions/missing", "{}").0, 404);
+    assert_eq!(request(address, "GET", "/actions/missing", "").0, 404);
+    assert_eq!(request(address, "DELETE", "/actions/greet", "").0, 405);
+    assert_eq!(request(address, "GET", "/nothing", "").0, 404);
+
+    // Path segments are percent-decoded before dispatch
+    assert_eq!(request(address, "GET", "/actions/gr%65et", "").0, 200);
+    assert_eq!(request(address, "GET", "/actions/gr%6", "").0, 400);
+    assert_eq!(request(address, "GET", "/actions/%FF", "").0, 400);
+
+    let oversized = format!(r#"{{"name": "{}"}}"#, "x".repeat(lib_cpi::http::MAX_BODY as usize));
+    let (status, error) = request(address, "POST", "/actions/greet", &oversized);
+    assert_eq!(status, 413);
+    assert!(error["error"].as_str().unwrap().contains("larger than"));
+
+    assert_eq!(request(address, "GET", "/settings", ""), (200, json!({"language": "en"})));
+    assert_eq!(request(address, "GET", "/health", ""), (200, json!({"status": "ok"})));
+
+    handle.shutdown();
+}
+
+#[test]
+fn test_http_gateway_limits_requests() {
+    let gateway = HttpGateway::bind("127.0.0.1:0", Arc::new(Greeter)).unwrap().with_max_requests(1);
+    let address = gateway.local_addr().unwrap();
+    let handle = gateway.spawn();
+
+    // Concurrent clients are served one at a time
+    let clients: Vec<_> = (0..4)
+        .map(|i| std::thread::spawn(move || request(address, "POST", "/actions/greet", &format!(r#"{{"name": "{}"}}"#, i))))
+        .collect();
+    for (i, client) in clients.into_iter().enumerate() {
+        assert_eq!(client.join().unwrap(), (200, json!(format!("Hello, {}!", i))));
+    }
+
+    handle.shutdown();
+}
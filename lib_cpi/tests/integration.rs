@@ -3,7 +3,7 @@
 //! This file tests the lib_cpi functionality without relying on the #[action] macro
 
 use lib_cpi::{
-    ActionParameter, ActionDefinition, ActionResult, CpiExtension, ParamType,
+    ActionDefinition, ActionResult, CpiError, CpiExtension, ErrorKind, ParamType,
     param, response, validation
 };
 use serde_json::{json, Value};
@@ -51,6 +51,7 @@ impl MockExtension {
             "message": "Complex data returned"
         });
         
+        #[allow(clippy::collapsible_if)]
         if include_details {
             if let Value::Object(ref mut obj) = result {
                 obj.insert("details".to_string(), json!({
@@ -70,7 +71,7 @@ impl MockExtension {
     
     fn test_error(&self, should_fail: bool) -> ActionResult {
         if should_fail {
-            Err("This action failed intentionally".to_string())
+            Err("This action failed intentionally".into())
         } else {
             Ok(json!({
                 "success": true,
@@ -101,38 +102,38 @@ impl CpiExtension for MockExtension {
     
     fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
         match action {
-            "test_install" => Some(ActionDefinition {
-                name: "test_install".to_string(),
-                description: "Test if the extension is properly installed".to_string(),
-                parameters: vec![],
-            }),
-            "test_no_params" => Some(ActionDefinition {
-                name: "test_no_params".to_string(),
-                description: "Test action with no parameters".to_string(),
-                parameters: vec![],
-            }),
-            "test_with_params" => Some(ActionDefinition {
-                name: "test_with_params".to_string(),
-                description: "Test action with string and integer parameters".to_string(),
-                parameters: vec![
+            "test_install" => Some(ActionDefinition::new(
+                "test_install",
+                "Test if the extension is properly installed",
+                vec![],
+            )),
+            "test_no_params" => Some(ActionDefinition::new(
+                "test_no_params",
+                "Test action with no parameters",
+                vec![],
+            )),
+            "test_with_params" => Some(ActionDefinition::new(
+                "test_with_params",
+                "Test action with string and integer parameters",
+                vec![
                     param!("name", "Name parameter", ParamType::String, required),
                     param!("count", "Count parameter", ParamType::Number, required),
                 ],
-            }),
-            "test_complex_return" => Some(ActionDefinition {
-                name: "test_complex_return".to_string(),
-                description: "Test action with complex return value".to_string(),
-                parameters: vec![
+            )),
+            "test_complex_return" => Some(ActionDefinition::new(
+                "test_complex_return",
+                "Test action with complex return value",
+                vec![
                     param!("include_details", "Include detailed information", ParamType::Boolean, optional, json!(false)),
                 ],
-            }),
-            "test_error" => Some(ActionDefinition {
-                name: "test_error".to_string(),
-                description: "Test action that returns an error".to_string(),
-                parameters: vec![
+            )),
+            "test_error" => Some(ActionDefinition::new(
+                "test_error",
+                "Test action that returns an error",
+                vec![
                     param!("should_fail", "Should the action fail?", ParamType::Boolean, required),
                 ],
-            }),
+            )),
             _ => None,
         }
     }
@@ -156,7 +157,7 @@ impl CpiExtension for MockExtension {
                 let should_fail = validation::extract_bool(params, "should_fail")?;
                 self.test_error(should_fail)
             },
-            _ => Err(format!("Unknown action: {}", action)),
+            _ => Err(CpiError::unsupported(format!("Unknown action: {}", action))),
         }
     }
     
@@ -239,7 +240,8 @@ mod tests {
         params.insert("should_fail".to_string(), json!(true));
         
         let error = extension.execute_action("test_error", &params).unwrap_err();
-        assert_eq!(error, "This action failed intentionally");
+        assert_eq!(error.message, "This action failed intentionally");
+        assert_eq!(error.kind, ErrorKind::Internal);
         
         // Test with should_fail = false
         let mut params = HashMap::new();
@@ -250,6 +252,7 @@ mod tests {
     }
     
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_parameter_validation() {
         // Test string extraction
         let mut params = HashMap::new();
@@ -276,11 +279,12 @@ mod tests {
         
         // Test missing required parameter
         let err = validation::extract_string(&params, "missing_param").unwrap_err();
-        assert!(err.contains("not provided"));
+        assert!(err.message.contains("not provided"));
+        assert_eq!(err.kind, ErrorKind::InvalidParams);
         
         // Test type mismatch
         let err = validation::extract_string(&params, "int_param").unwrap_err();
-        assert!(err.contains("must be a string"));
+        assert!(err.message.contains("must be a string"));
     }
     
     #[test]
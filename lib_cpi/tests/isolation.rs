@@ -0,0 +1,96 @@
+//! Tests for catching panics in extensions
+
+use lib_cpi::ffi::{self, ForeignExtension};
+use lib_cpi::isolation::{self, Isolated};
+use lib_cpi::{ActionDefinition, ActionResult, CpiExtension, ErrorKind, rpc};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+/// Panics in `explode` and in `list_actions`
+struct Fragile;
+
+impl CpiExtension for Fragile {
+    fn name(&self) -> &str {
+        "fragile"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        panic!("no list today");
+    }
+
+    fn get_action_definition(&self, _action: &str) -> Option<ActionDefinition> {
+        None
+    }
+
+    fn execute_action(&self, action: &str, _params: &HashMap<String, Value>) -> ActionResult {
+        match action {
+            "explode" => panic!("boom in {}", action),
+            _ => Ok(json!("fine")),
+        }
+    }
+}
+
+#[test]
+fn test_panics_become_internal_errors() {
+    let extension = Isolated::new(Fragile);
+
+    let error = extension.execute_action("explode", &HashMap::new()).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Internal);
+    assert_eq!(error.message, "Action 'explode' panicked: boom in explode");
+    assert_eq!(error.details, Some(json!({"action": "explode", "panic": "boom in explode"})));
+    assert!(isolation::is_panic(&error));
+    assert!(!isolation::is_panic(&"plain failure".into()));
+
+    // Methods that cannot fail answer their defaults
+    assert!(extension.list_actions().is_empty());
+    assert_eq!(extension.execute_action("calm", &HashMap::new()).unwrap(), json!("fine"));
+    assert_eq!(extension.panics(), 0);
+}
+
+#[test]
+fn test_repeated_panics_make_the_extension_unhealthy() {
+    let extension = Isolated::new(Fragile).with_max_panics(2);
+
+    extension.execute_action("explode", &HashMap::new()).unwrap_err();
+    assert!(extension.is_healthy());
+    extension.execute_action("explode", &HashMap::new()).unwrap_err();
+    assert!(!extension.is_healthy());
+
+    let error = extension.execute_action("calm", &HashMap::new()).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Unavailable);
+    assert!(!error.retryable);
+    assert_eq!(error.message, "Extension 'fragile' is unhealthy after 2 consecutive panics");
+
+    extension.reset();
+    assert_eq!(extension.execute_action("calm", &HashMap::new()).unwrap(), json!("fine"));
+}
+
+#[test]
+fn test_panics_do_not_cross_the_vtable() {
+    let foreign = unsafe { ForeignExtension::from_vtable(ffi::export(Fragile)) };
+    let extension = Isolated::new(foreign).with_max_panics(1);
+
+    assert!(extension.inner().list_actions().is_empty());
+    let error = extension.execute_action("explode", &HashMap::new()).unwrap_err();
+    assert_eq!(error.message, "Action 'explode' panicked: boom in explode");
+
+    // Panics caught inside the library count towards the host's health check
+    assert!(!extension.is_healthy());
+
+    // A panicking constructor yields a table the loader refuses, not an unwind
+    assert!(!ffi::export_with(|| Fragile).is_failed());
+    let vtable = ffi::export_with(|| -> Fragile { panic!("no config") });
+    assert!(vtable.is_failed());
+}
+
+#[test]
+fn test_panics_are_reported_over_rpc() {
+    let line = r#"{"jsonrpc": "2.0", "id": 1, "method": "execute_action", "params": {"action": "explode", "params": {}}}"#;
+    let response = rpc::handle_message(&Fragile, line);
+    let error = response.error.unwrap().into_cpi_error();
+    assert!(isolation::is_panic(&error), "{:?}", error);
+}
@@ -0,0 +1,99 @@
+//! Tests for loading extensions from shared libraries
+
+mod common;
+
+use lib_cpi::{CpiError, CpiExtension, ExtensionLoader, ffi};
+use lib_cpi::ffi::ForeignExtension;
+use serde_json::json;
+use std::collections::HashMap;
+
+#[test]
+fn test_load_fixture_extension() {
+    let extension = ExtensionLoader::new().load(common::fixture_library()).unwrap();
+
+    assert_eq!(extension.name(), "test_extension");
+    assert_eq!(extension.provider_type(), "test");
+    assert_eq!(extension.version(), "0.1.0");
+    assert_eq!(extension.path(), common::fixture_library());
+    assert_eq!(extension.list_actions(), vec!["echo", "add", "hold"]);
+    assert_eq!(extension.default_settings()["greeting"], json!("hello"));
+
+    let definition = extension.get_action_definition("add").unwrap();
+    assert_eq!(definition.parameters.len(), 2);
+
+    let mut params = HashMap::new();
+    params.insert("a".to_string(), json!(2));
+    params.insert("b".to_string(), json!(40));
+    let result = extension.execute_action("add", &params).unwrap();
+    assert_eq!(result["sum"], json!(42));
+
+    let error = extension.execute_action("missing", &params).unwrap_err();
+    assert_eq!(error, CpiError::unsupported("Unknown action: missing"));
+
+    extension.unload().unwrap();
+}
+
+#[test]
+fn test_load_missing_library() {
+    let error = ExtensionLoader::new().load("/nonexistent/libnothing.so").unwrap_err();
+    assert!(error.contains("Failed to load extension library"));
+}
+
+struct Counter;
+
+impl CpiExtension for Counter {
+    fn name(&self) -> &str {
+        "counter"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        vec!["count".to_string()]
+    }
+
+    fn get_action_definition(&self, _action: &str) -> Option<lib_cpi::ActionDefinition> {
+        None
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, serde_json::Value>) -> lib_cpi::ActionResult {
+        match action {
+            "count" => Ok(json!(params.len())),
+            _ => Err(CpiError::unsupported(format!("Unknown action: {}", action))),
+        }
+    }
+}
+
+#[test]
+fn test_vtable_round_trip() {
+    let extension = unsafe { ForeignExtension::from_vtable(ffi::export(Counter)) };
+
+    assert_eq!(extension.name(), "counter");
+    assert_eq!(extension.provider_type(), "test");
+    assert_eq!(extension.list_actions(), vec!["count"]);
+    assert!(extension.get_action_definition("count").is_none());
+    assert_eq!(extension.version(), "NONE");
+    assert_eq!(extension.test_install().unwrap(), json!({"status": "ok"}));
+
+    let mut params = HashMap::new();
+    params.insert("a".to_string(), json!(1));
+    params.insert("b".to_string(), json!(2));
+    assert_eq!(extension.execute_action("count", &params).unwrap(), json!(2));
+    assert_eq!(extension.execute_action("other", &params).unwrap_err(), CpiError::unsupported("Unknown action: other"));
+}
+
+#[test]
+fn test_abi_handshake() {
+    let extension = ExtensionLoader::new().load(common::fixture_library()).unwrap();
+    assert_eq!(extension.lib_cpi_version(), ffi::LIB_CPI_VERSION);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_abi_handshake_refuses_foreign_library() {
+    // A library that was not built with register_extension! is refused before any call
+    let error = ExtensionLoader::new().load("libc.so.6").unwrap_err();
+    assert!(error.contains(ffi::ABI_VERSION_SYMBOL), "{}", error);
+}
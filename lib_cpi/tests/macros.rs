@@ -0,0 +1,447 @@
+//! Tests for extensions written with the `#[action]` family of macros
+
+mod common;
+
+use lib_cpi::macros::{action, cpi_extension, param, register_actions};
+use lib_cpi::{ActionResult, Constraints, CpiError, CpiExtension, ErrorKind, ParamType};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+use common::params;
+
+#[derive(Deserialize)]
+struct Disk {
+    size_gb: u64,
+}
+
+struct Greeter {
+    punctuation: String,
+}
+
+impl Greeter {
+    fn new() -> Self {
+        Self {
+            punctuation: "!".to_string(),
+        }
+    }
+
+    #[action(description = "Greets someone")]
+    #[param(name = "name", description = "Who to greet")]
+    #[param(name = "times", description = "How often", default = "1")]
+    #[lib_cpi::macros::generate_metadata]
+    fn greet(&self, name: &str, times: i64) -> ActionResult {
+        let greeting = format!("Hello, {}{}", name, self.punctuation);
+        Ok(json!(vec![greeting; times as usize]))
+    }
+
+    // #[param] above #[action] works as well
+    #[param(name = "values", description = "Numbers to add")]
+    #[action(description = "Sums numbers")]
+    fn sum(&self, values: &[u32], offset: Option<u32>) -> ActionResult {
+        Ok(json!(values.iter().sum::<u32>() + offset.unwrap_or(0)))
+    }
+
+    #[action(description = "Creates a machine")]
+    #[param(name = "memory", description = "Memory in MB", type = "Number", required = true)]
+    #[allow(clippy::too_many_arguments)]
+    fn create(
+        &self,
+        name: String,
+        cpus: u8,
+        memory: Option<f64>,
+        load: f32,
+        headless: bool,
+        tags: Vec<String>,
+        labels: Option<&[String]>,
+        disk: Disk,
+    ) -> ActionResult {
+        Ok(json!({
+            "name": name,
+            "cpus": cpus,
+            "memory": memory,
+            "load": load,
+            "headless": headless,
+            "tags": tags,
+            "labels": labels,
+            "disk": disk.size_gb,
+        }))
+    }
+
+    #[action(description = "Takes no parameters")]
+    fn ping() -> ActionResult {
+        Ok(json!("pong"))
+    }
+}
+
+impl CpiExtension for Greeter {
+    fn name(&self) -> &str {
+        "greeter"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    register_actions![greet, sum, create, ping];
+}
+
+// Declared with the impl-block attribute, with an action named like one of Greeter's
+struct Shouter;
+
+#[cpi_extension]
+impl Shouter {
+    #[action(description = "Greets loudly")]
+    #[param(name = "name", description = "Who to shout at", default = "EVERYONE")]
+    fn greet(&self, name: String) -> ActionResult {
+        Ok(json!(format!("HELLO, {}!", name.to_uppercase())))
+    }
+
+    #[action]
+    fn whisper(&self, text: Option<String>) -> ActionResult {
+        Ok(json!(text.unwrap_or_default().to_lowercase()))
+    }
+
+    // Not an action
+    fn helper(&self) -> &'static str {
+        "helper"
+    }
+}
+
+impl CpiExtension for Shouter {
+    fn name(&self) -> &str {
+        self.helper()
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    register_actions![greet, whisper];
+}
+
+// Implements CpiExtension entirely through the attribute
+struct Hypervisor;
+
+#[cpi_extension(name = "vbox", provider_type = "hypervisor", version = env!("CARGO_PKG_VERSION"))]
+impl Hypervisor {
+    #[default_settings]
+    const SETTINGS: &str = r#"{"memory": 1024, "headless": true}"#;
+
+    #[action(description = "Starts a machine")]
+    fn start(&self, name: String, headless: Option<bool>) -> ActionResult {
+        Ok(json!({ "started": name, "headless": headless.unwrap_or(true) }))
+    }
+
+    #[action(description = "Applies a machine spec as given")]
+    fn apply(&self, spec: Value) -> ActionResult {
+        Ok(spec)
+    }
+
+    #[test_install]
+    fn check(&self) -> ActionResult {
+        Err(CpiError::unavailable("VBoxManage not found"))
+    }
+}
+
+#[derive(Serialize)]
+struct Quota {
+    region: &'static str,
+    replicas: u32,
+}
+
+struct Storage;
+
+#[cpi_extension(name = "storage", provider_type = "storage")]
+impl Storage {
+    #[default_settings]
+    const SETTINGS: Quota = Quota {
+        region: "eu-west",
+        replicas: 3,
+    };
+
+    #[action]
+    fn size(&self) -> ActionResult {
+        Ok(json!(0))
+    }
+
+    #[action(description = "Provisions a volume")]
+    #[param(name = "ratio", default = 0.75)]
+    #[param(name = "offset", default = -2)]
+    #[param(name = "zones", default = r#"["a", "b"]"#)]
+    #[param(name = "options", default = r#"{"encrypted": true}"#)]
+    #[param(name = "replicas", default = Self::SETTINGS.replicas * 2)]
+    #[param(name = "label", default = None::<String>)]
+    fn provision(
+        &self,
+        ratio: f64,
+        offset: i32,
+        zones: Vec<String>,
+        options: HashMap<String, Value>,
+        replicas: u32,
+        label: Option<String>,
+    ) -> ActionResult {
+        Ok(json!({
+            "ratio": ratio,
+            "offset": offset,
+            "zones": zones,
+            "options": options,
+            "replicas": replicas,
+            "label": label,
+        }))
+    }
+
+    #[action(description = "Attaches a disk")]
+    #[param(name = "format", values = ["qcow2", "raw"], default = "qcow2")]
+    #[param(name = "password", type = "Secret")]
+    #[param(name = "timeout", type = "Duration", default = "30s")]
+    #[param(name = "size", type = "ByteSize", default = "20GiB")]
+    fn attach(&self, format: String, password: u32, timeout: String, size: String) -> ActionResult {
+        Ok(json!([format, password, timeout, size]))
+    }
+
+    #[action(description = "Resizes a volume")]
+    #[param(name = "name", pattern = "[a-z][a-z0-9-]*", max_length = 16)]
+    #[param(name = "size", type = "ByteSize", min = 1073741824, max = 1099511627776)]
+    #[param(name = "replicas", min = 1, max = 5, default = 1)]
+    #[param(name = "zones", min_items = 1, max_items = 3)]
+    #[param(name = "tier", allowed = ["hot", "cold"])]
+    fn resize(&self, name: String, size: String, replicas: u8, zones: Vec<String>, tier: Option<String>) -> ActionResult {
+        Ok(json!({ "name": name, "size": size, "replicas": replicas, "zones": zones, "tier": tier }))
+    }
+}
+
+#[test]
+fn test_generated_metadata() {
+    let greeter = Greeter::new();
+    assert_eq!(greeter.list_actions(), vec!["greet", "sum", "create", "ping"]);
+
+    let definition = greeter.get_action_definition("greet").unwrap();
+    assert_eq!(definition.description, "Greets someone");
+    assert_eq!(definition.parameters[0].name, "name");
+    assert!(definition.parameters[0].required);
+    assert_eq!(definition.parameters[1].name, "times");
+    assert_eq!(definition.parameters[1].param_type, ParamType::Integer);
+    assert!(!definition.parameters[1].required);
+    assert_eq!(definition.parameters[1].default_value, Some(json!(1)));
+
+    assert!(greeter.get_action_definition("ping").unwrap().parameters.is_empty());
+    assert!(greeter.get_action_definition("missing").is_none());
+}
+
+#[test]
+fn test_impl_block_attribute() {
+    let shouter = Shouter;
+    assert_eq!(shouter.list_actions(), vec!["greet", "whisper"]);
+
+    // Metadata is scoped to the type, not shared with Greeter::greet
+    let definition = shouter.get_action_definition("greet").unwrap();
+    assert_eq!(definition.description, "Greets loudly");
+    assert_eq!(definition.parameters.len(), 1);
+    assert_eq!(definition.parameters[0].description, "Who to shout at");
+    assert!(!definition.parameters[0].required);
+    assert_eq!(Greeter::new().get_action_definition("greet").unwrap().parameters.len(), 2);
+
+    assert_eq!(shouter.get_action_definition("whisper").unwrap().description, "Action whisper");
+
+    assert_eq!(shouter.execute_action("greet", &HashMap::new()).unwrap(), json!("HELLO, EVERYONE!"));
+    let result = shouter.execute_action("whisper", &params(json!({"text": "QUIET"}))).unwrap();
+    assert_eq!(result, json!("quiet"));
+}
+
+#[test]
+fn test_attribute_implements_extension() {
+    let hypervisor = Hypervisor;
+    assert_eq!(hypervisor.name(), "vbox");
+    assert_eq!(hypervisor.provider_type(), "hypervisor");
+    assert_eq!(hypervisor.version(), env!("CARGO_PKG_VERSION"));
+    assert_eq!(hypervisor.list_actions(), vec!["start", "apply"]);
+    assert_eq!(hypervisor.get_action_definition("start").unwrap().parameters.len(), 2);
+
+    let result = hypervisor.execute_action("start", &params(json!({"name": "vm1"}))).unwrap();
+    assert_eq!(result, json!({"started": "vm1", "headless": true}));
+    assert_eq!(hypervisor.execute_action("stop", &HashMap::new()).unwrap_err(), CpiError::unsupported("Unknown action: stop"));
+
+    let settings = hypervisor.default_settings();
+    assert_eq!(settings["memory"], json!(1024));
+    assert_eq!(settings["headless"], json!(true));
+    assert_eq!(hypervisor.test_install().unwrap_err().kind, ErrorKind::Unavailable);
+
+    // Defaults of the trait apply to what is not annotated
+    let storage = Storage;
+    assert_eq!(storage.version(), "NONE");
+    assert_eq!(storage.test_install().unwrap(), json!({"status": "ok"}));
+    assert_eq!(storage.default_settings()["region"], json!("eu-west"));
+    assert_eq!(storage.default_settings()["replicas"], json!(3));
+}
+
+#[test]
+fn test_typed_defaults() {
+    let definition = Storage.get_action_definition("provision").unwrap();
+    let defaults: Vec<(&str, Option<Value>, bool)> = definition
+        .parameters
+        .iter()
+        .map(|p| (p.name.as_str(), p.default_value.clone(), p.required))
+        .collect();
+
+    assert_eq!(
+        defaults,
+        vec![
+            ("ratio", Some(json!(0.75)), false),
+            ("offset", Some(json!(-2)), false),
+            ("zones", Some(json!(["a", "b"])), false),
+            ("options", Some(json!({"encrypted": true})), false),
+            ("replicas", Some(json!(6)), false),
+            ("label", Some(Value::Null), false),
+        ]
+    );
+
+    // The action is called with exactly the defaults its metadata reports
+    let result = Storage.execute_action("provision", &HashMap::new()).unwrap();
+    assert_eq!(
+        result,
+        json!({
+            "ratio": 0.75, "offset": -2, "zones": ["a", "b"],
+            "options": {"encrypted": true}, "replicas": 6, "label": null,
+        })
+    );
+}
+
+#[test]
+fn test_declared_parameter_types() {
+    let definition = Storage.get_action_definition("attach").unwrap();
+    let types: Vec<ParamType> = definition.parameters.iter().map(|p| p.param_type.clone()).collect();
+    assert_eq!(
+        types,
+        vec![ParamType::one_of(["qcow2", "raw"]), ParamType::Secret, ParamType::Duration, ParamType::ByteSize]
+    );
+    assert_eq!(definition.parameters[0].default_value, Some(json!("qcow2")));
+    assert_eq!(definition.parameters[3].default_value, Some(json!("20GiB")));
+
+    // The value of a secret is not echoed back in errors
+    let error = Storage.execute_action("attach", &params(json!({"password": "hunter2"}))).unwrap_err();
+    assert_eq!(error, CpiError::invalid_params("Parameter 'password' is invalid"));
+    let error = Storage.execute_action("attach", &HashMap::new()).unwrap_err();
+    assert_eq!(error.message, "Required parameter 'password' not provided");
+}
+
+#[test]
+fn test_declared_constraints() {
+    let definition = Storage.get_action_definition("resize").unwrap();
+    let name = &definition.parameters[0].constraints;
+    assert_eq!(name.pattern.as_deref(), Some("[a-z][a-z0-9-]*"));
+    assert_eq!(name.max_length, Some(16));
+    assert_eq!(definition.parameters[1].constraints, Constraints::default().min(1 << 30).max(1_099_511_627_776.0));
+    assert_eq!(definition.parameters[4].constraints.allowed, Some(vec![json!("hot"), json!("cold")]));
+    assert!(Storage.get_action_definition("attach").unwrap().parameters[0].constraints.is_empty());
+
+    let valid = json!({"name": "data-1", "size": "20GiB", "zones": ["a"], "tier": "hot"});
+    let result = Storage.execute_action("resize", &params(valid.clone())).unwrap();
+    assert_eq!(result["replicas"], json!(1));
+
+    // Constraints are checked before the action runs
+    let cases = [
+        (json!({"name": "Data"}), "Parameter 'name' must match the pattern '[a-z][a-z0-9-]*'"),
+        (json!({"name": "a-very-long-volume-name"}), "Parameter 'name' must be at most 16 characters long"),
+        (json!({"size": "512MiB"}), "Parameter 'size' must be at least 1073741824"),
+        (json!({"size": 2_000_000_000_000_u64}), "Parameter 'size' must be at most 1099511627776"),
+        (json!({"replicas": 0}), "Parameter 'replicas' must be at least 1"),
+        (json!({"zones": []}), "Parameter 'zones' must have at least 1 items"),
+        (json!({"zones": ["a", "b", "c", "d"]}), "Parameter 'zones' must have at most 3 items"),
+        (json!({"tier": "warm"}), "Parameter 'tier' must be one of \"hot\", \"cold\""),
+    ];
+    for (change, expected) in cases {
+        let mut invalid = valid.clone();
+        invalid.as_object_mut().unwrap().extend(change.as_object().unwrap().clone());
+        assert_eq!(Storage.execute_action("resize", &params(invalid)).unwrap_err().message, expected);
+    }
+}
+
+#[test]
+fn test_parameter_types_are_inferred() {
+    let definition = Greeter::new().get_action_definition("create").unwrap();
+    let inferred: Vec<(&str, ParamType, bool)> = definition
+        .parameters
+        .iter()
+        .map(|p| (p.name.as_str(), p.param_type.clone(), p.required))
+        .collect();
+
+    assert_eq!(
+        inferred,
+        vec![
+            ("name", ParamType::String, true),
+            ("cpus", ParamType::Integer, true),
+            // Explicit #[param] settings win over the signature
+            ("memory", ParamType::Number, true),
+            ("load", ParamType::Float, true),
+            ("headless", ParamType::Boolean, true),
+            ("tags", ParamType::array(ParamType::String), true),
+            ("labels", ParamType::array(ParamType::String), false),
+            ("disk", ParamType::Object(Vec::new()), true),
+        ]
+    );
+
+    let sum = Greeter::new().get_action_definition("sum").unwrap();
+    assert_eq!(sum.parameters[0].param_type, ParamType::array(ParamType::Integer));
+    assert_eq!(sum.parameters[0].description, "Numbers to add");
+    assert_eq!(sum.parameters[1].param_type, ParamType::Integer);
+    assert!(!sum.parameters[1].required);
+}
+
+#[test]
+fn test_generated_dispatch() {
+    let greeter = Greeter::new();
+
+    let result = greeter.execute_action("greet", &params(json!({"name": "World", "times": 2}))).unwrap();
+    assert_eq!(result, json!(["Hello, World!", "Hello, World!"]));
+
+    // Defaults apply to missing parameters
+    let result = greeter.execute_action("greet", &params(json!({"name": "World"}))).unwrap();
+    assert_eq!(result, json!(["Hello, World!"]));
+
+    // Option arguments may be left out, slices are extracted from arrays
+    assert_eq!(greeter.execute_action("sum", &params(json!({"values": [1, 2, 3]}))).unwrap(), json!(6));
+    let result = greeter.execute_action("sum", &params(json!({"values": [1, 2], "offset": 10})));
+    assert_eq!(result.unwrap(), json!(13));
+
+    assert_eq!(greeter.execute_action("ping", &HashMap::new()).unwrap(), json!("pong"));
+
+    let machine = json!({
+        "name": "vm1", "cpus": 2, "memory": 512, "load": 0.5, "headless": true,
+        "tags": ["a"], "labels": ["x", "y"], "disk": {"size_gb": 20},
+    });
+    let result = greeter.execute_action("create", &params(machine)).unwrap();
+    assert_eq!(result["memory"], json!(512.0));
+    assert_eq!(result["labels"], json!(["x", "y"]));
+    assert_eq!(result["disk"], json!(20));
+}
+
+#[test]
+fn test_generated_dispatch_errors() {
+    let greeter = Greeter::new();
+
+    let error = greeter.execute_action("greet", &HashMap::new()).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidParams);
+    assert_eq!(error.message, "Required parameter 'name' not provided");
+
+    let error = greeter.execute_action("greet", &params(json!({"name": 42}))).unwrap_err();
+    assert!(error.message.starts_with("Parameter 'name' is invalid"), "{}", error);
+
+    let error = greeter.execute_action("sum", &params(json!({"values": [-1]}))).unwrap_err();
+    assert!(error.message.starts_with("Parameter 'values' is invalid"), "{}", error);
+
+    // Required parameters must be given even when their argument accepts null
+    let error = Hypervisor.execute_action("apply", &params(json!({}))).unwrap_err();
+    assert_eq!(error.message, "Required parameter 'spec' not provided");
+    assert_eq!(Hypervisor.execute_action("apply", &params(json!({"spec": 1}))).unwrap(), json!(1));
+    let machine = json!({
+        "name": "vm1", "cpus": 2, "load": 0.5, "headless": true,
+        "tags": [], "disk": {"size_gb": 20},
+    });
+    let error = greeter.execute_action("create", &params(machine)).unwrap_err();
+    assert_eq!(error.message, "Required parameter 'memory' not provided");
+
+    let error = greeter.execute_action("missing", &HashMap::new()).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Unsupported);
+    assert_eq!(error.message, "Unknown action: missing");
+}
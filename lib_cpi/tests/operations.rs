@@ -0,0 +1,195 @@
+//! Tests for running actions as operations
+
+mod common;
+
+use lib_cpi::ffi::{self, ForeignExtension};
+use lib_cpi::macros::cpi_extension;
+use lib_cpi::operation::{self, CANCEL_OPERATION};
+use lib_cpi::{
+    ActionContext, ActionResult, CpiExtension, ErrorKind, JobManager, Jobs, Operation, OperationState, Operations,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::params;
+
+/// Creates machines step by step, as the test allows
+struct Hypervisor {
+    steps: Mutex<Receiver<()>>,
+    /// Machines whose creation stopped early, with the step it stopped at
+    stopped: Arc<Mutex<Vec<(String, u8)>>>,
+}
+
+#[cpi_extension(name = "vbox", provider_type = "hypervisor")]
+impl Hypervisor {
+    #[action(description = "Creates a machine in four steps")]
+    fn create_vm(&self, name: String, context: &ActionContext) -> ActionResult {
+        for step in 1..=4 {
+            if let Err(error) = context.checkpoint() {
+                self.stopped.lock().unwrap().push((name, step));
+                return Err(error);
+            }
+            self.steps.lock().unwrap().recv_timeout(Duration::from_secs(5)).ok();
+            context.report_progress(step * 25 - 5);
+        }
+        if name == "broken" {
+            return Err(lib_cpi::CpiError::unavailable("Disk controller not found"));
+        }
+        Ok(json!({ "id": format!("vm-{}", name) }))
+    }
+
+    #[action(description = "Lists machines")]
+    fn list_vms(&self) -> ActionResult {
+        Ok(json!([]))
+    }
+}
+
+fn hypervisor() -> (Hypervisor, Sender<()>) {
+    let (sender, receiver) = mpsc::channel();
+    let stopped = Arc::new(Mutex::new(Vec::new()));
+    (Hypervisor { steps: Mutex::new(receiver), stopped }, sender)
+}
+
+/// Polls until `check` holds, giving up after a few seconds
+fn eventually(check: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !check() {
+        if Instant::now() > deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    true
+}
+
+/// Polls until `check` accepts the operation, returning it as last seen
+fn wait_for(extension: &dyn CpiExtension, id: &str, check: impl Fn(&Operation) -> bool) -> Operation {
+    eventually(|| check(&extension.get_operation(id).unwrap()));
+    extension.get_operation(id).unwrap()
+}
+
+#[test]
+fn test_operation_lifecycle() {
+    let (vbox, steps) = hypervisor();
+    let extension = Jobs::new(vbox, ["create_vm"]);
+    assert!(extension.list_actions().contains(&"get_operation".to_string()));
+    assert_eq!(extension.get_action_definition(CANCEL_OPERATION).unwrap().parameters[0].name, "id");
+
+    let handle = Operation::from_value(extension.execute_action("create_vm", &params(json!({"name": "web"}))).unwrap());
+    let handle = handle.unwrap();
+    assert_eq!((handle.action.as_str(), handle.state, handle.progress), ("create_vm", OperationState::Pending, 0));
+    assert!(handle.outcome().is_none());
+
+    steps.send(()).unwrap();
+    let operation = wait_for(&extension, &handle.id, |operation| operation.progress == 20);
+    assert_eq!((operation.state, operation.progress), (OperationState::Running, 20));
+
+    for _ in 0..3 {
+        steps.send(()).unwrap();
+    }
+    let operation = wait_for(&extension, &handle.id, Operation::is_finished);
+    assert_eq!((operation.state, operation.progress), (OperationState::Succeeded, 100));
+    assert_eq!(operation.outcome().unwrap().unwrap(), json!({"id": "vm-web"}));
+    assert_eq!(extension.list_operations().unwrap(), vec![operation]);
+
+    // Failures are kept on the operation; other actions answer directly
+    let handle = Operation::from_value(extension.execute_action("create_vm", &params(json!({"name": "broken"}))).unwrap());
+    for _ in 0..4 {
+        steps.send(()).unwrap();
+    }
+    let operation = wait_for(&extension, &handle.unwrap().id, Operation::is_finished);
+    assert_eq!(operation.state, OperationState::Failed);
+    assert_eq!(operation.outcome().unwrap().unwrap_err().kind, ErrorKind::Unavailable);
+    assert_eq!(extension.execute_action("list_vms", &HashMap::new()).unwrap(), json!([]));
+    assert_eq!(extension.get_operation("op-unknown").unwrap_err().kind, ErrorKind::NotFound);
+}
+
+#[test]
+fn test_cancelling_operations() {
+    let (vbox, steps) = hypervisor();
+    let stopped = vbox.stopped.clone();
+    let manager = JobManager::new().with_max_running(1);
+    let extension = Jobs::new(vbox, ["create_vm"]).with_manager(manager);
+
+    let running = Operation::from_value(extension.execute_action("create_vm", &params(json!({"name": "a"}))).unwrap());
+    let queued = Operation::from_value(extension.execute_action("create_vm", &params(json!({"name": "b"}))).unwrap());
+    let (running, queued) = (running.unwrap(), queued.unwrap());
+    wait_for(&extension, &running.id, |operation| operation.state == OperationState::Running);
+    assert_eq!(extension.get_operation(&queued.id).unwrap().state, OperationState::Pending);
+
+    let cancelled = extension.cancel_operation(&queued.id).unwrap();
+    assert_eq!(cancelled.state, OperationState::Cancelled);
+    assert_eq!(cancelled.outcome().unwrap().unwrap_err().kind, ErrorKind::Cancelled);
+
+    // The running action stops at its next checkpoint rather than taking all four steps
+    assert_eq!(extension.cancel_operation(&running.id).unwrap().state, OperationState::Cancelled);
+    steps.send(()).unwrap();
+    assert!(eventually(|| !stopped.lock().unwrap().is_empty()));
+    assert!(matches!(stopped.lock().unwrap()[..], [(ref name, step)] if name == "a" && step <= 2));
+    assert_eq!(extension.get_operation(&running.id).unwrap().state, OperationState::Cancelled);
+    assert_eq!(extension.get_operation(&queued.id).unwrap().progress, 0);
+
+    // Its worker is free again, and the cancelled queued operation is skipped
+    let next = Operation::from_value(extension.execute_action("create_vm", &params(json!({"name": "c"}))).unwrap());
+    for _ in 0..4 {
+        steps.send(()).unwrap();
+    }
+    let operation = wait_for(&extension, &next.unwrap().id, Operation::is_finished);
+    assert_eq!(operation.result, Some(json!({"id": "vm-c"})));
+    assert_eq!(stopped.lock().unwrap().len(), 1);
+    assert_eq!(extension.cancel_operation("op-unknown").unwrap_err().kind, ErrorKind::NotFound);
+}
+
+#[test]
+fn test_job_manager() {
+    let manager = JobManager::new();
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let seen = progress.clone();
+    let operation = manager.spawn("export_image", ActionContext::new(), move |context| {
+        context.report_progress(150);
+        seen.lock().unwrap().push(context.request_id.clone());
+        Ok(json!("exported"))
+    });
+
+    assert!(eventually(|| manager.get(&operation.id).unwrap().is_finished()));
+    assert_eq!(manager.get(&operation.id).unwrap().result, Some(json!("exported")));
+    // The operation's id is the request id its action sees
+    assert_eq!(*progress.lock().unwrap(), vec![operation.id.clone()]);
+
+    // A panic fails the operation
+    let crashed = manager.spawn("crash", ActionContext::new(), |_| panic!("out of disk"));
+    assert!(eventually(|| manager.get(&crashed.id).unwrap().is_finished()));
+    assert_eq!(manager.get(&crashed.id).unwrap().error.unwrap().message, "Action 'crash' panicked: out of disk");
+    assert_eq!(manager.list().len(), 2);
+
+    // Finished operations expire once the retention period is over
+    let manager = JobManager::new().with_retention(Duration::ZERO);
+    let operation = manager.spawn("export_image", ActionContext::new(), |_| Ok(json!("exported")));
+    assert!(eventually(|| manager.get(&operation.id).is_none()));
+    assert!(manager.list().is_empty());
+}
+
+#[test]
+fn test_operations_cross_plugin_boundaries() {
+    let (vbox, steps) = hypervisor();
+    let foreign = unsafe { ForeignExtension::from_vtable(ffi::export(Jobs::new(vbox, ["create_vm"]))) };
+    let handle = Operation::from_value(foreign.execute_action("create_vm", &params(json!({"name": "db"}))).unwrap());
+    for _ in 0..4 {
+        steps.send(()).unwrap();
+    }
+    let operation = wait_for(&foreign, &handle.unwrap().id, Operation::is_finished);
+    assert_eq!(operation.result, Some(json!({"id": "vm-db"})));
+
+    let value = operation.to_value();
+    assert_eq!(value["state"], json!("Succeeded"));
+    assert!(value.get("error").is_none());
+
+    // Extensions without operations report the reserved actions as unknown
+    let (vbox, _steps) = hypervisor();
+    assert_eq!(vbox.list_operations().unwrap_err().kind, ErrorKind::Unsupported);
+    assert!(operation::definition("list_vms").is_none());
+}
@@ -0,0 +1,354 @@
+//! Tests for parameter types and their validation
+
+mod common;
+
+use lib_cpi::{ActionDefinition, Constraints, CpiError, ParamType, param, validation};
+use serde_json::{Value, json};
+use std::time::Duration;
+
+use common::params;
+
+#[test]
+fn test_param_types_read_old_definitions() {
+    // Written by lib_cpi versions whose types carried no details
+    let old = json!({
+        "name": "create",
+        "description": "Creates a machine",
+        "parameters": [
+            {"name": "cpus", "description": "", "required": true, "param_type": "Number", "default_value": null},
+            {"name": "tags", "description": "", "required": false, "param_type": "Array", "default_value": []},
+            {"name": "disk", "description": "", "required": true, "param_type": "Object", "default_value": null},
+        ],
+    });
+
+    let definition: ActionDefinition = serde_json::from_value(old.clone()).unwrap();
+    assert_eq!(definition.parameters[0].param_type, ParamType::Number);
+    assert_eq!(definition.parameters[1].param_type, ParamType::array(ParamType::Any));
+    assert_eq!(definition.parameters[2].param_type, ParamType::Object(Vec::new()));
+
+    // And writes them back unchanged
+    assert_eq!(serde_json::to_value(&definition).unwrap(), old);
+}
+
+#[test]
+fn test_param_types_round_trip() {
+    let types = vec![
+        ParamType::Integer,
+        ParamType::Float,
+        ParamType::Secret,
+        ParamType::Duration,
+        ParamType::ByteSize,
+        ParamType::Any,
+        ParamType::one_of(["qcow2", "raw"]),
+        ParamType::array(ParamType::array(ParamType::Integer)),
+        ParamType::Object(vec![
+            param!("host", "Host name", ParamType::String, required),
+            param!("port", "Port", ParamType::Integer, optional, json!(22)),
+        ]),
+    ];
+
+    let serialized = serde_json::to_value(&types).unwrap();
+    assert_eq!(serialized[0], json!("Integer"));
+    assert_eq!(serialized[6], json!({"Enum": ["qcow2", "raw"]}));
+    assert_eq!(serialized[7], json!({"Array": {"Array": "Integer"}}));
+    assert_eq!(serialized[8]["Object"][1]["param_type"], json!("Integer"));
+
+    let deserialized: Vec<ParamType> = serde_json::from_value(serialized).unwrap();
+    assert_eq!(deserialized, types);
+}
+
+#[test]
+fn test_secrets_are_redacted() {
+    let credentials = ParamType::Object(vec![
+        param!("user", "User name", ParamType::String, required),
+        param!("password", "Password", ParamType::Secret, required),
+    ]);
+    let definition = ActionDefinition::new(
+        "login",
+        "Logs in",
+        vec![
+            param!("token", "API token", ParamType::Secret, required),
+            param!("keys", "SSH keys", ParamType::array(ParamType::Secret), optional),
+            param!("accounts", "Accounts", ParamType::array(credentials), optional),
+            param!("unset", "Not given", ParamType::Secret, optional),
+        ],
+    );
+
+    let redacted = definition.redact(&params(json!({
+        "token": "abc123",
+        "keys": ["key1", "key2"],
+        "accounts": [{"user": "admin", "password": "hunter2"}],
+        "unset": null,
+        "extra": "kept",
+    })));
+
+    assert_eq!(redacted["token"], json!("********"));
+    assert_eq!(redacted["keys"], json!(["********", "********"]));
+    assert_eq!(redacted["accounts"], json!([{"user": "admin", "password": "********"}]));
+    assert_eq!(redacted["unset"], Value::Null);
+    assert_eq!(redacted["extra"], json!("kept"));
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(validation::parse_duration("90s").unwrap(), Duration::from_secs(90));
+    assert_eq!(validation::parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+    assert_eq!(validation::parse_duration("1h 30m").unwrap(), Duration::from_secs(5400));
+    assert_eq!(validation::parse_duration("250ms").unwrap(), Duration::from_millis(250));
+    assert_eq!(validation::parse_duration("1.5d").unwrap(), Duration::from_secs(129_600));
+    assert_eq!(validation::parse_duration("45").unwrap(), Duration::from_secs(45));
+
+    for invalid in ["", "s", "10x", "-5s", "-5", "inf", "200000000000000d200000000000000d"] {
+        let error = validation::parse_duration(invalid).unwrap_err();
+        assert!(error.contains("must be a duration"), "{}: {}", invalid, error);
+    }
+}
+
+#[test]
+fn test_parse_byte_size() {
+    assert_eq!(validation::parse_byte_size("20GiB").unwrap(), 20 * (1 << 30));
+    assert_eq!(validation::parse_byte_size("512MB").unwrap(), 512_000_000);
+    assert_eq!(validation::parse_byte_size("1.5 KiB").unwrap(), 1536);
+    assert_eq!(validation::parse_byte_size("4096").unwrap(), 4096);
+
+    for invalid in ["", "GiB", "20GB!", "0.5B", "-1KB", "99999999PiB"] {
+        let error = validation::parse_byte_size(invalid).unwrap_err();
+        assert!(error.contains("must be a size"), "{}: {}", invalid, error);
+    }
+}
+
+#[test]
+fn test_extract_duration_and_size() {
+    let params = params(json!({"timeout": "2m", "wait": 1.5, "size": "1MiB", "bytes": 10, "bad": true}));
+
+    assert_eq!(validation::extract_duration(&params, "timeout").unwrap(), Duration::from_secs(120));
+    assert_eq!(validation::extract_duration(&params, "wait").unwrap(), Duration::from_millis(1500));
+    assert_eq!(validation::extract_byte_size(&params, "size").unwrap(), 1 << 20);
+    assert_eq!(validation::extract_byte_size(&params, "bytes").unwrap(), 10);
+
+    let error = validation::extract_duration(&params, "bad").unwrap_err();
+    assert_eq!(error.message, "Parameter 'bad' must be a duration");
+    let error = validation::extract_byte_size(&params, "timeout").unwrap_err();
+    assert!(error.message.starts_with("Parameter 'timeout' must be a size"), "{}", error);
+    let error = validation::extract_duration(&params, "missing").unwrap_err();
+    assert_eq!(error.message, "Required parameter 'missing' not provided");
+}
+
+fn definition(parameters: Vec<lib_cpi::ActionParameter>) -> ActionDefinition {
+    ActionDefinition::new("create", "Creates a machine", parameters)
+}
+
+#[test]
+fn test_constraints_from_param_macro() {
+    let cpus = param!("cpus", "Number of CPUs", ParamType::Integer, required, min = 1, max = 64);
+    assert_eq!(cpus.constraints, Constraints::default().min(1).max(64));
+
+    let name = param!("name", "Name", ParamType::String, optional, json!("vm"), pattern = "[a-z]+", max_length = 8);
+    assert_eq!(name.default_value, Some(json!("vm")));
+    assert_eq!(name.constraints.pattern.as_deref(), Some("[a-z]+"));
+
+    let size = param!("size", "Disk size", ParamType::ByteSize, optional, min = 1 << 30);
+    assert_eq!(size.default_value, None);
+    assert_eq!(size.constraints.min, Some(1073741824.0));
+
+    let tags = param!("tags", "Tags", ParamType::array(ParamType::String), optional);
+    assert!(tags.constraints.is_empty());
+}
+
+#[test]
+fn test_check_constraints() {
+    let definition = definition(vec![
+        param!("cpus", "Number of CPUs", ParamType::Integer, required, min = 1, max = 64),
+        param!("name", "Name", ParamType::String, optional, pattern = "[a-z]+", min_length = 2),
+        param!("timeout", "Timeout", ParamType::Duration, optional, max = 3600),
+        param!("format", "Format", ParamType::String, optional, allowed = ["qcow2", "raw"]),
+        param!("disks", "Disks", ParamType::array(ParamType::Any), optional, max_items = 2),
+        param!(
+            "network",
+            "Network",
+            ParamType::Object(vec![param!("mtu", "MTU", ParamType::Integer, optional, min = 576)]),
+            optional
+        ),
+    ]);
+
+    let valid = params(json!({
+        "cpus": 4, "name": "vm", "timeout": "1h", "format": "raw", "disks": [{}], "network": {"mtu": 1500},
+    }));
+    assert_eq!(validation::check_constraints(&definition, &valid), Ok(()));
+
+    // Missing, null and wrongly typed values are not the constraints' business
+    let untouched = params(json!({"name": null, "timeout": true, "format": null}));
+    assert_eq!(validation::check_constraints(&definition, &untouched), Ok(()));
+
+    let cases = [
+        (json!({"cpus": 0}), "Parameter 'cpus' must be at least 1"),
+        (json!({"cpus": 65}), "Parameter 'cpus' must be at most 64"),
+        (json!({"name": "vm1"}), "Parameter 'name' must match the pattern '[a-z]+'"),
+        (json!({"name": "v"}), "Parameter 'name' must be at least 2 characters long"),
+        (json!({"timeout": "2h"}), "Parameter 'timeout' must be at most 3600"),
+        (json!({"timeout": 7200}), "Parameter 'timeout' must be at most 3600"),
+        (json!({"format": "vmdk"}), "Parameter 'format' must be one of \"qcow2\", \"raw\""),
+        (json!({"disks": [1, 2, 3]}), "Parameter 'disks' must have at most 2 items"),
+        (json!({"network": {"mtu": 100}}), "Parameter 'network.mtu' must be at least 576"),
+    ];
+    for (given, expected) in cases {
+        assert_eq!(validation::check_constraints(&definition, &params(given)), Err(CpiError::invalid_params(expected)));
+    }
+}
+
+#[test]
+fn test_invalid_patterns_are_reported() {
+    let definition = definition(vec![param!("name", "Name", ParamType::String, required, pattern = "[a-")]);
+    let error = validation::check_constraints(&definition, &params(json!({"name": "vm"}))).unwrap_err();
+    assert!(error.message.starts_with("Parameter 'name' has an invalid pattern"), "{}", error);
+}
+
+#[test]
+fn test_constraints_serialization() {
+    let cpus = param!("cpus", "Number of CPUs", ParamType::Integer, required, min = 1);
+    let serialized = serde_json::to_value(&cpus).unwrap();
+    assert_eq!(serialized["constraints"], json!({"min": 1.0}));
+
+    // Parameters without constraints serialize as they always did
+    let name = param!("name", "Name", ParamType::String, required);
+    let serialized = serde_json::to_value(&name).unwrap();
+    assert!(serialized.get("constraints").is_none());
+
+    let read: lib_cpi::ActionParameter = serde_json::from_value(json!({
+        "name": "cpus", "description": "", "required": true, "param_type": "Integer",
+        "default_value": null, "constraints": {"max": 8},
+    }))
+    .unwrap();
+    assert_eq!(read.constraints, Constraints::default().max(8));
+}
+
+fn machine() -> ActionDefinition {
+    definition(vec![
+        param!("name", "Name", ParamType::String, required, pattern = "[a-z0-9-]+"),
+        param!("cpus", "Number of CPUs", ParamType::Integer, optional, json!(1), min = 1, max = 64),
+        param!("ratio", "Overcommit ratio", ParamType::Float, optional),
+        param!("headless", "Start without a window", ParamType::Boolean, optional, json!(true)),
+        param!("format", "Disk format", ParamType::one_of(["qcow2", "raw"]), optional),
+        param!("ports", "Forwarded ports", ParamType::array(ParamType::Integer), optional),
+        param!(
+            "network",
+            "Network",
+            ParamType::Object(vec![
+                param!("bridge", "Bridge", ParamType::String, required),
+                param!("mtu", "MTU", ParamType::Integer, optional, json!(1500), min = 576),
+            ]),
+            optional
+        ),
+    ])
+}
+
+#[test]
+fn test_validate_against_fills_defaults() {
+    let given = params(json!({"name": "vm1", "headless": null, "network": {"bridge": "br0"}}));
+    let normalized = validation::validate_against(&machine(), &given).unwrap();
+
+    assert_eq!(
+        normalized,
+        params(json!({
+            "name": "vm1", "cpus": 1, "headless": true, "network": {"bridge": "br0", "mtu": 1500},
+        }))
+    );
+}
+
+#[test]
+fn test_validate_against_reports_every_problem() {
+    let given = params(json!({
+        "cpus": 100,
+        "ratio": "high",
+        "format": "vmdk",
+        "ports": [22, "http"],
+        "network": {"mtu": 9000, "vlan": 2},
+        "color": "red",
+    }));
+    let errors = validation::validate_against(&machine(), &given).unwrap_err();
+
+    let found: Vec<(&str, &str)> = errors.0.iter().map(|e| (e.parameter.as_str(), e.message.as_str())).collect();
+    assert_eq!(
+        found,
+        vec![
+            ("name", "Required parameter 'name' not provided"),
+            ("cpus", "Parameter 'cpus' must be at most 64"),
+            ("ratio", "Parameter 'ratio' must be a number"),
+            ("format", "Parameter 'format' must be one of \"qcow2\", \"raw\""),
+            ("ports[1]", "Parameter 'ports[1]' must be an integer"),
+            ("network.bridge", "Required parameter 'network.bridge' not provided"),
+            ("network.vlan", "Unknown parameter 'network.vlan'"),
+            ("color", "Unknown parameter 'color'"),
+        ]
+    );
+
+    // Usable as the error of an action
+    let error: CpiError = errors.into();
+    let message = error.message;
+    assert!(message.starts_with("Required parameter 'name' not provided; Parameter 'cpus'"), "{}", message);
+}
+
+#[test]
+fn test_validate_against_checks_special_types() {
+    let definition = definition(vec![
+        param!("timeout", "Timeout", ParamType::Duration, optional, max = 60),
+        param!("size", "Size", ParamType::ByteSize, optional),
+        param!("password", "Password", ParamType::Secret, optional),
+    ]);
+
+    let valid = params(json!({"timeout": "30s", "size": "1GiB", "password": "hunter2"}));
+    assert_eq!(validation::validate_against(&definition, &valid).unwrap(), valid);
+
+    let errors = validation::validate_against(&definition, &params(json!({"timeout": "2m", "size": "big", "password": 7})));
+    let messages: Vec<String> = errors.unwrap_err().0.into_iter().map(|e| e.message).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Parameter 'timeout' must be at most 60",
+            "Parameter 'size' must be a size such as '20GiB' or '512MB', got 'big'",
+            "Parameter 'password' must be a string",
+        ]
+    );
+}
+
+#[test]
+fn test_validate_against_coerces_strings() {
+    let options = validation::ValidationOptions {
+        coerce: true,
+        ..Default::default()
+    };
+    let given = params(json!({
+        "name": "vm1",
+        "cpus": "4",
+        "ratio": "1.5",
+        "headless": "FALSE",
+        "ports": "22, 80",
+        "network": "{\"bridge\": \"br0\", \"mtu\": \"9000\"}",
+    }));
+    let normalized = validation::validate_against_with(&machine(), &given, options).unwrap();
+
+    assert_eq!(normalized["cpus"], json!(4));
+    assert_eq!(normalized["ratio"], json!(1.5));
+    assert_eq!(normalized["headless"], json!(false));
+    assert_eq!(normalized["ports"], json!([22, 80]));
+    assert_eq!(normalized["network"], json!({"bridge": "br0", "mtu": 9000}));
+
+    // Without coercion the same strings are type errors
+    let errors = validation::validate_against(&machine(), &given).unwrap_err();
+    assert_eq!(errors.0.len(), 5);
+
+    // Strings that do not read as the declared type are still reported
+    let errors = validation::validate_against_with(&machine(), &params(json!({"name": "vm", "cpus": "four"})), options);
+    assert_eq!(errors.unwrap_err().to_string(), "Parameter 'cpus' must be an integer");
+}
+
+#[test]
+fn test_validate_against_allows_unknown_parameters() {
+    let options = validation::ValidationOptions {
+        allow_unknown: true,
+        ..Default::default()
+    };
+    let given = params(json!({"name": "vm1", "trace": true}));
+    let normalized = validation::validate_against_with(&machine(), &given, options).unwrap();
+    assert_eq!(normalized["trace"], json!(true));
+}
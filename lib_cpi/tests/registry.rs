@@ -0,0 +1,181 @@
+//! Tests for discovering extensions from a plugin directory
+
+mod common;
+
+use lib_cpi::{CpiExtension, ExtensionLoader, ExtensionRegistry};
+use serde_json::json;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use common::params;
+
+/// Copies the fixture into a fresh plugin directory, returning the library's path there
+fn plugin_dir(name: &str) -> PathBuf {
+    let plugin = common::scratch_dir(name).join(common::fixture_library().file_name().unwrap());
+    std::fs::copy(common::fixture_library(), &plugin).unwrap();
+    plugin
+}
+
+/// Puts the 0.2.0 build in place of `plugin`, dated ahead so it counts as changed
+fn ship_new_build(plugin: &Path) {
+    let staged = plugin.with_extension("staged");
+    std::fs::copy(common::fixture_library_with_version("0.2.0"), &staged).unwrap();
+    let file = std::fs::File::options().write(true).open(&staged).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+    std::fs::rename(&staged, plugin).unwrap();
+}
+
+/// Starts a `hold` call on another thread and waits until it is in flight
+///
+/// The call returns once the test drops the connection it hands back.
+fn hold_call(registry: &Arc<ExtensionRegistry>) -> (JoinHandle<serde_json::Value>, std::net::TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let extension = registry.get("test_extension").unwrap();
+    let call = thread::spawn(move || extension.execute_action("hold", &params(json!({"address": address}))).unwrap());
+    let (connection, _) = listener.accept().unwrap();
+    (call, connection)
+}
+
+#[test]
+fn test_scan_directory() {
+    let dir = common::scratch_dir("registry-scan");
+    let library = common::fixture_library();
+    std::fs::copy(&library, dir.join(library.file_name().unwrap())).unwrap();
+    std::fs::write(dir.join(format!("broken.{}", std::env::consts::DLL_EXTENSION)), b"not a library").unwrap();
+    std::fs::write(dir.join("README.txt"), b"ignored").unwrap();
+
+    let registry = ExtensionRegistry::new();
+    let report = registry.scan_dir(&dir).unwrap();
+
+    assert_eq!(report.loaded, vec!["test_extension"]);
+    assert_eq!(report.failures.len(), 1);
+    assert!(report.failures[0].path.ends_with(format!("broken.{}", std::env::consts::DLL_EXTENSION)));
+    assert!(report.failures[0].error.contains("Failed to load extension library"));
+
+    assert_eq!(registry.len(), 1);
+    assert_eq!(registry.names(), vec!["test_extension"]);
+    assert_eq!(registry.provider_types(), vec!["test"]);
+    assert_eq!(registry.get("test_extension").unwrap().provider_type(), "test");
+    assert!(registry.get("virtualbox").is_none());
+    assert_eq!(registry.by_provider_type("test").len(), 1);
+    assert!(registry.by_provider_type("hypervisor").is_empty());
+
+    // Loading the same extension again is refused rather than shadowing it
+    let error = registry.load(&library).unwrap_err();
+    assert!(error.contains("already loaded"), "{}", error);
+
+    assert!(registry.remove("test_extension").is_some());
+    assert!(registry.is_empty());
+    assert!(registry.by_provider_type("test").is_empty());
+}
+
+#[test]
+fn test_scan_missing_directory() {
+    let registry = ExtensionRegistry::new();
+    let error = registry.scan_dir("/nonexistent/plugins").unwrap_err();
+    assert!(error.contains("Failed to read extension directory"));
+}
+
+#[test]
+fn test_reload_swaps_in_new_version() {
+    let dir = common::scratch_dir("registry-reload");
+    let plugin = dir.join(common::fixture_library().file_name().unwrap());
+    std::fs::copy(common::fixture_library(), &plugin).unwrap();
+
+    // Registries load shadow copies by default, so the original is not mapped
+    let registry = ExtensionRegistry::new();
+    registry.scan_dir(&dir).unwrap();
+    let old = registry.get("test_extension").unwrap();
+    assert_eq!(old.version(), "0.1.0");
+    assert!(registry.reload_changed().is_empty());
+
+    // Ship a new build over the original file while the old one is in use
+    std::fs::copy(common::fixture_library_with_version("0.2.0"), &plugin).unwrap();
+    assert_eq!(old.version(), "0.1.0");
+    drop(old);
+    let file = std::fs::File::options().write(true).open(&plugin).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+
+    let events = registry.reload_changed();
+    assert_eq!(events.len(), 1);
+    let event = events[0].as_ref().unwrap();
+    assert_eq!(event.name, "test_extension");
+    assert_eq!(event.old_version, "0.1.0");
+    assert_eq!(event.new_version, "0.2.0");
+    assert!(event.drained);
+    assert!(event.unloaded);
+
+    assert_eq!(registry.get("test_extension").unwrap().version(), "0.2.0");
+    assert_eq!(registry.len(), 1);
+    assert!(registry.reload_changed().is_empty());
+}
+
+#[test]
+fn test_watch_reports_reloads() {
+    let plugin = plugin_dir("registry-watch");
+    let shadow_dir = plugin.parent().unwrap().join("shadow");
+    let registry = Arc::new(ExtensionRegistry::with_loader(ExtensionLoader::new().with_shadow_dir(shadow_dir)));
+    registry.load(&plugin).unwrap();
+
+    let (sender, events) = mpsc::channel();
+    let watcher = registry.watch(Duration::from_millis(20), move |event| {
+        let _ = sender.send(event);
+    });
+
+    // Renaming the finished build into place means the watcher never sees half a library
+    ship_new_build(&plugin);
+
+    let event = events.recv_timeout(Duration::from_secs(10)).unwrap().unwrap();
+    assert_eq!(event.new_version, "0.2.0");
+    drop(watcher);
+}
+
+#[test]
+fn test_reload_drains_in_flight_calls() {
+    let plugin = plugin_dir("registry-drain");
+    let registry = Arc::new(ExtensionRegistry::new());
+    registry.load(&plugin).unwrap();
+
+    let (call, connection) = hold_call(&registry);
+    ship_new_build(&plugin);
+    let reloader = {
+        let registry = registry.clone();
+        thread::spawn(move || registry.reload_changed())
+    };
+
+    // The new instance is swapped in before the reload starts waiting for the old one
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while registry.get("test_extension").unwrap().version() != "0.2.0" {
+        assert!(Instant::now() < deadline, "the reload never swapped in the new build");
+        thread::yield_now();
+    }
+    assert!(!reloader.is_finished());
+
+    drop(connection);
+    assert_eq!(call.join().unwrap(), json!("released"));
+    let events = reloader.join().unwrap();
+    assert!(events[0].as_ref().unwrap().drained);
+}
+
+#[test]
+fn test_reload_gives_up_draining_after_the_timeout() {
+    let plugin = plugin_dir("registry-drain-timeout");
+    let registry = Arc::new(ExtensionRegistry::new().with_drain_timeout(Duration::from_millis(100)));
+    registry.load(&plugin).unwrap();
+
+    let (call, connection) = hold_call(&registry);
+    ship_new_build(&plugin);
+    let events = registry.reload_changed();
+    let event = events[0].as_ref().unwrap();
+    assert_eq!(event.new_version, "0.2.0");
+    assert!(!event.drained);
+    // The caller still holds the old instance, so its library stays mapped
+    assert!(!event.unloaded);
+
+    drop(connection);
+    assert_eq!(call.join().unwrap(), json!("released"));
+}
@@ -0,0 +1,225 @@
+//! Tests for running extensions out of process over JSON-RPC
+
+mod common;
+
+use lib_cpi::rpc::{self, Endpoint, Response, RpcServer};
+use lib_cpi::{ActionDefinition, ActionResult, CpiError, CpiExtension, RemoteExtension, validation};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use common::params;
+
+#[test]
+fn test_remote_extension_over_stdio() {
+    let extension = RemoteExtension::spawn(Command::new(common::fixture_binary())).unwrap();
+
+    assert_eq!(extension.name(), "test_extension");
+    assert_eq!(extension.provider_type(), "test");
+    assert_eq!(extension.version(), "0.1.0");
+    assert_eq!(extension.list_actions(), vec!["echo", "add", "hold"]);
+    assert_eq!(extension.default_settings()["greeting"], json!("hello"));
+    assert_eq!(extension.test_install().unwrap(), json!({"status": "ok"}));
+    assert_eq!(extension.get_action_definition("echo").unwrap().parameters[0].name, "message");
+    assert!(extension.get_action_definition("missing").is_none());
+
+    let mut params = HashMap::new();
+    params.insert("message".to_string(), json!("hi"));
+    assert_eq!(extension.execute_action("echo", &params).unwrap(), json!({"message": "hi"}));
+
+    let error = extension.execute_action("add", &params).unwrap_err();
+    assert_eq!(error, CpiError::invalid_params("Required parameter 'a' not provided"));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_remote_extension_reports_dead_process() {
+    // A process that exits without speaking the protocol fails the handshake
+    let error = RemoteExtension::spawn(Command::new("true")).err().unwrap();
+    assert!(error.contains("closed the connection"), "{}", error);
+}
+
+// In-process extension that reports each action it starts, and holds `wait` until the
+// test lets it go
+struct Gated {
+    started: Sender<&'static str>,
+    gate: Mutex<Receiver<()>>,
+}
+
+impl CpiExtension for Gated {
+    fn name(&self) -> &str {
+        "gated"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        vec!["echo".to_string(), "wait".to_string()]
+    }
+
+    fn get_action_definition(&self, _action: &str) -> Option<ActionDefinition> {
+        None
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        match action {
+            "echo" => {
+                self.started.send("echo").ok();
+                Ok(json!(validation::extract_int(params, "n")?))
+            }
+            "wait" => {
+                self.started.send("wait").ok();
+                self.gate.lock().unwrap().recv_timeout(Duration::from_secs(30)).ok();
+                Ok(json!("released"))
+            }
+            _ => Err(CpiError::unsupported(format!("Unknown action: {}", action))),
+        }
+    }
+}
+
+/// The extension, the actions it reports starting, and the gate its `wait` action waits on
+fn gated() -> (Arc<Gated>, Receiver<&'static str>, Sender<()>) {
+    let (started, reported) = mpsc::channel();
+    let (gate, waiting) = mpsc::channel();
+    (Arc::new(Gated { started, gate: Mutex::new(waiting) }), reported, gate)
+}
+
+fn echo(extension: &RemoteExtension, n: i64) -> ActionResult {
+    extension.execute_action("echo", &params(json!({"n": n})))
+}
+
+/// Starts a `wait` call on another thread and waits until the extension is running it
+fn hold(extension: &Arc<RemoteExtension>, started: &Receiver<&'static str>) -> JoinHandle<ActionResult> {
+    let extension = extension.clone();
+    let call = std::thread::spawn(move || extension.execute_action("wait", &HashMap::new()));
+    assert_eq!(started.recv_timeout(Duration::from_secs(5)), Ok("wait"));
+    call
+}
+
+#[test]
+fn test_serve_protocol_errors() {
+    let (extension, _started, _gate) = gated();
+    let input = concat!(
+        "not json\n",
+        "{\"jsonrpc\": \"2.0\", \"id\": 7, \"method\": \"nope\"}\n",
+        "{\"jsonrpc\": \"2.0\", \"id\": 8, \"method\": \"execute_action\", \"params\": {\"action\": \"echo\", \"params\": {\"n\": 3}}}\n",
+    );
+
+    let mut output = Vec::new();
+    rpc::serve(&*extension, input.as_bytes(), &mut output).unwrap();
+    let responses: Vec<Response> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[0].error.as_ref().unwrap().code, rpc::PARSE_ERROR);
+    assert_eq!(responses[1].id, json!(7));
+    assert_eq!(responses[1].error.as_ref().unwrap().code, rpc::METHOD_NOT_FOUND);
+    assert_eq!(responses[2].result, Some(json!(3)));
+    assert_eq!(responses[2].id, Value::from(8));
+
+    // Single messages are handled the same way
+    let response = rpc::handle_message(&*extension, "{\"jsonrpc\": \"2.0\", \"id\": 9, \"method\": \"name\"}");
+    assert_eq!((response.id, response.result), (json!(9), Some(json!("gated"))));
+}
+
+#[test]
+fn test_tcp_server_multiplexes_requests() {
+    let (gated, started, gate) = gated();
+    let server = RpcServer::bind(&Endpoint::Tcp("127.0.0.1:0".to_string()), gated).unwrap();
+    let handle = server.spawn();
+    let extension = Arc::new(RemoteExtension::connect(handle.endpoint().clone()).unwrap());
+    assert_eq!(extension.name(), "gated");
+
+    // The second call must not queue behind the held one sharing the connection
+    let held = hold(&extension, &started);
+    assert_eq!(echo(&extension, 1).unwrap(), json!(1));
+    assert!(!held.is_finished());
+
+    gate.send(()).unwrap();
+    assert_eq!(held.join().unwrap().unwrap(), json!("released"));
+}
+
+#[test]
+fn test_server_limits_requests_and_connections() {
+    let (gated, started, gate) = gated();
+    let server = RpcServer::bind(&Endpoint::Tcp("127.0.0.1:0".to_string()), gated).unwrap();
+    let handle = server.with_max_requests(1).with_max_connections(1).spawn();
+    let extension = Arc::new(RemoteExtension::connect(handle.endpoint().clone()).unwrap());
+
+    // With one slot the second call does not start until the first returns
+    let held = hold(&extension, &started);
+    let waiting = {
+        let extension = extension.clone();
+        std::thread::spawn(move || echo(&extension, 2))
+    };
+    assert!(started.recv_timeout(Duration::from_millis(100)).is_err());
+    gate.send(()).unwrap();
+    assert_eq!(held.join().unwrap().unwrap(), json!("released"));
+    assert_eq!(started.recv_timeout(Duration::from_secs(5)), Ok("echo"));
+    assert_eq!(waiting.join().unwrap().unwrap(), json!(2));
+
+    // A second client is turned away while the first is connected
+    assert!(RemoteExtension::connect(handle.endpoint().clone()).is_err());
+}
+
+#[test]
+fn test_server_skips_oversized_requests() {
+    let server = RpcServer::bind(&Endpoint::Tcp("127.0.0.1:0".to_string()), gated().0).unwrap();
+    let handle = server.with_max_line(64).spawn();
+    let Endpoint::Tcp(address) = handle.endpoint() else {
+        unreachable!()
+    };
+    let mut stream = TcpStream::connect(address).unwrap();
+    let oversized = format!("{{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"{}\"}}\n", "x".repeat(1000));
+    stream.write_all(oversized.as_bytes()).unwrap();
+    stream.write_all(b"{\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"name\"}\n").unwrap();
+
+    // The connection stays usable after the oversized line
+    let mut lines = BufReader::new(stream).lines();
+    let response: Response = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    assert_eq!(response.error.unwrap().code, rpc::INVALID_REQUEST);
+    let response: Response = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    assert_eq!((response.id, response.result), (json!(2), Some(json!("gated"))));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_unix_server_reconnects() {
+    let endpoint = Endpoint::Unix(common::scratch_dir("rpc-unix").join("gated.sock"));
+    let handle = RpcServer::bind(&endpoint, gated().0).unwrap().spawn();
+    let extension = RemoteExtension::connect(endpoint.clone()).unwrap();
+    assert_eq!(echo(&extension, 1).unwrap(), json!(1));
+
+    // Restarting the daemon drops the connection; the next call reconnects
+    handle.shutdown().unwrap();
+    let _handle = RpcServer::bind(&endpoint, gated().0).unwrap().spawn();
+    let mut result = echo(&extension, 2);
+    if result.is_err() {
+        // The drop may only be noticed by the call that was in progress
+        result = echo(&extension, 2);
+    }
+    assert_eq!(result.unwrap(), json!(2));
+}
+
+#[test]
+fn test_endpoint_parse() {
+    assert_eq!(Endpoint::parse("tcp://127.0.0.1:7400").unwrap(), Endpoint::Tcp("127.0.0.1:7400".to_string()));
+    #[cfg(unix)]
+    assert_eq!(
+        Endpoint::parse("unix:///run/vbox.sock").unwrap(),
+        Endpoint::Unix("/run/vbox.sock".into())
+    );
+    assert!(Endpoint::parse("http://localhost").is_err());
+    assert_eq!(Endpoint::Tcp("localhost:1".to_string()).to_string(), "tcp://localhost:1");
+}
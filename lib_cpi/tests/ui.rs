@@ -0,0 +1,9 @@
+//! Compile errors reported by the macros, checked against `tests/ui/*.stderr`
+//!
+//! Regenerate the expected output with `TRYBUILD=overwrite cargo test --test ui`.
+
+#[test]
+fn ui() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
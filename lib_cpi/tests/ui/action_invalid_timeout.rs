@@ -0,0 +1,13 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension]
+impl Extension {
+    #[action(timeout = "ten minutes")]
+    fn run(&self) -> lib_cpi::ActionResult {
+        Ok(serde_json::Value::Null)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension]
+impl Extension {
+    #[action]
+    fn append(&self, text: &mut String) -> lib_cpi::ActionResult {
+        text.push('!');
+        Ok(serde_json::Value::Null)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension]
+impl Extension {
+    #[action(descripton = "Typo in the key")]
+    fn run(&self) -> lib_cpi::ActionResult {
+        Ok(serde_json::Value::Null)
+    }
+}
+
+fn main() {}
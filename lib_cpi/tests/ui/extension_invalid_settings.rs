@@ -0,0 +1,16 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension(name = "extension", provider_type = "test")]
+impl Extension {
+    #[default_settings]
+    const SETTINGS: &str = r#"{"memory": 1024,}"#;
+
+    #[action]
+    fn ping(&self) -> lib_cpi::ActionResult {
+        Ok("pong".into())
+    }
+}
+
+fn main() {}
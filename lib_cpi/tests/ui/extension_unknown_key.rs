@@ -0,0 +1,13 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension(name = "extension", provider = "test")]
+impl Extension {
+    #[action]
+    fn ping(&self) -> lib_cpi::ActionResult {
+        Ok("pong".into())
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension]
+impl Extension {
+    #[action]
+    #[param(name = "format", type = "Enum")]
+    fn convert(&self, format: String) -> lib_cpi::ActionResult {
+        Ok(format.into())
+    }
+}
+
+fn main() {}
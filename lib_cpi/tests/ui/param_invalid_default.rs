@@ -0,0 +1,14 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension]
+impl Extension {
+    #[action]
+    #[param(name = "count", default = "ten")]
+    fn repeat(&self, count: i64) -> lib_cpi::ActionResult {
+        Ok(count.into())
+    }
+}
+
+fn main() {}
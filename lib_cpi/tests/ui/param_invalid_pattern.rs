@@ -0,0 +1,14 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension]
+impl Extension {
+    #[action]
+    #[param(name = "name", pattern = "[a-z")]
+    fn create(&self, name: String) -> lib_cpi::ActionResult {
+        Ok(name.into())
+    }
+}
+
+fn main() {}
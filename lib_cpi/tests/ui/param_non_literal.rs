@@ -0,0 +1,15 @@
+use lib_cpi::macros::action;
+
+const DESCRIPTION: &str = "Not a literal";
+
+struct Extension;
+
+impl Extension {
+    #[action]
+    #[lib_cpi::macros::param(name = "name", description = DESCRIPTION)]
+    fn greet(&self, name: String) -> lib_cpi::ActionResult {
+        Ok(name.into())
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension]
+impl Extension {
+    #[action(description = "Describes an argument that does not exist")]
+    #[param(name = "nmae", description = "Typo in the name")]
+    fn greet(&self, name: String) -> lib_cpi::ActionResult {
+        Ok(name.into())
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use lib_cpi::macros::cpi_extension;
+
+struct Extension;
+
+#[cpi_extension]
+impl Extension {
+    #[action]
+    #[param(name = "ratio", type = "Decimal")]
+    fn scale(&self, ratio: f64) -> lib_cpi::ActionResult {
+        Ok(ratio.into())
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use lib_cpi::macros::param;
+
+struct Extension;
+
+impl Extension {
+    #[param(name = "name")]
+    fn greet(&self, name: String) -> lib_cpi::ActionResult {
+        Ok(name.into())
+    }
+}
+
+fn main() {}
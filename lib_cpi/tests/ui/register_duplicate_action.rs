@@ -0,0 +1,26 @@
+use lib_cpi::{ActionResult, CpiExtension};
+use lib_cpi::macros::{cpi_extension, register_actions};
+
+struct Extension;
+
+#[cpi_extension]
+impl Extension {
+    #[action]
+    fn ping(&self) -> ActionResult {
+        Ok("pong".into())
+    }
+}
+
+impl CpiExtension for Extension {
+    fn name(&self) -> &str {
+        "extension"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    register_actions![ping, ping];
+}
+
+fn main() {}
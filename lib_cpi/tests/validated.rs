@@ -0,0 +1,104 @@
+//! Tests for the `Validated` wrapper
+
+mod common;
+
+use lib_cpi::validation::ValidationOptions;
+use lib_cpi::{ActionDefinition, ActionResult, CpiError, CpiExtension, ErrorKind, ParamType, Validated, param};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use common::params;
+
+/// Records the parameters every action is called with and does no checking of its own
+#[derive(Default)]
+struct Recorder {
+    calls: Mutex<Vec<(String, HashMap<String, Value>)>>,
+}
+
+impl CpiExtension for Recorder {
+    fn name(&self) -> &str {
+        "recorder"
+    }
+
+    fn provider_type(&self) -> &str {
+        "test"
+    }
+
+    fn list_actions(&self) -> Vec<String> {
+        vec!["resize".to_string(), "undocumented".to_string()]
+    }
+
+    fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        match action {
+            "resize" => Some(ActionDefinition::new(
+                "resize",
+                "Resizes a disk",
+                vec![
+                    param!("disk", "Disk to resize", ParamType::String, required),
+                    param!("size", "New size", ParamType::ByteSize, optional, json!("20GiB")),
+                    param!("online", "Resize while running", ParamType::Boolean, optional, json!(false)),
+                ],
+            )),
+            _ => None,
+        }
+    }
+
+    fn execute_action(&self, action: &str, params: &HashMap<String, Value>) -> ActionResult {
+        self.calls.lock().unwrap().push((action.to_string(), params.clone()));
+        Ok(json!("done"))
+    }
+}
+
+#[test]
+fn test_valid_calls_are_default_filled_and_delegated() {
+    let extension = Validated::new(Recorder::default());
+    assert_eq!(extension.name(), "recorder");
+    assert_eq!(extension.list_actions(), vec!["resize", "undocumented"]);
+
+    let result = extension.execute_action("resize", &params(json!({"disk": "sda"})));
+    assert_eq!(result.unwrap(), json!("done"));
+
+    let calls = extension.inner().calls.lock().unwrap();
+    assert_eq!(calls[0].0, "resize");
+    assert_eq!(calls[0].1, params(json!({"disk": "sda", "size": "20GiB", "online": false})));
+}
+
+#[test]
+fn test_invalid_calls_never_reach_the_extension() {
+    let extension = Validated::new(Recorder::default());
+
+    let error = extension.execute_action("shrink", &HashMap::new()).unwrap_err();
+    assert_eq!(error, CpiError::unsupported("Unknown action: shrink"));
+
+    let error = extension.execute_action("undocumented", &HashMap::new()).unwrap_err();
+    assert_eq!(error.message, "Action 'undocumented' has no definition");
+
+    let error = extension.execute_action("resize", &params(json!({"size": "huge", "force": true}))).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidParams);
+    assert_eq!(error.details.unwrap().as_array().unwrap().len(), 3);
+    assert_eq!(
+        error.message,
+        "Required parameter 'disk' not provided; \
+         Parameter 'size' must be a size such as '20GiB' or '512MB', got 'huge'; \
+         Unknown parameter 'force'"
+    );
+
+    assert!(extension.inner().calls.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_options_and_shared_extensions() {
+    // Works on the Arcs and boxes hosts keep their extensions in
+    let shared = Arc::new(Recorder::default());
+    let options = ValidationOptions {
+        coerce: true,
+        ..Default::default()
+    };
+    let extension = Validated::with_options(shared.clone(), options);
+    extension.execute_action("resize", &params(json!({"disk": "sda", "online": "true"}))).unwrap();
+    assert_eq!(shared.calls.lock().unwrap()[0].1["online"], json!(true));
+
+    let boxed: Box<dyn CpiExtension> = Box::new(Validated::new(Recorder::default()));
+    assert!(boxed.execute_action("resize", &HashMap::new()).is_err());
+}
@@ -0,0 +1,135 @@
+//! Tests for running extensions compiled to WebAssembly
+
+mod common;
+
+use lib_cpi::ffi::{JsonBoundary, Method};
+use lib_cpi::{CpiExtension, WasmRuntime, ffi};
+use serde_json::json;
+use std::collections::HashMap;
+
+#[test]
+#[ignore = "needs wasm32-unknown-unknown"]
+fn test_load_fixture_module() {
+    let module = common::fixture_wasm();
+    let extension = WasmRuntime::new().load(module).unwrap();
+
+    assert_eq!(extension.name(), "test_extension");
+    assert_eq!(extension.provider_type(), "test");
+    assert_eq!(extension.version(), "0.1.0");
+    assert_eq!(extension.boundary().lib_cpi_version(), ffi::LIB_CPI_VERSION);
+    assert_eq!(extension.list_actions(), vec!["echo", "add", "hold"]);
+    assert_eq!(extension.default_settings()["greeting"], json!("hello"));
+    assert_eq!(extension.get_action_definition("echo").unwrap().parameters.len(), 1);
+    assert!(extension.get_action_definition("missing").is_none());
+
+    let mut params = HashMap::new();
+    params.insert("a".to_string(), json!(2));
+    params.insert("b".to_string(), json!(40));
+    assert_eq!(extension.execute_action("add", &params).unwrap()["sum"], json!(42));
+
+    let error = extension.execute_action("missing", &params).unwrap_err();
+    assert_eq!(error.message, "Unknown action: missing");
+}
+
+#[test]
+#[ignore = "needs wasm32-unknown-unknown"]
+fn test_modules_are_shared_between_threads() {
+    let module = common::fixture_wasm();
+    let extension = std::sync::Arc::new(WasmRuntime::new().load(module).unwrap());
+
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let extension = extension.clone();
+            std::thread::spawn(move || {
+                let mut params = HashMap::new();
+                params.insert("message".to_string(), json!(format!("hello {}", i)));
+                extension.execute_action("echo", &params).unwrap()
+            })
+        })
+        .collect();
+
+    for (i, thread) in threads.into_iter().enumerate() {
+        assert_eq!(thread.join().unwrap()["message"], json!(format!("hello {}", i)));
+    }
+}
+
+// Hand-written guest whose execute_action never returns
+const SPINNING_MODULE: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "\"spin\"")
+  (data (i32.const 16) "[\"spin\"]")
+  (func $str (param $ptr i64) (param $len i64) (result i64)
+    (i64.or (i64.shl (local.get $ptr) (i64.const 32)) (local.get $len)))
+  (func (export "cpi_abi_version") (result i32) (i32.const 1))
+  (func (export "cpi_alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "cpi_free") (param i32 i32))
+  (func (export "cpi_name") (param i32 i32) (result i64) (call $str (i64.const 0) (i64.const 6)))
+  (func (export "cpi_provider_type") (param i32 i32) (result i64) (call $str (i64.const 0) (i64.const 6)))
+  (func (export "cpi_list_actions") (param i32 i32) (result i64) (call $str (i64.const 16) (i64.const 8)))
+  (func (export "cpi_get_action_definition") (param i32 i32) (result i64) (unreachable))
+  (func (export "cpi_execute_action") (param i32 i32) (result i64) (loop $forever (br $forever)) (unreachable))
+  (func (export "cpi_default_settings") (param i32 i32) (result i64) (unreachable))
+  (func (export "cpi_test_install") (param i32 i32) (result i64) (unreachable))
+  (func (export "cpi_version") (param i32 i32) (result i64) (call $str (i64.const 0) (i64.const 6)))
+)
+"#;
+
+#[test]
+fn test_fuel_bounds_runaway_calls() {
+    let module = wat::parse_str(SPINNING_MODULE).unwrap();
+    let extension = WasmRuntime::new().with_fuel(100_000).load_bytes(&module).unwrap();
+
+    assert_eq!(extension.name(), "spin");
+    assert_eq!(extension.list_actions(), vec!["spin"]);
+    // No lib version export, which is allowed
+    assert_eq!(extension.boundary().lib_cpi_version(), "");
+
+    let error = extension.execute_action("spin", &HashMap::new()).unwrap_err();
+    assert!(error.message.contains("trapped in 'cpi_execute_action'"), "{}", error);
+
+    // A trap is reported as a failed call and the instance stays usable
+    assert!(extension.test_install().unwrap_err().message.contains("cpi_test_install"));
+    assert_eq!(extension.version(), "spin");
+}
+
+#[test]
+fn test_out_of_bounds_results() {
+    // Claims a 4 GiB result, far past the single page of memory the module has
+    let module = SPINNING_MODULE.replace(
+        r#"(export "cpi_version") (param i32 i32) (result i64) (call $str (i64.const 0) (i64.const 6))"#,
+        r#"(export "cpi_version") (param i32 i32) (result i64) (call $str (i64.const 16) (i64.const 4294967295))"#,
+    );
+    let extension = WasmRuntime::new().load_bytes(&wat::parse_str(module).unwrap()).unwrap();
+
+    let error = extension.boundary().call(Method::Version, b"").unwrap_err();
+    assert!(error.contains("out of bounds buffer"), "{}", error);
+    assert_eq!(extension.name(), "spin");
+}
+
+#[test]
+fn test_rejects_foreign_modules() {
+    let runtime = WasmRuntime::new();
+
+    let error = runtime.load_bytes(b"not wasm").unwrap_err();
+    assert!(error.contains("Invalid WASM module"), "{}", error);
+
+    let plain = wat::parse_str(r#"(module (memory (export "memory") 1))"#).unwrap();
+    let error = runtime.load_bytes(&plain).unwrap_err();
+    assert!(error.contains("Not a lib_cpi extension"), "{}", error);
+
+    // Guests get no host functions to call
+    let importing = wat::parse_str(r#"(module (import "env" "open" (func (param i32))))"#).unwrap();
+    let error = runtime.load_bytes(&importing).unwrap_err();
+    assert!(error.contains("Failed to instantiate"), "{}", error);
+
+    let newer = wat::parse_str(r#"(module (func (export "cpi_abi_version") (result i32) (i32.const 99)))"#).unwrap();
+    let error = runtime.load_bytes(&newer).unwrap_err();
+    assert!(error.contains("extension ABI 99"), "{}", error);
+}
+
+#[test]
+fn test_load_missing_module() {
+    let error = WasmRuntime::new().load("/nonexistent/extension.wasm").unwrap_err();
+    assert!(error.contains("Failed to read WASM extension"));
+}
@@ -0,0 +1,38 @@
+//! Parsing shared by `lib_cpi` and `lib_cpi_macros`, so that values written in
+//! attributes and values passed at runtime follow the same grammar
+
+use std::time::Duration;
+
+/// Parses a duration made of `<number><unit>` parts, e.g. `"90s"`, `"1h30m"` or `"250ms"`
+///
+/// The units are `ms`, `s`, `m`, `h` and `d`; a bare number is a number of seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("must be a duration such as '90s' or '1h30m', got '{}'", text);
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(secs) = text.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).map_err(|_| invalid());
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit() && c != '.').ok_or_else(invalid)?;
+        let unit_len = rest[digits..].find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len() - digits);
+        let amount: f64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit_secs = match rest[digits..digits + unit_len].trim() {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            _ => return Err(invalid()),
+        };
+        let part = Duration::try_from_secs_f64(amount * unit_secs).map_err(|_| invalid())?;
+        total = total.checked_add(part).ok_or_else(invalid)?;
+        rest = &rest[digits + unit_len..];
+    }
+    Ok(total)
+}
@@ -0,0 +1,468 @@
+//! Everything known about one `#[action]` method, and the code generated from it
+//!
+//! An action is described entirely by its own tokens: the signature plus the
+//! `#[param]` attributes written below `#[action]`. Nothing is shared between
+//! macro invocations, so the result does not depend on expansion order and two
+//! types may have actions of the same name.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Attribute, Expr, ExprLit, ExprUnary, FnArg, Ident, Lit, Pat, Signature, Type, UnOp};
+
+use crate::attrs::{ActionAttr, ParamAttr, parse_args, take_attrs};
+use crate::types::{ParamKind, first_type_argument, infer_param_type};
+
+/// Stores parameter metadata during macro processing
+pub(crate) struct ParamInfo {
+    ident: Ident,
+    ty: Type,
+    name: String,
+    description: String,
+    param_type: ParamKind,
+    required: bool,
+    // JSON default as an `Option<Value>` expression
+    default_value: TokenStream,
+    // `Constraints` builder calls, `.min(1f64)`
+    constraints: Vec<TokenStream>,
+}
+
+pub(crate) struct ActionInfo {
+    ident: Ident,
+    description: String,
+    timeout_ms: Option<u64>,
+    params: Vec<ParamInfo>,
+    has_receiver: bool,
+    is_async: bool,
+    // Position among the arguments of the one taking the `&ActionContext`, if any
+    context: Option<usize>,
+}
+
+impl ActionInfo {
+    /// Reads an action from its signature and the `#[param]` attributes in `attrs`
+    ///
+    /// The `#[param]` and `#[generate_metadata]` attributes are removed from `attrs`,
+    /// since they only carry metadata for the action.
+    pub fn new(action_attr: ActionAttr, attrs: &mut Vec<Attribute>, sig: &Signature) -> syn::Result<Self> {
+        let fn_name = sig.ident.to_string();
+        let description = action_attr.description.unwrap_or_else(|| format!("Action {}", fn_name));
+        take_attrs(attrs, "generate_metadata");
+
+        // Extract parameter names and the metadata their types imply
+        let mut params = Vec::new();
+        let mut context = None;
+        for arg in &sig.inputs {
+            let FnArg::Typed(pat_type) = arg else {
+                continue;
+            };
+            let Pat::Ident(pat_ident) = &*pat_type.pat else {
+                return Err(syn::Error::new_spanned(&pat_type.pat, "action arguments must be plain identifiers"));
+            };
+            // The context of the call rather than a parameter
+            if is_context(&pat_type.ty) {
+                if context.is_some() {
+                    return Err(syn::Error::new_spanned(pat_type, "an action takes the ActionContext at most once"));
+                }
+                context = Some(params.len());
+                continue;
+            }
+            check_argument_type(&pat_type.ty)?;
+
+            let (param_type, optional) = infer_param_type(&pat_type.ty);
+            params.push(ParamInfo {
+                ident: pat_ident.ident.clone(),
+                ty: (*pat_type.ty).clone(),
+                name: pat_ident.ident.to_string(),
+                description: String::new(),
+                param_type,
+                required: !optional,
+                default_value: quote! { None },
+                constraints: Vec::new(),
+            });
+        }
+
+        let mut described: Vec<String> = Vec::new();
+        for attr in take_attrs(attrs, "param") {
+            let param_attr: ParamAttr = parse_args(&attr)?;
+            let Some(param_name) = param_attr.name.clone() else {
+                return Err(syn::Error::new_spanned(attr, "#[param] needs the `name` of the argument it describes"));
+            };
+            let Some(param) = params.iter_mut().find(|param| param.name == param_name.value()) else {
+                let names: Vec<String> = sig.inputs.iter().filter_map(argument_name).map(|n| format!("`{}`", n)).collect();
+                let message = match names.is_empty() {
+                    true => format!("`{}` takes no arguments", fn_name),
+                    false => format!("`{}` has no argument `{}`, expected one of {}", fn_name, param_name.value(), names.join(", ")),
+                };
+                return Err(syn::Error::new_spanned(param_name, message));
+            };
+            if described.contains(&param.name) {
+                return Err(syn::Error::new_spanned(param_name, format!("`{}` is described more than once", param.name)));
+            }
+            described.push(param.name.clone());
+            param.apply(param_attr)?;
+        }
+
+        Ok(Self {
+            ident: sig.ident.clone(),
+            description,
+            timeout_ms: action_attr.timeout_ms,
+            params,
+            has_receiver: sig.receiver().is_some(),
+            is_async: sig.asyncness.is_some(),
+            context,
+        })
+    }
+
+    /// Name the action is registered under
+    pub fn name(&self) -> String {
+        self.ident.to_string()
+    }
+
+    pub fn metadata_ident(&self) -> Ident {
+        format_ident!("{}_metadata", self.ident)
+    }
+
+    /// Whether the action is an `async fn`, whose `<action>_execute` returns a `BoxFuture`
+    pub fn is_async(&self) -> bool {
+        self.is_async
+    }
+
+    pub fn execute_ident(&self) -> Ident {
+        format_ident!("{}_execute", self.ident)
+    }
+
+    /// Generates `<action>_metadata`, which returns the `ActionDefinition`
+    pub fn metadata_fn(&self) -> TokenStream {
+        let meta_fn_name = self.metadata_ident();
+        let fn_name = self.name();
+        let description = &self.description;
+        let param_defs = self.params.iter().map(ParamInfo::definition);
+        let timeout = self.timeout_ms.map(|ms| quote! { .with_timeout(::std::time::Duration::from_millis(#ms)) });
+
+        quote! {
+            fn #meta_fn_name() -> ::lib_cpi::ActionDefinition {
+                ::lib_cpi::ActionDefinition::new(
+                    #fn_name,
+                    #description,
+                    vec![
+                        #(#param_defs),*
+                    ],
+                )
+                #timeout
+            }
+        }
+    }
+
+    /// Generates `<action>_execute`, which pulls every argument of the action out of
+    /// the parameter map by name, converts it to the argument's type and calls the action
+    ///
+    /// An argument of type `&ActionContext` is given the context of the call. For an
+    /// `async fn` the same happens inside the returned `BoxFuture`.
+    pub fn execute_fn(&self) -> TokenStream {
+        let fn_ident = &self.ident;
+        let execute_fn_name = self.execute_ident();
+
+        let mut extractions = Vec::new();
+        let mut call_args = Vec::new();
+        for param in &self.params {
+            let ident = &param.ident;
+            let name = &param.name;
+            let default = &param.default_value;
+            let required = param.required;
+            let (owned_type, pass) = owned_argument(&param.ty);
+            let extract = match param.param_type.is("Secret") {
+                true => quote! { extract_secret_arg },
+                false => quote! { extract_arg },
+            };
+            extractions.push(quote! {
+                let #ident: #owned_type = ::lib_cpi::validation::#extract(params, #name, #default, #required)?;
+            });
+            call_args.push(pass(quote! { #ident }));
+        }
+        let context = match self.context {
+            Some(position) => {
+                call_args.insert(position, quote! { cpi_context });
+                quote! { cpi_context }
+            }
+            None => quote! { _cpi_context },
+        };
+
+        let call = if self.has_receiver {
+            quote! { self.#fn_ident(#(#call_args),*) }
+        } else {
+            quote! { Self::#fn_ident(#(#call_args),*) }
+        };
+
+        // Constraints are checked first, so every extension reports them the same way
+        let check = self.params.iter().any(|param| !param.constraints.is_empty()).then(|| {
+            let metadata_fn = self.metadata_ident();
+            quote! { ::lib_cpi::validation::check_constraints(&Self::#metadata_fn(), params)?; }
+        });
+
+        if self.is_async {
+            return quote! {
+                fn #execute_fn_name<'cpi_call>(
+                    &'cpi_call self,
+                    params: &'cpi_call ::std::collections::HashMap<String, ::lib_cpi::serde_json::Value>,
+                    #context: &'cpi_call ::lib_cpi::ActionContext,
+                ) -> ::lib_cpi::BoxFuture<'cpi_call, ::lib_cpi::ActionResult> {
+                    Box::pin(async move {
+                        #check
+                        #(#extractions)*
+                        #call.await
+                    })
+                }
+            };
+        }
+
+        quote! {
+            fn #execute_fn_name(
+                &self,
+                params: &::std::collections::HashMap<String, ::lib_cpi::serde_json::Value>,
+                #context: &::lib_cpi::ActionContext,
+            ) -> ::lib_cpi::ActionResult {
+                #check
+                #(#extractions)*
+                #call
+            }
+        }
+    }
+}
+
+impl ParamInfo {
+    // Type and required-ness are inferred from the argument unless overridden
+    fn apply(&mut self, param_attr: ParamAttr) -> syn::Result<()> {
+        if let Some(description) = param_attr.description {
+            self.description = description;
+        }
+        if let Some(values) = param_attr.values {
+            self.param_type = ParamKind::Enum(values);
+        } else if let Some(param_type) = param_attr.param_type {
+            self.param_type = ParamKind::from_name(&param_type, &self.param_type);
+        }
+        // A parameter with a default is optional unless stated otherwise
+        if let Some(required) = param_attr.required.or(param_attr.default_value.as_ref().map(|_| false)) {
+            self.required = required;
+        }
+        if let Some(default) = &param_attr.default_value {
+            self.default_value = self.default_value_tokens(default)?;
+        }
+        for constraint in &param_attr.constraints {
+            let call = self.constraint_tokens(&constraint.key, &constraint.value)?;
+            self.constraints.push(call);
+        }
+        Ok(())
+    }
+
+    fn definition(&self) -> TokenStream {
+        let p_name = &self.name;
+        let p_desc = &self.description;
+        let p_type = self.param_type.tokens();
+        let p_required = self.required;
+        let default_value_code = &self.default_value;
+        let constraints = (!self.constraints.is_empty()).then(|| {
+            let constraints = &self.constraints;
+            quote! { .with_constraints(::lib_cpi::Constraints::default()#(#constraints)*) }
+        });
+
+        quote! {
+            {
+                let mut param = ::lib_cpi::ActionParameter::new(#p_name, #p_desc, #p_type, #p_required);
+                param.default_value = #default_value_code;
+                param #constraints
+            }
+        }
+    }
+
+    /// JSON default value of the parameter, as an `Option<Value>` expression
+    ///
+    /// Literals are turned into JSON and checked against the parameter type while
+    /// compiling. A string literal is the default itself for parameters whose values
+    /// are strings (String, Secret, Enum, ...) and JSON text for any other type, so
+    /// `"2.5"`, `"[1, 2]"`, `"{}"` and `"null"` all work. Any other expression is
+    /// serialized when the metadata is built.
+    fn default_value_tokens(&self, default: &Expr) -> syn::Result<TokenStream> {
+        let value = match default {
+            Expr::Lit(ExprLit { lit: Lit::Str(text), .. }) if !self.param_type.is_textual() => {
+                serde_json::from_str(&text.value()).map_err(|e| {
+                    syn::Error::new_spanned(text, format!("default `{}` is not valid JSON: {}", text.value(), e))
+                })?
+            }
+            _ => match literal_json(default) {
+                Some(Ok(value)) => value,
+                Some(Err(message)) => return Err(syn::Error::new_spanned(default, message)),
+                None => return Ok(quote! { Some(::lib_cpi::__private::default_value(&(#default))) }),
+            },
+        };
+
+        if !self.param_type.accepts(&value) {
+            return Err(syn::Error::new_spanned(
+                default,
+                format!("default `{}` is not a valid {}", value, self.param_type),
+            ));
+        }
+
+        let text = value.to_string();
+        Ok(quote! { Some(::lib_cpi::__private::json_literal(#text)) })
+    }
+
+    /// The `Constraints` builder call for a `#[param(key = value)]` constraint, checked while compiling
+    fn constraint_tokens(&self, key: &Ident, value: &Expr) -> syn::Result<TokenStream> {
+        let error = |message: &str| syn::Error::new_spanned(value, message);
+        match key.to_string().as_str() {
+            "min" | "max" => match literal_json(value) {
+                Some(Ok(serde_json::Value::Number(number))) => {
+                    let bound = number.as_f64().unwrap_or_default();
+                    Ok(quote! { .#key(#bound) })
+                }
+                _ => Err(error("expected a number literal")),
+            },
+            "pattern" => {
+                let Expr::Lit(ExprLit { lit: Lit::Str(pattern), .. }) = value else {
+                    return Err(error("expected a string literal"));
+                };
+                if let Err(e) = regex::Regex::new(&pattern.value()) {
+                    return Err(syn::Error::new_spanned(pattern, format!("invalid pattern: {}", e)));
+                }
+                Ok(quote! { .pattern(#pattern) })
+            }
+            "allowed" => {
+                let Expr::Array(array) = value else {
+                    return Err(error("expected an array of literals"));
+                };
+                let mut allowed = Vec::new();
+                for element in &array.elems {
+                    let value = match literal_json(element) {
+                        Some(Ok(value)) => value,
+                        Some(Err(message)) => return Err(syn::Error::new_spanned(element, message)),
+                        None => return Err(syn::Error::new_spanned(element, "expected a literal")),
+                    };
+                    if !self.param_type.accepts(&value) {
+                        let message = format!("allowed value `{}` is not a valid {}", value, self.param_type);
+                        return Err(syn::Error::new_spanned(element, message));
+                    }
+                    allowed.push(value.to_string());
+                }
+                Ok(quote! { .allowed([#(::lib_cpi::__private::json_literal(#allowed)),*]) })
+            }
+            _ => match value {
+                Expr::Lit(ExprLit { lit: Lit::Int(count), .. }) => {
+                    let count: usize = count.base10_parse()?;
+                    Ok(quote! { .#key(#count) })
+                }
+                _ => Err(error("expected an integer literal")),
+            },
+        }
+    }
+}
+
+/// The JSON value of a literal default, or `None` if `default` is not a literal
+fn literal_json(default: &Expr) -> Option<Result<serde_json::Value, String>> {
+    let (lit, negative) = match default {
+        Expr::Lit(ExprLit { lit, .. }) => (lit, false),
+        Expr::Unary(ExprUnary { op: UnOp::Neg(_), expr, .. }) => match &**expr {
+            Expr::Lit(ExprLit { lit, .. }) => (lit, true),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let sign = if negative { "-" } else { "" };
+    let value = match lit {
+        Lit::Str(text) if !negative => Ok(serde_json::Value::String(text.value())),
+        Lit::Bool(flag) if !negative => Ok(serde_json::Value::Bool(flag.value)),
+        Lit::Int(int) => {
+            let digits = format!("{}{}", sign, int.base10_digits());
+            serde_json::from_str(&digits).map_err(|e| format!("default `{}` is not a valid JSON number: {}", digits, e))
+        }
+        Lit::Float(float) => {
+            let digits = format!("{}{}", sign, float.base10_digits());
+            let number = digits.parse().ok().and_then(serde_json::Number::from_f64);
+            number
+                .map(serde_json::Value::Number)
+                .ok_or_else(|| format!("default `{}` is not a finite number", digits))
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+// Arguments are deserialized from the JSON parameters, so they must be owned types or shared references to them
+fn check_argument_type(ty: &Type) -> syn::Result<()> {
+    let unsupported = |message: &str| Err(syn::Error::new_spanned(ty, message));
+    match ty {
+        Type::Reference(reference) if reference.mutability.is_some() => {
+            unsupported("action arguments cannot be mutable references")
+        }
+        Type::Reference(reference) => check_argument_type(&reference.elem),
+        Type::ImplTrait(_) => unsupported("action arguments need a concrete type to be deserialized into"),
+        Type::Ptr(_) | Type::BareFn(_) | Type::TraitObject(_) | Type::Never(_) | Type::Infer(_) | Type::Macro(_) => {
+            unsupported("unsupported action argument type, arguments must be deserializable from JSON")
+        }
+        _ => Ok(()),
+    }
+}
+
+// `&ActionContext`, however the path to it is written
+fn is_context(ty: &Type) -> bool {
+    let Type::Reference(reference) = ty else {
+        return false;
+    };
+    match &*reference.elem {
+        Type::Path(path) => path.path.segments.last().is_some_and(|segment| segment.ident == "ActionContext"),
+        _ => false,
+    }
+}
+
+fn argument_name(arg: &FnArg) -> Option<String> {
+    match arg {
+        FnArg::Typed(pat_type) if is_context(&pat_type.ty) => None,
+        FnArg::Typed(pat_type) => match &*pat_type.pat {
+            Pat::Ident(pat_ident) => Some(pat_ident.ident.to_string()),
+            _ => None,
+        },
+        FnArg::Receiver(_) => None,
+    }
+}
+
+type PassArgument = fn(TokenStream) -> TokenStream;
+
+/// The type an argument is extracted as, and how the extracted value is passed on
+///
+/// Borrowed arguments are extracted as their owned counterpart (`&str` as `String`,
+/// `&[T]` as `Vec<T>`) and passed by reference, also inside an `Option`.
+fn owned_argument(ty: &Type) -> (TokenStream, PassArgument) {
+    if let Type::Reference(reference) = ty {
+        return (owned_type(&reference.elem), |value| quote! { &#value });
+    }
+    if let Some(Type::Reference(reference)) = option_inner(ty) {
+        let owned = owned_type(&reference.elem);
+        return match &*reference.elem {
+            Type::Path(path) if path.path.is_ident("str") => (quote! { Option<#owned> }, |value| quote! { #value.as_deref() }),
+            Type::Slice(_) => (quote! { Option<#owned> }, |value| quote! { #value.as_deref() }),
+            _ => (quote! { Option<#owned> }, |value| quote! { #value.as_ref() }),
+        };
+    }
+    (quote! { #ty }, |value| value)
+}
+
+fn owned_type(ty: &Type) -> TokenStream {
+    match ty {
+        Type::Path(path) if path.path.is_ident("str") => quote! { String },
+        Type::Slice(slice) => {
+            let elem = &slice.elem;
+            quote! { Vec<#elem> }
+        }
+        ty => quote! { #ty },
+    }
+}
+
+/// `T` for an `Option<T>`
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    first_type_argument(&segment.arguments)
+}
@@ -0,0 +1,196 @@
+//! Parsing of the `#[action(...)]` and `#[param(...)]` attribute arguments
+
+use std::time::Duration;
+use lib_cpi_common::parse_duration;
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Expr, ExprArray, ExprLit, Ident, Lit, LitStr, Meta, Token};
+
+use crate::types::PARAM_TYPES;
+
+/// One `key = value` pair of an attribute; unlike `MetaNameValue` the key may be a keyword such as `type`
+pub(crate) struct KeyValue {
+    pub key: Ident,
+    pub value: Expr,
+}
+
+impl Parse for KeyValue {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key = Ident::parse_any(input)?;
+        input.parse::<Token![=]>()?;
+        let value = input.parse()?;
+        Ok(KeyValue { key, value })
+    }
+}
+
+/// Parameter attribute structure
+#[derive(Default)]
+pub(crate) struct ParamAttr {
+    pub name: Option<LitStr>,
+    pub description: Option<String>,
+    pub param_type: Option<String>,
+    // The values of an Enum parameter
+    pub values: Option<Vec<String>>,
+    pub required: Option<bool>,
+    // A JSON literal string, or any Rust expression that serializes to the default
+    pub default_value: Option<Expr>,
+    // `min = 1`, `pattern = "..."`, ..., named like the fields of `Constraints`
+    pub constraints: Vec<KeyValue>,
+}
+
+/// Keys of `#[param]` that set a field of `Constraints`
+pub(crate) const CONSTRAINTS: &[&str] =
+    &["min", "max", "pattern", "min_length", "max_length", "allowed", "min_items", "max_items"];
+
+impl Parse for ParamAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut attrs = ParamAttr::default();
+
+        let mut type_lit = None;
+        let keys = [&["name", "description", "type", "values", "required", "default"], CONSTRAINTS].concat();
+        for pair in parse_pairs(input, &keys)? {
+            match pair.key.to_string().as_str() {
+                "name" => attrs.name = Some(lit_str(&pair.value)?),
+                "description" => attrs.description = Some(lit_str(&pair.value)?.value()),
+                "type" => {
+                    let param_type = lit_str(&pair.value)?;
+                    if !PARAM_TYPES.contains(&param_type.value().as_str()) {
+                        return Err(syn::Error::new_spanned(
+                            param_type,
+                            format!("unsupported parameter type, expected one of {}", PARAM_TYPES.join(", ")),
+                        ));
+                    }
+                    attrs.param_type = Some(param_type.value());
+                    type_lit = Some(param_type);
+                }
+                "values" => attrs.values = Some(lit_str_array(&pair.value)?),
+                "required" => attrs.required = Some(lit_bool(&pair.value)?),
+                "default" => attrs.default_value = Some(pair.value),
+                _ => attrs.constraints.push(pair),
+            }
+        }
+
+        match (&type_lit, &attrs.values) {
+            (Some(param_type), Some(_)) if param_type.value() != "Enum" => Err(syn::Error::new_spanned(
+                param_type,
+                format!("`values` makes the parameter an Enum, it cannot also be of type {}", param_type.value()),
+            )),
+            (Some(param_type), None) if param_type.value() == "Enum" => {
+                Err(syn::Error::new_spanned(param_type, "an Enum parameter needs its `values = [\"...\", ...]`"))
+            }
+            _ => Ok(attrs),
+        }
+    }
+}
+
+/// Action attribute structure
+#[derive(Default)]
+pub(crate) struct ActionAttr {
+    pub description: Option<String>,
+    /// `timeout = "10m"`, in milliseconds
+    pub timeout_ms: Option<u64>,
+}
+
+impl Parse for ActionAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut attr = ActionAttr::default();
+
+        for pair in parse_pairs(input, &["description", "timeout"])? {
+            match pair.key.to_string().as_str() {
+                "description" => attr.description = Some(lit_str(&pair.value)?.value()),
+                "timeout" => attr.timeout_ms = Some(timeout_millis(&pair.value)?),
+                _ => unreachable!("parse_pairs only returns allowed keys"),
+            }
+        }
+
+        Ok(attr)
+    }
+}
+
+/// A timeout given as a duration string such as `"90s"` or `"1h30m"`, or as a
+/// number of seconds; read with the `parse_duration` the runtime uses
+fn timeout_millis(value: &Expr) -> syn::Result<u64> {
+    let invalid = || syn::Error::new_spanned(value, "expected a timeout such as \"90s\", \"10m\" or \"1h30m\"");
+    let timeout = match value {
+        Expr::Lit(ExprLit { lit: Lit::Int(int), .. }) => Duration::from_secs(int.base10_parse::<u64>()?),
+        Expr::Lit(ExprLit { lit: Lit::Str(text), .. }) => parse_duration(&text.value()).map_err(|_| invalid())?,
+        _ => return Err(invalid()),
+    };
+    match u64::try_from(timeout.as_millis()) {
+        Ok(0) => Err(syn::Error::new_spanned(value, "the timeout must be at least 1ms")),
+        Ok(millis) => Ok(millis),
+        Err(_) => Err(syn::Error::new_spanned(value, "the timeout is too long")),
+    }
+}
+
+/// Parses `key = value, ...`, refusing keys outside `allowed` and keys given twice
+pub(crate) fn parse_pairs(input: ParseStream, allowed: &[&str]) -> syn::Result<Vec<KeyValue>> {
+    let pairs = Punctuated::<KeyValue, Token![,]>::parse_terminated(input)?;
+
+    let mut seen: Vec<String> = Vec::new();
+    for pair in &pairs {
+        let key = pair.key.to_string();
+        if !allowed.contains(&key.as_str()) {
+            let expected: Vec<String> = allowed.iter().map(|key| format!("`{}`", key)).collect();
+            return Err(syn::Error::new_spanned(
+                &pair.key,
+                format!("unknown key `{}`, expected {}", key, expected.join(", ")),
+            ));
+        }
+        if seen.contains(&key) {
+            return Err(syn::Error::new_spanned(&pair.key, format!("`{}` is given more than once", key)));
+        }
+        seen.push(key);
+    }
+
+    Ok(pairs.into_iter().collect())
+}
+
+fn lit_str(value: &Expr) -> syn::Result<LitStr> {
+    match value {
+        Expr::Lit(ExprLit { lit: Lit::Str(lit_str), .. }) => Ok(lit_str.clone()),
+        _ => Err(syn::Error::new_spanned(value, "expected a string literal")),
+    }
+}
+
+fn lit_str_array(value: &Expr) -> syn::Result<Vec<String>> {
+    let expected = || syn::Error::new_spanned(value, "expected an array of string literals");
+    let Expr::Array(ExprArray { elems, .. }) = value else {
+        return Err(expected());
+    };
+    let values = elems.iter().map(lit_str).map(|lit| lit.map(|lit| lit.value())).collect::<syn::Result<Vec<_>>>();
+    match values {
+        Ok(values) if !values.is_empty() => Ok(values),
+        _ => Err(expected()),
+    }
+}
+
+fn lit_bool(value: &Expr) -> syn::Result<bool> {
+    match value {
+        Expr::Lit(ExprLit { lit: Lit::Bool(lit_bool), .. }) => Ok(lit_bool.value),
+        _ => Err(syn::Error::new_spanned(value, "expected `true` or `false`")),
+    }
+}
+
+/// Whether `attr` is `#[name]`, however the macro was imported (`#[param]`, `#[lib_cpi::macros::param]`, ...)
+pub(crate) fn is_attr(attr: &Attribute, name: &str) -> bool {
+    attr.path().segments.last().is_some_and(|segment| segment.ident == name)
+}
+
+/// Parses the arguments of an attribute that was written inside an item, where
+/// both `#[name]` and `#[name(...)]` are allowed
+pub(crate) fn parse_args<T: Parse + Default>(attr: &Attribute) -> syn::Result<T> {
+    match &attr.meta {
+        Meta::Path(_) => Ok(T::default()),
+        Meta::List(list) => list.parse_args(),
+        Meta::NameValue(_) => Err(syn::Error::new_spanned(attr, "expected `#[name(key = value, ...)]`")),
+    }
+}
+
+/// Removes every `#[name]` attribute from `attrs` and returns them in order
+pub(crate) fn take_attrs(attrs: &mut Vec<Attribute>, name: &str) -> Vec<Attribute> {
+    let (taken, kept) = std::mem::take(attrs).into_iter().partition(|attr| is_attr(attr, name));
+    *attrs = kept;
+    taken
+}
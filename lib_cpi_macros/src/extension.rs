@@ -0,0 +1,233 @@
+//! `#[cpi_extension(...)]`: actions, settings and the `CpiExtension` (or
+//! `AsyncCpiExtension`) impl of a whole impl block
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{Attribute, Expr, ExprLit, Ident, ImplItem, ItemImpl, Lit, Type};
+
+use crate::action::ActionInfo;
+use crate::action_methods;
+use crate::attrs::{parse_args, parse_pairs, take_attrs};
+
+/// Arguments of `#[cpi_extension]`; without `name` and `provider_type` only the
+/// actions are generated and `CpiExtension` is left to the author
+#[derive(Default)]
+pub(crate) struct ExtensionAttr {
+    name: Option<Expr>,
+    provider_type: Option<Expr>,
+    version: Option<Expr>,
+}
+
+impl Parse for ExtensionAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut attr = ExtensionAttr::default();
+
+        for pair in parse_pairs(input, &["name", "provider_type", "version"])? {
+            match pair.key.to_string().as_str() {
+                "name" => attr.name = Some(pair.value),
+                "provider_type" => attr.provider_type = Some(pair.value),
+                "version" => attr.version = Some(pair.value),
+                _ => unreachable!("parse_pairs only returns allowed keys"),
+            }
+        }
+
+        Ok(attr)
+    }
+}
+
+/// Where `default_settings` come from
+enum Settings {
+    /// `#[default_settings] fn settings(&self) -> impl Serialize`
+    Method { ident: Ident, has_receiver: bool },
+    /// `#[default_settings] const SETTINGS: &str = r#"{ ... }"#;`, JSON text
+    JsonConst(Ident),
+    /// `#[default_settings] const SETTINGS: T = ...;` for any serializable `T`
+    Const(Ident),
+}
+
+pub(crate) fn expand(attr: ExtensionAttr, mut input: ItemImpl) -> syn::Result<TokenStream> {
+    if let Some((_, path, _)) = &input.trait_ {
+        return Err(syn::Error::new_spanned(path, "#[cpi_extension] goes on an inherent impl block"));
+    }
+
+    let mut actions = Vec::new();
+    let mut settings = None;
+    let mut test_install = None;
+    for item in &mut input.items {
+        match item {
+            ImplItem::Fn(method) => {
+                if let Some(action_attr) = single_attr(&mut method.attrs, "action")? {
+                    let action_attr = parse_args(&action_attr)?;
+                    actions.push(ActionInfo::new(action_attr, &mut method.attrs, &method.sig)?);
+                }
+                if let Some(attr) = single_attr(&mut method.attrs, "default_settings")? {
+                    check_unset(&settings, &attr, "default_settings")?;
+                    settings = Some(Settings::Method {
+                        ident: method.sig.ident.clone(),
+                        has_receiver: method.sig.receiver().is_some(),
+                    });
+                }
+                if let Some(attr) = single_attr(&mut method.attrs, "test_install")? {
+                    check_unset(&test_install, &attr, "test_install")?;
+                    test_install = Some((method.sig.ident.clone(), method.sig.asyncness.is_some()));
+                }
+            }
+            ImplItem::Const(constant) => {
+                if let Some(attr) = single_attr(&mut constant.attrs, "default_settings")? {
+                    check_unset(&settings, &attr, "default_settings")?;
+                    settings = Some(if is_str(&constant.ty) {
+                        check_json_object(&constant.expr)?;
+                        Settings::JsonConst(constant.ident.clone())
+                    } else {
+                        Settings::Const(constant.ident.clone())
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for action in &actions {
+        input.items.push(ImplItem::Verbatim(action.metadata_fn()));
+        input.items.push(ImplItem::Verbatim(action.execute_fn()));
+    }
+
+    let extension_impl = match (&attr.name, &attr.provider_type) {
+        (Some(name), Some(provider_type)) => {
+            extension_impl(&input, name, provider_type, &attr, &actions, settings, test_install)
+        }
+        (None, None) if attr.version.is_none() => TokenStream::new(),
+        _ => {
+            return Err(syn::Error::new(
+                proc_macro2::Span::call_site(),
+                "#[cpi_extension] needs both `name` and `provider_type` to implement CpiExtension",
+            ));
+        }
+    };
+
+    Ok(quote! {
+        #input
+
+        #extension_impl
+    })
+}
+
+fn extension_impl(
+    input: &ItemImpl,
+    name: &Expr,
+    provider_type: &Expr,
+    attr: &ExtensionAttr,
+    actions: &[ActionInfo],
+    settings: Option<Settings>,
+    test_install: Option<(Ident, bool)>,
+) -> TokenStream {
+    let (impl_generics, _, where_clause) = input.generics.split_for_impl();
+    let self_ty = &input.self_ty;
+
+    let asynchronous = actions.iter().any(ActionInfo::is_async) || test_install.as_ref().is_some_and(|(_, is_async)| *is_async);
+    let action_names: Vec<String> = actions.iter().map(ActionInfo::name).collect();
+    let action_methods = action_methods(&action_names, asynchronous);
+
+    let default_settings = settings.map(|settings| {
+        let value = match settings {
+            Settings::Method { ident, has_receiver: true } => quote! { ::lib_cpi::__private::settings(&self.#ident()) },
+            Settings::Method { ident, has_receiver: false } => quote! { ::lib_cpi::__private::settings(&Self::#ident()) },
+            Settings::JsonConst(ident) => quote! { ::lib_cpi::__private::settings_from_json(Self::#ident) },
+            Settings::Const(ident) => quote! { ::lib_cpi::__private::settings(&Self::#ident) },
+        };
+        quote! {
+            fn default_settings(&self) -> ::std::collections::HashMap<String, ::lib_cpi::serde_json::Value> {
+                #value
+            }
+        }
+    });
+
+    let test_install = test_install.map(|(ident, is_async)| match (asynchronous, is_async) {
+        (true, true) => quote! {
+            fn test_install(&self) -> ::lib_cpi::BoxFuture<'_, ::lib_cpi::ActionResult> {
+                Box::pin(self.#ident())
+            }
+        },
+        (true, false) => quote! {
+            fn test_install(&self) -> ::lib_cpi::BoxFuture<'_, ::lib_cpi::ActionResult> {
+                Box::pin(async move { self.#ident() })
+            }
+        },
+        _ => quote! {
+            fn test_install(&self) -> ::lib_cpi::ActionResult {
+                self.#ident()
+            }
+        },
+    });
+
+    let version = attr.version.as_ref().map(|version| {
+        quote! {
+            fn version(&self) -> String {
+                (#version).to_string()
+            }
+        }
+    });
+
+    let extension_trait = match asynchronous {
+        true => quote! { ::lib_cpi::AsyncCpiExtension },
+        false => quote! { ::lib_cpi::CpiExtension },
+    };
+
+    quote! {
+        impl #impl_generics #extension_trait for #self_ty #where_clause {
+            fn name(&self) -> &str {
+                #name
+            }
+
+            fn provider_type(&self) -> &str {
+                #provider_type
+            }
+
+            #action_methods
+
+            #default_settings
+
+            #test_install
+
+            #version
+        }
+    }
+}
+
+/// Removes `#[name]` from `attrs`, refusing it more than once on the same item
+fn single_attr(attrs: &mut Vec<Attribute>, name: &str) -> syn::Result<Option<Attribute>> {
+    let mut found = take_attrs(attrs, name).into_iter();
+    let first = found.next();
+    match found.next() {
+        Some(second) => Err(syn::Error::new_spanned(second, format!("#[{}] is given more than once", name))),
+        None => Ok(first),
+    }
+}
+
+// Only one item of the block may be marked `#[name]`
+fn check_unset<T>(existing: &Option<T>, attr: &Attribute, name: &str) -> syn::Result<()> {
+    match existing {
+        Some(_) => Err(syn::Error::new_spanned(attr, format!("only one item may be marked #[{}]", name))),
+        None => Ok(()),
+    }
+}
+
+fn is_str(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) => matches!(&*reference.elem, Type::Path(path) if path.path.is_ident("str")),
+        _ => false,
+    }
+}
+
+// JSON settings written as a literal are checked while compiling
+fn check_json_object(expr: &Expr) -> syn::Result<()> {
+    let Expr::Lit(ExprLit { lit: Lit::Str(text), .. }) = expr else {
+        return Ok(());
+    };
+    match serde_json::from_str::<serde_json::Value>(&text.value()) {
+        Ok(serde_json::Value::Object(_)) => Ok(()),
+        Ok(_) => Err(syn::Error::new_spanned(text, "default settings must be a JSON object")),
+        Err(e) => Err(syn::Error::new_spanned(text, format!("invalid JSON in default settings: {}", e))),
+    }
+}
@@ -1,324 +1,157 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::{quote, format_ident};
-use syn::{parse_macro_input, ItemFn, FnArg, Pat, Expr, ExprLit, Lit, parse::Parse, parse::ParseStream, Token};
 use syn::punctuated::Punctuated;
-use syn::{Attribute, Meta, MetaNameValue, Path, Ident};
-use std::collections::HashMap;
+use syn::{parse_macro_input, Ident, ItemFn, ItemImpl, Token};
 
-/// Parameter attribute structure
-struct ParamAttr {
-    name: Option<String>,
-    description: Option<String>,
-    param_type: Option<String>,
-    required: Option<bool>,
-    default_value: Option<String>,
-}
-
-impl Parse for ParamAttr {
-    fn parse(input: ParseStream) -> syn::Result<Self> {
-        let mut attrs = ParamAttr {
-            name: None,
-            description: None,
-            param_type: None,
-            required: None,
-            default_value: None,
-        };
-        
-        let content;
-        syn::parenthesized!(content in input);
-        let pairs = Punctuated::<MetaNameValue, Token![,]>::parse_terminated(&content)?;
-        
-        for pair in pairs {
-            let name_ident = pair.path.get_ident().unwrap().to_string();
-            
-            if let Expr::Lit(ExprLit { lit, .. }) = pair.value {
-                match name_ident.as_str() {
-                    "name" => {
-                        if let Lit::Str(lit_str) = lit {
-                            attrs.name = Some(lit_str.value());
-                        }
-                    },
-                    "description" => {
-                        if let Lit::Str(lit_str) = lit {
-                            attrs.description = Some(lit_str.value());
-                        }
-                    },
-                    "type" => {
-                        if let Lit::Str(lit_str) = lit {
-                            attrs.param_type = Some(lit_str.value());
-                        }
-                    },
-                    "required" => {
-                        if let Lit::Bool(lit_bool) = lit {
-                            attrs.required = Some(lit_bool.value);
-                        }
-                    },
-                    "default" => {
-                        if let Lit::Str(lit_str) = lit {
-                            attrs.default_value = Some(lit_str.value());
-                        }
-                    },
-                    _ => {}
-                }
-            }
-        }
-        
-        Ok(attrs)
-    }
-}
-
-/// Action attribute structure
-struct ActionAttr {
-    description: Option<String>,
-}
-
-impl Parse for ActionAttr {
-    fn parse(input: ParseStream) -> syn::Result<Self> {
-        let mut attr = ActionAttr {
-            description: None,
-        };
-        
-        let content;
-        syn::parenthesized!(content in input);
-        let pairs = Punctuated::<MetaNameValue, Token![,]>::parse_terminated(&content)?;
-        
-        for pair in pairs {
-            let name_ident = pair.path.get_ident().unwrap().to_string();
-            
-            if name_ident == "description" {
-                if let Expr::Lit(ExprLit { lit: Lit::Str(lit_str), .. }) = pair.value {
-                    attr.description = Some(lit_str.value());
-                }
-            }
-        }
-        
-        Ok(attr)
-    }
-}
-
-/// Stores parameter metadata during macro processing
-struct ParamInfo {
-    name: String,
-    description: String,
-    param_type: String,
-    required: bool,
-    default_value: Option<String>,
-}
-
-impl Default for ParamInfo {
-    fn default() -> Self {
-        Self {
-            name: String::new(),
-            description: String::new(),
-            param_type: "String".to_string(),
-            required: true,
-            default_value: None,
-        }
-    }
-}
+mod action;
+mod attrs;
+mod extension;
+mod types;
 
-/// Global storage for action metadata during compilation
-thread_local! {
-    static ACTION_METADATA: std::cell::RefCell<HashMap<String, (String, Vec<ParamInfo>)>> = 
-        std::cell::RefCell::new(HashMap::new());
-}
+use action::ActionInfo;
+use attrs::{ActionAttr, is_attr};
 
 /// Macro to annotate extension action functions with metadata
+///
+/// Generates `<action>_metadata`, returning the `ActionDefinition`, and
+/// `<action>_execute`, which extracts the arguments from the parameter map by
+/// name, converts them to their Rust types and calls the action. Parameters are
+/// described by `#[param]` attributes placed below `#[action]`; an argument of type
+/// `&ActionContext` is given the context of the call instead.
 /// 
 /// Usage: #[action(description = "Description of the action")]
 #[proc_macro_attribute]
 pub fn action(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ItemFn);
-    let fn_name = input.sig.ident.to_string();
-    
-    // Parse the action attribute
+    let mut input = parse_macro_input!(item as ItemFn);
     let action_attr = parse_macro_input!(attr as ActionAttr);
-    let description = action_attr.description
-        .unwrap_or_else(|| format!("Action {}", fn_name));
     
-    // Extract parameter names for metadata initialization
-    let mut param_names = Vec::new();
-    for arg in &input.sig.inputs {
-        if let FnArg::Typed(pat_type) = arg {
-            if let Pat::Ident(pat_ident) = &*pat_type.pat {
-                let param_name = &pat_ident.ident;
-                
-                // Skip 'self' parameter
-                if param_name == "self" || param_name == "&self" {
-                    continue;
-                }
-                
-                param_names.push(param_name.to_string());
-            }
-        }
-    }
-    
-    // Initialize metadata for this function
-    ACTION_METADATA.with(|metadata| {
-        let mut map = metadata.borrow_mut();
-        let params_vec = Vec::new();
-        map.insert(fn_name.clone(), (description, params_vec));
-        
-        // Initialize with empty parameter entries
-        if let Some((_, params)) = map.get_mut(&fn_name) {
-            for name in param_names {
-                let param_info = ParamInfo {
-                    name,
-                    ..Default::default()
-                };
-                params.push(param_info);
-            }
-        }
-    });
+    let action = match ActionInfo::new(action_attr, &mut input.attrs, &input.sig) {
+        Ok(action) => action,
+        Err(error) => return error.to_compile_error().into(),
+    };
+    let metadata_fn = action.metadata_fn();
+    let execute_fn = action.execute_fn();
     
-    // Return the function unchanged
     quote! {
         #input
+        
+        #metadata_fn
+        
+        #execute_fn
     }.into()
 }
 
 /// Macro to define parameter metadata for actions
 ///
+/// Only meaningful together with `#[action]`, which reads and removes it.
+///
 /// Usage: #[param(name = "param_name", description = "Description", type = "String", required = true, default = "value")]
 #[proc_macro_attribute]
 pub fn param(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ItemFn);
-    let fn_name = input.sig.ident.to_string();
-    
-    // Parse the parameter metadata
-    let param_attr = parse_macro_input!(attr as ParamAttr);
-    let param_name = param_attr.name.unwrap_or_default();
-    let description = param_attr.description.unwrap_or_default();
-    let param_type = param_attr.param_type.unwrap_or_else(|| "String".to_string());
-    let required = param_attr.required.unwrap_or(true);
-    let default_value = param_attr.default_value;
-    
-    // Update the parameter metadata
-    ACTION_METADATA.with(|metadata| {
-        let mut map = metadata.borrow_mut();
-        if let Some((_, params)) = map.get_mut(&fn_name) {
-            for param in params.iter_mut() {
-                if param.name == param_name {
-                    param.description = description.clone();
-                    param.param_type = param_type.clone();
-                    param.required = required;
-                    param.default_value = default_value.clone();
-                    break;
-                }
-            }
-        }
-    });
+    let mut input = parse_macro_input!(item as ItemFn);
+    let attr = proc_macro2::TokenStream::from(attr);
+    
+    // Written above #[action], so it expanded first: move it below, where #[action] finds it
+    let Some(position) = input.attrs.iter().position(|a| is_attr(a, "action")) else {
+        return syn::Error::new(proc_macro2::Span::call_site(), "#[param] must be used together with #[action]")
+            .to_compile_error()
+            .into();
+    };
+    input.attrs.insert(position + 1, syn::parse_quote! { #[param(#attr)] });
     
-    // Return the function unchanged
     quote! {
         #input
     }.into()
 }
 
-/// Macro to generate the metadata function for an action
-/// This should be called after all param annotations
+/// No longer needed: `#[action]` generates the metadata itself
+///
+/// Kept so existing extensions keep compiling.
 #[proc_macro_attribute]
 pub fn generate_metadata(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ItemFn);
-    let fn_name = input.sig.ident.to_string();
-    let fn_ident = &input.sig.ident;
-    
-    // Generate metadata function name
-    let meta_fn_name = format_ident!("{}_metadata", fn_name);
-    
-    // Generate the parameter definitions
-    let param_defs = ACTION_METADATA.with(|metadata| {
-        let map = metadata.borrow();
-        let mut defs = Vec::new();
-        
-        if let Some((_, params)) = map.get(&fn_name) {
-            for param in params {
-                let p_name = &param.name;
-                let p_desc = &param.description;
-                let p_type = match param.param_type.as_str() {
-                    "String" => quote! { ParamType::String },
-                    "Integer" => quote! { ParamType::Integer },
-                    "Boolean" => quote! { ParamType::Boolean },
-                    "Object" => quote! { ParamType::Object },
-                    "Array" => quote! { ParamType::Array },
-                    _ => quote! { ParamType::String },
-                };
-                let p_required = param.required;
-                
-                let default_value_code = if let Some(default) = &param.default_value {
-                    if param.param_type == "String" {
-                        quote! { Some(json!(#default)) }
-                    } else if param.param_type == "Integer" {
-                        let int_value: i64 = default.parse().unwrap_or(0);
-                        quote! { Some(json!(#int_value)) }
-                    } else if param.param_type == "Boolean" {
-                        let bool_value: bool = default.parse().unwrap_or(false);
-                        quote! { Some(json!(#bool_value)) }
-                    } else {
-                        quote! { None }
-                    }
-                } else {
-                    quote! { None }
-                };
-                
-                defs.push(quote! {
-                    ActionParameter {
-                        name: #p_name.to_string(),
-                        description: #p_desc.to_string(),
-                        param_type: #p_type,
-                        required: #p_required,
-                        default_value: #default_value_code,
-                    }
-                });
-            }
-        }
-        
-        defs
-    });
-    
-    // Get the description
-    let description = ACTION_METADATA.with(|metadata| {
-        let map = metadata.borrow();
-        if let Some((desc, _)) = map.get(&fn_name) {
-            desc.clone()
-        } else {
-            format!("Action {}", fn_name)
-        }
-    });
-    
-    // Final output - function + metadata function
-    let result = quote! {
-        #input
-        
-        fn #meta_fn_name() -> ActionDefinition {
-            ActionDefinition {
-                name: #fn_name.to_string(),
-                description: #description.to_string(),
-                parameters: vec![
-                    #(#param_defs),*
-                ],
-            }
-        }
-    };
+    item
+}
+
+/// Attribute for an inherent impl block that turns every `#[action]` method in it
+/// into an action and implements `CpiExtension` for the type
+///
+/// All metadata is read from the methods of the block, so it is scoped to the type.
+/// `default_settings` come from a method or const marked `#[default_settings]`: any
+/// value serializing to a JSON object, or a `&str` const holding JSON text. A method
+/// marked `#[test_install]` implements `test_install`. Without `name` and
+/// `provider_type` only the actions are generated, for use with `register_actions!`.
+/// If an action or the `#[test_install]` method is an `async fn`, the block
+/// implements `AsyncCpiExtension` instead. Its sync methods then run when the
+/// returned future is first polled, on the thread polling it, so they must not
+/// block; slow work belongs in an `async fn` awaiting a `BlockingPool`.
+///
+/// Usage: #[cpi_extension(name = "vbox", provider_type = "hypervisor", version = env!("CARGO_PKG_VERSION"))]
+#[proc_macro_attribute]
+pub fn cpi_extension(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemImpl);
+    let attr = parse_macro_input!(attr as extension::ExtensionAttr);
     
-    result.into()
+    match extension::expand(attr, input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
 }
 
 /// Macro to register action functions with the CpiExtension trait
 ///
+/// Implements `list_actions`, `get_action_definition`, `execute_action` and
+/// `execute_action_with_context` for the listed actions, which must carry
+/// `#[action]`. An `async fn` action is waited for on the calling thread.
+///
 /// Usage: register_actions![action1, action2, ...]
 #[proc_macro]
 pub fn register_actions(input: TokenStream) -> TokenStream {
-    let input_str = input.to_string();
-    let action_names: Vec<String> = input_str
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
+    let idents = parse_macro_input!(input with Punctuated::<Ident, Token![,]>::parse_terminated);
+    registered_actions(&idents, false).into()
+}
+
+/// `register_actions!` for an `AsyncCpiExtension` impl, whose `execute_action`
+/// returns a future; the listed actions may be sync or `async fn`
+///
+/// Sync actions run when the future is first polled, on the polling thread, and
+/// must not block it.
+///
+/// Usage: register_async_actions![action1, action2, ...]
+#[proc_macro]
+pub fn register_async_actions(input: TokenStream) -> TokenStream {
+    let idents = parse_macro_input!(input with Punctuated::<Ident, Token![,]>::parse_terminated);
+    registered_actions(&idents, true).into()
+}
+
+fn registered_actions(idents: &Punctuated<Ident, Token![,]>, asynchronous: bool) -> proc_macro2::TokenStream {
+    let mut action_names: Vec<String> = Vec::new();
+    let mut errors = Vec::new();
+    for ident in idents {
+        let name = ident.to_string();
+        if action_names.contains(&name) {
+            let error = syn::Error::new_spanned(ident, format!("action `{}` is registered more than once", name));
+            errors.push(error.to_compile_error());
+            continue;
+        }
+        action_names.push(name);
+    }
     
+    // The methods are still generated, so the impl does not also report them missing
+    let methods = action_methods(&action_names, asynchronous);
+    quote! {
+        #(#errors)*
+        #methods
+    }
+}
+
+/// `list_actions`, `get_action_definition`, `execute_action` and
+/// `execute_action_with_context` of a `CpiExtension` impl, or an `AsyncCpiExtension`
+/// one if `asynchronous`, dispatching to the functions `#[action]` generates
+fn action_methods(action_names: &[String], asynchronous: bool) -> proc_macro2::TokenStream {
     let action_strings = action_names.iter().map(|s| s.as_str());
     let action_meta_fns = action_names.iter().map(|name| format_ident!("{}_metadata", name));
+    let action_execute_fns = action_names.iter().map(|name| format_ident!("{}_execute", name));
     
     let list_actions_impl = quote! {
         fn list_actions(&self) -> Vec<String> {
@@ -330,12 +163,12 @@ pub fn register_actions(input: TokenStream) -> TokenStream {
     
     let get_action_def_match_arms = action_names.iter().zip(action_meta_fns).map(|(name, meta_fn)| {
         quote! {
-            #name => Some(#meta_fn()),
+            #name => Some(Self::#meta_fn()),
         }
     });
     
     let get_action_def_impl = quote! {
-        fn get_action_definition(&self, action: &str) -> Option<ActionDefinition> {
+        fn get_action_definition(&self, action: &str) -> Option<::lib_cpi::ActionDefinition> {
             match action {
                 #(#get_action_def_match_arms)*
                 _ => None,
@@ -343,13 +176,78 @@ pub fn register_actions(input: TokenStream) -> TokenStream {
         }
     };
     
-    let final_code = quote! {
+    // `_execute` of an `async fn` returns a future, which the sync trait waits for.
+    // The async trait defers the call until it is polled, so sync actions do not run
+    // before the caller awaits them
+    let execute_match_arms = action_names.iter().zip(action_execute_fns).map(|(name, execute_fn)| match asynchronous {
+        true => quote! {
+            #name => Box::pin(async move {
+                ::lib_cpi::__private::ActionOutput::into_future(self.#execute_fn(params, context)).await
+            }),
+        },
+        false => quote! {
+            #name => ::lib_cpi::__private::ActionOutput::wait(self.#execute_fn(params, context)),
+        },
+    });
+    let unknown = quote! {
+        Err(::lib_cpi::CpiError::unsupported(format!("Unknown action: {}", action)))
+    };
+    
+    // Without a context from the caller, actions are given a fresh one
+    let execute_action_impl = match asynchronous {
+        true => quote! {
+            fn execute_action<'cpi_call>(
+                &'cpi_call self,
+                action: &'cpi_call str,
+                params: &'cpi_call ::std::collections::HashMap<String, ::lib_cpi::serde_json::Value>,
+            ) -> ::lib_cpi::BoxFuture<'cpi_call, ::lib_cpi::ActionResult> {
+                Box::pin(async move {
+                    let context = ::lib_cpi::ActionContext::new();
+                    ::lib_cpi::AsyncCpiExtension::execute_action_with_context(self, action, params, &context).await
+                })
+            }
+
+            fn execute_action_with_context<'cpi_call>(
+                &'cpi_call self,
+                action: &'cpi_call str,
+                params: &'cpi_call ::std::collections::HashMap<String, ::lib_cpi::serde_json::Value>,
+                context: &'cpi_call ::lib_cpi::ActionContext,
+            ) -> ::lib_cpi::BoxFuture<'cpi_call, ::lib_cpi::ActionResult> {
+                match action {
+                    #(#execute_match_arms)*
+                    _ => Box::pin(::std::future::ready(#unknown)),
+                }
+            }
+        },
+        false => quote! {
+            fn execute_action(
+                &self,
+                action: &str,
+                params: &::std::collections::HashMap<String, ::lib_cpi::serde_json::Value>,
+            ) -> ::lib_cpi::ActionResult {
+                let context = ::lib_cpi::ActionContext::new();
+                ::lib_cpi::CpiExtension::execute_action_with_context(self, action, params, &context)
+            }
+
+            fn execute_action_with_context(
+                &self,
+                action: &str,
+                params: &::std::collections::HashMap<String, ::lib_cpi::serde_json::Value>,
+                context: &::lib_cpi::ActionContext,
+            ) -> ::lib_cpi::ActionResult {
+                match action {
+                    #(#execute_match_arms)*
+                    _ => #unknown,
+                }
+            }
+        },
+    };
+    
+    quote! {
         #list_actions_impl
         
         #get_action_def_impl
         
-        // NOTE: The execute_action method needs to be implemented manually
-    };
-    
-    final_code.into()
+        #execute_action_impl
+    }
 }
\ No newline at end of file
@@ -0,0 +1,146 @@
+//! The `ParamType` of a parameter while expanding: inferred from the argument's
+//! Rust type or named in `#[param(type = "...")]`
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use serde_json::Value;
+use syn::{GenericArgument, PathArguments, Type};
+
+/// Parameter types that `#[param(type = "...")]` accepts
+pub(crate) const PARAM_TYPES: &[&str] = &[
+    "String", "Integer", "Number", "Float", "Boolean", "Enum", "Secret", "Duration", "ByteSize", "Array", "Object", "Any",
+];
+
+#[derive(Clone, PartialEq)]
+pub(crate) enum ParamKind {
+    /// A type without details, by its name in `PARAM_TYPES`
+    Named(String),
+    Enum(Vec<String>),
+    Array(Box<ParamKind>),
+}
+
+impl ParamKind {
+    pub fn named(name: &str) -> Self {
+        ParamKind::Named(name.to_string())
+    }
+
+    pub fn is(&self, name: &str) -> bool {
+        matches!(self, ParamKind::Named(named) if named == name)
+    }
+
+    /// Whether values of this type are strings, so a string literal default is the value itself
+    pub fn is_textual(&self) -> bool {
+        matches!(self, ParamKind::Enum(_)) || ["String", "Secret", "Duration", "ByteSize"].iter().any(|name| self.is(name))
+    }
+
+    /// The kind `#[param(type = name)]` asks for, keeping what was inferred for the
+    /// items of an array
+    pub fn from_name(name: &str, inferred: &ParamKind) -> Self {
+        match (name, inferred) {
+            ("Array", ParamKind::Array(_)) => inferred.clone(),
+            ("Array", _) => ParamKind::Array(Box::new(ParamKind::named("Any"))),
+            _ => ParamKind::named(name),
+        }
+    }
+
+    /// Whether a literal default is a valid value of this type
+    pub fn accepts(&self, value: &Value) -> bool {
+        match self {
+            _ if value.is_null() => true,
+            ParamKind::Enum(values) => value.as_str().is_some_and(|value| values.iter().any(|v| v == value)),
+            ParamKind::Array(items) => value.as_array().is_some_and(|values| values.iter().all(|v| items.accepts(v))),
+            ParamKind::Named(name) => match name.as_str() {
+                "String" | "Secret" => value.is_string(),
+                "Integer" => value.is_i64() || value.is_u64(),
+                "Number" | "Float" => value.is_number(),
+                "Boolean" => value.is_boolean(),
+                "Duration" => value.is_string() || value.as_f64().is_some_and(|secs| secs >= 0.0),
+                "ByteSize" => value.is_string() || value.is_u64(),
+                "Object" => value.is_object(),
+                _ => true,
+            },
+        }
+    }
+
+    /// The `::lib_cpi::ParamType` expression
+    pub fn tokens(&self) -> TokenStream {
+        match self {
+            ParamKind::Enum(values) => quote! { ::lib_cpi::ParamType::Enum(vec![#(#values.to_string()),*]) },
+            ParamKind::Array(items) => {
+                let items = items.tokens();
+                quote! { ::lib_cpi::ParamType::Array(Box::new(#items)) }
+            }
+            ParamKind::Named(name) => match name.as_str() {
+                "Object" => quote! { ::lib_cpi::ParamType::Object(Vec::new()) },
+                name => {
+                    let variant = syn::Ident::new(name, proc_macro2::Span::call_site());
+                    quote! { ::lib_cpi::ParamType::#variant }
+                }
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ParamKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamKind::Named(name) => write!(f, "{}", name),
+            ParamKind::Enum(values) => write!(f, "Enum({})", values.join(", ")),
+            ParamKind::Array(items) => write!(f, "Array<{}>", items),
+        }
+    }
+}
+
+/// Derives the `ParamType` of an action argument from its Rust type, and whether
+/// the argument is optional
+///
+/// `Option<T>` is an optional `T`; types that are not recognised are assumed to be
+/// serde structs and described as objects.
+pub(crate) fn infer_param_type(ty: &Type) -> (ParamKind, bool) {
+    let kind = |name: &str| (ParamKind::named(name), false);
+    match ty {
+        Type::Reference(reference) => infer_param_type(&reference.elem),
+        Type::Paren(paren) => infer_param_type(&paren.elem),
+        Type::Group(group) => infer_param_type(&group.elem),
+        Type::Slice(slice) => (array_of(&slice.elem), false),
+        Type::Array(array) => (array_of(&array.elem), false),
+        Type::Tuple(_) => (ParamKind::Array(Box::new(ParamKind::named("Any"))), false),
+        Type::Path(path) => {
+            let Some(segment) = path.path.segments.last() else {
+                return kind("Object");
+            };
+            let argument = first_type_argument(&segment.arguments);
+            match segment.ident.to_string().as_str() {
+                "Option" => (argument.map_or(ParamKind::named("Any"), |inner| infer_param_type(inner).0), true),
+                "String" | "str" | "char" => kind("String"),
+                "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => {
+                    kind("Integer")
+                }
+                "f32" | "f64" => kind("Float"),
+                "bool" => kind("Boolean"),
+                "Value" => kind("Any"),
+                "Vec" | "VecDeque" | "HashSet" | "BTreeSet" => match argument {
+                    Some(item) => (array_of(item), false),
+                    None => (ParamKind::Array(Box::new(ParamKind::named("Any"))), false),
+                },
+                _ => kind("Object"),
+            }
+        }
+        _ => kind("Object"),
+    }
+}
+
+fn array_of(item: &Type) -> ParamKind {
+    ParamKind::Array(Box::new(infer_param_type(item).0))
+}
+
+/// `T` in `Name<T, ...>`
+pub(crate) fn first_type_argument(arguments: &PathArguments) -> Option<&Type> {
+    let PathArguments::AngleBracketed(args) = arguments else {
+        return None;
+    };
+    args.args.iter().find_map(|arg| match arg {
+        GenericArgument::Type(inner) => Some(inner),
+        _ => None,
+    })
+}